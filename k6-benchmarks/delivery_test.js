@@ -3,7 +3,6 @@ import { check, sleep } from 'k6';
 
 const API_BASE = __ENV.API_BASE || 'http://localhost:8080';
 const WAREHOUSES = parseInt(__ENV.WAREHOUSES || '10');
-const DISTRICTS_PER_W = 10;
 
 // Utility random functions
 function randInt(min, max) {
@@ -12,9 +11,6 @@ function randInt(min, max) {
 function pickWarehouse() {
   return randInt(1, WAREHOUSES);
 }
-function pickDistrict() {
-  return randInt(1, DISTRICTS_PER_W);
-}
 
 export let options = {
   vus: 3,
@@ -23,11 +19,11 @@ export let options = {
 
 export default function () {
   const w_id = pickWarehouse();
-  const d_id = pickDistrict();
 
+  // Full TPC-C Delivery: every district of the warehouse with a random carrier
   const res = http.post(`${API_BASE}/delivery`, JSON.stringify({
     warehouse_id: w_id,
-    district_id: d_id
+    carrier_id: randInt(1, 10)
   }), { headers: { 'Content-Type': 'application/json' } });
 
   check(res, { 
@@ -1,8 +1,22 @@
 import http from 'k6/http';
 import { check, sleep } from 'k6';
+import { Counter, Trend } from 'k6/metrics';
+
+// New-Orders rolled back for an unused item ID (expected, not failures)
+const newOrderRollbacks = new Counter('new_order_rollbacks');
+
+// Attempts the server needed per transaction (retries after 40001/40P01)
+const transactionAttempts = new Trend('transaction_attempts');
+function recordAttempts(res) {
+  const attempts = res.headers['X-Transaction-Attempts'];
+  if (attempts) {
+    transactionAttempts.add(parseInt(attempts));
+  }
+}
 
 const API_BASE = __ENV.API_BASE || 'http://localhost:8080';
 const WAREHOUSES = parseInt(__ENV.WAREHOUSES || '10');
+const TABLES = parseInt(__ENV.TABLES || '1'); // Table sets, like sysbench-tpcc --tables
 const DISTRICTS_PER_W = 10;
 const CUSTOMERS_PER_D = 3000;
 const ITEMS = 100000;
@@ -11,6 +25,15 @@ const ITEMS = 100000;
 function randInt(min, max) {
   return Math.floor(Math.random() * (max - min + 1)) + min;
 }
+// Each transaction runs against a random table set, like sysbench's table_num
+function requestParams() {
+  return {
+    headers: {
+      'Content-Type': 'application/json',
+      'X-Table-Set': String(randInt(1, TABLES)),
+    },
+  };
+}
 function pickWarehouse() {
   return randInt(1, WAREHOUSES);
 }
@@ -21,9 +44,60 @@ function pickCustomer() {
   return randInt(1, CUSTOMERS_PER_D);
 }
 
+// TPC-C customer last name built from three syllables (same as sysbench Lastname())
+const NAME_SYLLABLES = ['BAR', 'OUGHT', 'ABLE', 'PRI', 'PRES', 'ESE', 'ANTI', 'CALLY', 'ATION', 'EING'];
+function lastName(num) {
+  return NAME_SYLLABLES[Math.floor(num / 100)] +
+    NAME_SYLLABLES[Math.floor(num / 10) % 10] +
+    NAME_SYLLABLES[num % 10];
+}
+
+// Non-uniform random number NURand(A, x, y) with run-time constant C (TPC-C 2.1.6)
+function nurand(a, x, y, c) {
+  return (((randInt(0, a) | randInt(x, y)) + c) % (y - x + 1)) + x;
+}
+
+// C for NURand(255, 0, 999). With C_LOAD (the C_LAST constant printed by tpcc-load) it
+// differs from it by 65..119 but not 96 or 112, like tpcc-drive --c-load (2.1.6.1)
+function pickCLast() {
+  if (__ENV.C_LOAD === undefined) {
+    return randInt(0, 255);
+  }
+  const cLoad = parseInt(__ENV.C_LOAD);
+  for (;;) {
+    const cLast = randInt(0, 255);
+    const delta = Math.abs(cLast - cLoad);
+    if (delta >= 65 && delta <= 119 && delta !== 96 && delta !== 112) {
+      return cLast;
+    }
+  }
+}
+
+// 60% of Payments and Order-Status transactions select the customer by last name, 40% by customer ID
+function pickCustomerSelector(cLast) {
+  if (randInt(1, 100) <= 60) {
+    return { customer_last_name: lastName(nurand(255, 0, 999, cLast)) };
+  }
+  return { customer_id: pickCustomer() };
+}
+
+// 85% of Payments are for a customer of the paying warehouse/district, 15% for a
+// customer of another warehouse (only possible with more than one warehouse)
+function pickCustomerHome(w_id, d_id) {
+  if (WAREHOUSES === 1 || randInt(1, 100) <= 85) {
+    return { customer_warehouse_id: w_id, customer_district_id: d_id };
+  }
+  let c_w_id = w_id;
+  while (c_w_id === w_id) {
+    c_w_id = pickWarehouse();
+  }
+  return { customer_warehouse_id: c_w_id, customer_district_id: pickDistrict() };
+}
+
 export let options = {
   vus: 10,
   duration: '15s',
+  teardownTimeout: '5m',
 };
 
 // Transaction distribution based on TPC-C specification
@@ -49,7 +123,12 @@ function selectTransaction() {
   return 'new_order'; // fallback
 }
 
-export default function () {
+// One C_LAST run constant shared by every VU
+export function setup() {
+  return { cLast: pickCLast() };
+}
+
+export default function (data) {
   const txnType = selectTransaction();
   
   switch (txnType) {
@@ -57,10 +136,10 @@ export default function () {
       runNewOrder();
       break;
     case 'payment':
-      runPayment();
+      runPayment(data.cLast);
       break;
     case 'order_status':
-      runOrderStatus();
+      runOrderStatus(data.cLast);
       break;
     case 'delivery':
       runDelivery();
@@ -87,52 +166,68 @@ function runNewOrder() {
       quantity: randInt(1, 10),
     });
   }
+  // 1% of orders end with an unused item ID and must roll back
+  const rollback = randInt(1, 100) === 1;
+  if (rollback) {
+    order_lines[ol_cnt - 1].item_id = ITEMS + 1;
+  }
 
   const res = http.post(`${API_BASE}/new-order`, JSON.stringify({
     warehouse_id: w_id,
     district_id: d_id,
     customer_id: c_id,
     order_lines: order_lines
-  }), { headers: { 'Content-Type': 'application/json' } });
+  }), requestParams());
 
-  check(res, { 'new_order 200': (r) => r.status === 200 });
+  check(res, {
+    'new_order 200': (r) => r.status === 200,
+    'new_order outcome': (r) => r.status === 200 && r.json('outcome') === (rollback ? 'rolled_back' : 'committed'),
+  });
+  recordAttempts(res);
+  if (rollback && res.status === 200) {
+    newOrderRollbacks.add(1);
+  }
 }
 
-function runPayment() {
+function runPayment(cLast) {
   const w_id = pickWarehouse();
   const d_id = pickDistrict();
-  const c_id = pickCustomer();
   const payment_amount = randInt(1, 5000) / 100.0;
 
   const res = http.post(`${API_BASE}/payment`, JSON.stringify({
     warehouse_id: w_id,
     district_id: d_id,
-    customer_id: c_id,
+    ...pickCustomerSelector(cLast),
+    ...pickCustomerHome(w_id, d_id),
     amount: payment_amount
-  }), { headers: { 'Content-Type': 'application/json' } });
+  }), requestParams());
 
   check(res, { 'payment 200': (r) => r.status === 200 });
+  recordAttempts(res);
 }
 
-function runOrderStatus() {
+function runOrderStatus(cLast) {
   const w_id = pickWarehouse();
   const d_id = pickDistrict();
-  const c_id = pickCustomer();
+  const selector = Object.entries(pickCustomerSelector(cLast))
+    .map(([key, value]) => `${key}=${encodeURIComponent(value)}`)
+    .join('&');
 
-  const res = http.get(`${API_BASE}/order-status?warehouse_id=${w_id}&district_id=${d_id}&customer_id=${c_id}`);
+  const res = http.get(`${API_BASE}/order-status?warehouse_id=${w_id}&district_id=${d_id}&${selector}`, requestParams());
   check(res, { 'order_status 200': (r) => r.status === 200 });
 }
 
 function runDelivery() {
   const w_id = pickWarehouse();
-  const d_id = pickDistrict();
 
+  // Full TPC-C Delivery: every district of the warehouse with a random carrier
   const res = http.post(`${API_BASE}/delivery`, JSON.stringify({
     warehouse_id: w_id,
-    district_id: d_id
-  }), { headers: { 'Content-Type': 'application/json' } });
+    carrier_id: randInt(1, 10)
+  }), requestParams());
 
   check(res, { 'delivery 200': (r) => r.status === 200 });
+  recordAttempts(res);
 }
 
 function runStockLevel() {
@@ -140,6 +235,31 @@ function runStockLevel() {
   const d_id = pickDistrict();
   const threshold = randInt(10, 20);
 
-  const res = http.get(`${API_BASE}/stock-level?warehouse_id=${w_id}&district_id=${d_id}&threshold=${threshold}`);
+  const res = http.get(`${API_BASE}/stock-level?warehouse_id=${w_id}&district_id=${d_id}&threshold=${threshold}`, requestParams());
   check(res, { 'stock_level 200': (r) => r.status === 200 });
-}
\ No newline at end of file
+}
+
+// After the run, check the TPC-C consistency conditions of every table set (CHECK_CONSISTENCY=0 skips)
+export function teardown() {
+  if (__ENV.CHECK_CONSISTENCY === '0') {
+    return;
+  }
+  for (let tableSet = 1; tableSet <= TABLES; tableSet++) {
+    const res = http.get(`${API_BASE}/admin/consistency`, {
+      headers: { 'X-Table-Set': String(tableSet) },
+      timeout: '5m',
+    });
+    if (res.status !== 200) {
+      console.error(`Consistency check of table set ${tableSet} failed with HTTP ${res.status}`);
+      continue;
+    }
+    for (const condition of res.json().conditions) {
+      const status = condition.passed ? 'PASSED' : condition.required ? 'FAILED!!!' : 'FAILED (informational)';
+      console.log(`Table set ${tableSet}, check ${condition.condition} ${status}: ${condition.description}`);
+      if (!condition.passed) {
+        console.log(JSON.stringify(condition.violations.slice(0, 5)));
+      }
+    }
+    check(res, { 'consistency conditions hold': (r) => r.json().consistent === true });
+  }
+}
@@ -20,6 +20,35 @@ function pickCustomer() {
   return randInt(1, CUSTOMERS_PER_D);
 }
 
+// TPC-C customer last name built from three syllables (same as sysbench Lastname())
+const NAME_SYLLABLES = ['BAR', 'OUGHT', 'ABLE', 'PRI', 'PRES', 'ESE', 'ANTI', 'CALLY', 'ATION', 'EING'];
+function lastName(num) {
+  return NAME_SYLLABLES[Math.floor(num / 100)] +
+    NAME_SYLLABLES[Math.floor(num / 10) % 10] +
+    NAME_SYLLABLES[num % 10];
+}
+
+// 60% of Payments select the customer by last name, 40% by customer ID
+function pickCustomerSelector() {
+  if (randInt(1, 100) <= 60) {
+    return { customer_last_name: lastName(randInt(0, 999)) };
+  }
+  return { customer_id: pickCustomer() };
+}
+
+// 85% of Payments are for a customer of the paying warehouse/district, 15% for a
+// customer of another warehouse (only possible with more than one warehouse)
+function pickCustomerHome(w_id, d_id) {
+  if (WAREHOUSES === 1 || randInt(1, 100) <= 85) {
+    return { customer_warehouse_id: w_id, customer_district_id: d_id };
+  }
+  let c_w_id = w_id;
+  while (c_w_id === w_id) {
+    c_w_id = pickWarehouse();
+  }
+  return { customer_warehouse_id: c_w_id, customer_district_id: pickDistrict() };
+}
+
 export let options = {
   vus: 5,
   duration: '10s',
@@ -28,13 +57,13 @@ export let options = {
 export default function () {
   const w_id = pickWarehouse();
   const d_id = pickDistrict();
-  const c_id = pickCustomer();
   const payment_amount = randInt(1, 5000) / 100.0; // $0.01 to $50.00
 
   const res = http.post(`${API_BASE}/payment`, JSON.stringify({
     warehouse_id: w_id,
     district_id: d_id,
-    customer_id: c_id,
+    ...pickCustomerSelector(),
+    ...pickCustomerHome(w_id, d_id),
     amount: payment_amount
   }), { headers: { 'Content-Type': 'application/json' } });
 
@@ -0,0 +1,389 @@
+-- TPC-C transactions as PL/pgSQL functions
+-- With TX_EXECUTION_MODE=stored-procedures the handlers call these instead of sending
+-- the statements one by one; the results are the same as the inline path's.
+--
+-- Like the inline SQL they name the set-1 tables and leave search_path alone, so other
+-- table sets resolve them through the views of their tpcc_tablesK schema.
+-- A row that does not exist raises no_data_found with the table in TABLE and, where
+-- the caller cannot know it, the missing row's key in DETAIL.
+
+-- NEW-ORDER
+-- The order lines are given as three parallel arrays and their results returned as
+-- arrays in the same order. An unused item ID stops the order with unused_item_id set;
+-- the caller must then roll back (TPC-C 2.4.2.3). The caller works out the line
+-- amounts and the total from item_prices, as the inline path does.
+CREATE OR REPLACE FUNCTION tpcc_new_order(
+    warehouse_id smallint,
+    district_id smallint,
+    customer_id int,
+    entry_date timestamp,
+    item_ids int[],
+    supply_warehouse_ids smallint[],
+    quantities smallint[],
+    OUT order_id int,
+    OUT w_tax numeric,
+    OUT d_tax numeric,
+    OUT c_last varchar,
+    OUT c_credit char(2),
+    OUT c_discount numeric,
+    OUT unused_item_id int,
+    OUT item_names varchar[],
+    OUT item_prices numeric[],
+    OUT stock_quantities smallint[],
+    OUT brand_generic text[]
+) LANGUAGE plpgsql AS $$
+#variable_conflict use_column
+DECLARE
+    line_count int := array_length(item_ids, 1);
+    all_local boolean := supply_warehouse_ids <@ ARRAY[warehouse_id];
+    item record;
+    stock record;
+    line_amount numeric;
+BEGIN
+    SELECT w_tax INTO w_tax FROM warehouse1 WHERE w_id = warehouse_id;
+    IF NOT FOUND THEN
+        RAISE EXCEPTION 'warehouse not found' USING ERRCODE = 'no_data_found', TABLE = 'warehouse';
+    END IF;
+    w_tax := COALESCE(w_tax, 0);
+
+    UPDATE district1 SET d_next_o_id = COALESCE(d_next_o_id, 1) + 1
+    WHERE d_w_id = warehouse_id AND d_id = district_id
+    RETURNING COALESCE(d_tax, 0), d_next_o_id - 1 INTO d_tax, order_id;
+    IF NOT FOUND THEN
+        RAISE EXCEPTION 'district not found' USING ERRCODE = 'no_data_found', TABLE = 'district';
+    END IF;
+
+    SELECT COALESCE(c_last, ''), COALESCE(c_credit, ''), COALESCE(c_discount, 0)
+    INTO c_last, c_credit, c_discount
+    FROM customer1 WHERE c_w_id = warehouse_id AND c_d_id = district_id AND c_id = customer_id;
+    IF NOT FOUND THEN
+        RAISE EXCEPTION 'customer not found' USING ERRCODE = 'no_data_found', TABLE = 'customer';
+    END IF;
+
+    INSERT INTO orders1 (o_id, o_d_id, o_w_id, o_c_id, o_entry_d, o_carrier_id, o_ol_cnt, o_all_local)
+    VALUES (order_id, district_id, warehouse_id, customer_id, entry_date, NULL, line_count,
+            all_local::int);
+    INSERT INTO new_orders1 (no_o_id, no_d_id, no_w_id) VALUES (order_id, district_id, warehouse_id);
+
+    -- Lock the stock rows in (supply warehouse, item ID) order, so orders sharing
+    -- items cannot deadlock
+    PERFORM 1 FROM stock1
+    WHERE (s_w_id, s_i_id) IN (SELECT * FROM unnest(supply_warehouse_ids, item_ids))
+    ORDER BY s_w_id, s_i_id
+    FOR UPDATE;
+
+    item_names := '{}';
+    item_prices := '{}';
+    stock_quantities := '{}';
+    brand_generic := '{}';
+    FOR line IN 1..line_count LOOP
+        SELECT COALESCE(i_name, '') AS name, COALESCE(i_price, 0) AS price INTO item
+        FROM item1 WHERE i_id = item_ids[line];
+        IF NOT FOUND THEN
+            unused_item_id := item_ids[line];
+            RETURN;
+        END IF;
+
+        UPDATE stock1
+        SET s_quantity = CASE WHEN COALESCE(s_quantity, 0) >= quantities[line]
+                              THEN COALESCE(s_quantity, 0) - quantities[line]
+                              ELSE COALESCE(s_quantity, 0) - quantities[line] + 91 -- TPC-C specification
+                         END,
+            s_ytd = COALESCE(s_ytd, 0) + quantities[line],
+            s_order_cnt = COALESCE(s_order_cnt, 0) + 1,
+            s_remote_cnt = COALESCE(s_remote_cnt, 0)
+                           + CASE WHEN supply_warehouse_ids[line] <> warehouse_id THEN 1 ELSE 0 END
+        WHERE s_i_id = item_ids[line] AND s_w_id = supply_warehouse_ids[line]
+        RETURNING s_quantity AS quantity, COALESCE(s_data, '') AS data,
+                  COALESCE(CASE district_id
+                               WHEN 1 THEN s_dist_01 WHEN 2 THEN s_dist_02 WHEN 3 THEN s_dist_03
+                               WHEN 4 THEN s_dist_04 WHEN 5 THEN s_dist_05 WHEN 6 THEN s_dist_06
+                               WHEN 7 THEN s_dist_07 WHEN 8 THEN s_dist_08 WHEN 9 THEN s_dist_09
+                               WHEN 10 THEN s_dist_10
+                           END, '') AS dist_info
+        INTO stock;
+        IF NOT FOUND THEN
+            RAISE EXCEPTION 'stock not found' USING
+                ERRCODE = 'no_data_found', TABLE = 'stock', DETAIL = line::text;
+        END IF;
+
+        line_amount := item.price * quantities[line];
+
+        INSERT INTO order_line1 (ol_o_id, ol_d_id, ol_w_id, ol_number, ol_i_id, ol_supply_w_id,
+                                 ol_delivery_d, ol_quantity, ol_amount, ol_dist_info)
+        VALUES (order_id, district_id, warehouse_id, line, item_ids[line], supply_warehouse_ids[line],
+                NULL, quantities[line], line_amount, stock.dist_info);
+
+        item_names := item_names || item.name;
+        item_prices := item_prices || item.price;
+        stock_quantities := stock_quantities || stock.quantity;
+        brand_generic := brand_generic
+            || CASE WHEN item.name LIKE '%ORIGINAL%' AND stock.data LIKE '%ORIGINAL%'
+                    THEN 'B' ELSE 'G' END;
+    END LOOP;
+END;
+$$;
+
+-- PAYMENT
+-- customer_id NULL selects the customer by last name: the middle one by first name
+-- (TPC-C 2.5.2.2)
+CREATE OR REPLACE FUNCTION tpcc_payment(
+    warehouse_id smallint,
+    district_id smallint,
+    customer_warehouse_id smallint,
+    customer_district_id smallint,
+    customer_id int,
+    customer_last_name varchar,
+    amount numeric,
+    payment_date timestamp,
+    OUT w_name varchar,
+    OUT w_street_1 varchar,
+    OUT w_street_2 varchar,
+    OUT w_city varchar,
+    OUT w_state char(2),
+    OUT w_zip char(9),
+    OUT d_name varchar,
+    OUT d_street_1 varchar,
+    OUT d_street_2 varchar,
+    OUT d_city varchar,
+    OUT d_state char(2),
+    OUT d_zip char(9),
+    OUT c_id int,
+    OUT c_first varchar,
+    OUT c_middle char(2),
+    OUT c_last varchar,
+    OUT c_street_1 varchar,
+    OUT c_street_2 varchar,
+    OUT c_city varchar,
+    OUT c_state char(2),
+    OUT c_zip char(9),
+    OUT c_phone char(16),
+    OUT c_since timestamp,
+    OUT c_credit char(2),
+    OUT c_credit_lim bigint,
+    OUT c_discount numeric,
+    OUT c_balance numeric
+) LANGUAGE plpgsql AS $$
+#variable_conflict use_column
+DECLARE
+    customer_ids int[];
+BEGIN
+    UPDATE warehouse1 SET w_ytd = COALESCE(w_ytd, 0) + amount
+    WHERE w_id = warehouse_id
+    RETURNING w_name, w_street_1, w_street_2, w_city, w_state, w_zip
+    INTO w_name, w_street_1, w_street_2, w_city, w_state, w_zip;
+    IF NOT FOUND THEN
+        RAISE EXCEPTION 'warehouse not found' USING ERRCODE = 'no_data_found', TABLE = 'warehouse';
+    END IF;
+
+    UPDATE district1 SET d_ytd = COALESCE(d_ytd, 0) + amount
+    WHERE d_w_id = warehouse_id AND d_id = district_id
+    RETURNING d_name, d_street_1, d_street_2, d_city, d_state, d_zip
+    INTO d_name, d_street_1, d_street_2, d_city, d_state, d_zip;
+    IF NOT FOUND THEN
+        RAISE EXCEPTION 'district not found' USING ERRCODE = 'no_data_found', TABLE = 'district';
+    END IF;
+
+    IF customer_id IS NULL THEN
+        SELECT array_agg(c_id ORDER BY c_first) INTO customer_ids
+        FROM customer1
+        WHERE c_w_id = customer_warehouse_id AND c_d_id = customer_district_id
+          AND c_last = customer_last_name;
+        IF customer_ids IS NULL THEN
+            RAISE EXCEPTION 'customer not found' USING ERRCODE = 'no_data_found', TABLE = 'customer';
+        END IF;
+        customer_id := customer_ids[(array_length(customer_ids, 1) - 1) / 2 + 1];
+    END IF;
+
+    -- Bad credit customers get the payment prepended to C_DATA, cut to 500 characters
+    UPDATE customer1
+    SET c_balance = COALESCE(c_balance, 0) - amount,
+        c_ytd_payment = COALESCE(c_ytd_payment, 0) + amount,
+        c_payment_cnt = COALESCE(c_payment_cnt, 0) + 1,
+        c_data = CASE WHEN c_credit = 'BC'
+                      THEN left(format('%s|%s|%s|%s|%s|%s|%s|', customer_id, customer_district_id,
+                                       customer_warehouse_id, district_id, warehouse_id, amount,
+                                       amount)
+                                || COALESCE(c_data, ''), 500)
+                      ELSE c_data
+                 END
+    WHERE c_w_id = customer_warehouse_id AND c_d_id = customer_district_id AND c_id = customer_id
+    RETURNING c_id, c_first, c_middle, c_last, c_street_1, c_street_2, c_city, c_state, c_zip,
+              c_phone, c_since, c_credit, c_credit_lim, c_discount, c_balance
+    INTO c_id, c_first, c_middle, c_last, c_street_1, c_street_2, c_city, c_state, c_zip,
+         c_phone, c_since, c_credit, c_credit_lim, c_discount, c_balance;
+    IF NOT FOUND THEN
+        RAISE EXCEPTION 'customer not found' USING ERRCODE = 'no_data_found', TABLE = 'customer';
+    END IF;
+
+    INSERT INTO history1 (h_c_id, h_c_d_id, h_c_w_id, h_d_id, h_w_id, h_date, h_amount, h_data)
+    VALUES (customer_id, customer_district_id, customer_warehouse_id, district_id, warehouse_id,
+            payment_date, amount, COALESCE(w_name, '') || ' ' || COALESCE(d_name, ''));
+END;
+$$;
+
+-- DELIVERY
+-- Delivers the oldest undelivered order of each given district, returning one row per
+-- district in the given order; order_id is NULL for a district with nothing to deliver.
+-- line_amounts holds the delivered order lines' amounts, for the caller to count and sum.
+CREATE OR REPLACE FUNCTION tpcc_delivery(
+    warehouse_id smallint,
+    district_ids smallint[],
+    carrier_id smallint,
+    delivery_date timestamp
+) RETURNS TABLE (
+    district_id smallint,
+    order_id int,
+    customer_id int,
+    line_amounts numeric[]
+) LANGUAGE plpgsql AS $$
+#variable_conflict use_column
+DECLARE
+    total_amount numeric;
+BEGIN
+    FOREACH district_id IN ARRAY district_ids LOOP
+        -- Locked, so a concurrent Delivery of the same order waits and then skips it
+        SELECT no_o_id INTO order_id
+        FROM new_orders1
+        WHERE no_w_id = warehouse_id AND no_d_id = district_id
+        ORDER BY no_o_id
+        LIMIT 1
+        FOR UPDATE;
+        IF NOT FOUND THEN
+            order_id := NULL;
+            customer_id := NULL;
+            line_amounts := NULL;
+            RETURN NEXT;
+            CONTINUE;
+        END IF;
+
+        SELECT COALESCE(o_c_id, 0) INTO customer_id
+        FROM orders1 WHERE o_w_id = warehouse_id AND o_d_id = district_id AND o_id = order_id;
+        IF NOT FOUND THEN
+            RAISE EXCEPTION 'new order % in warehouse % district % has no order row',
+                order_id, warehouse_id, district_id
+                USING ERRCODE = 'no_data_found', TABLE = 'orders';
+        END IF;
+
+        UPDATE orders1 SET o_carrier_id = carrier_id
+        WHERE o_w_id = warehouse_id AND o_d_id = district_id AND o_id = order_id;
+
+        WITH delivered AS (
+            UPDATE order_line1 SET ol_delivery_d = delivery_date
+            WHERE ol_w_id = warehouse_id AND ol_d_id = district_id AND ol_o_id = order_id
+            RETURNING ol_amount
+        )
+        SELECT COALESCE(array_agg(ol_amount), '{}'), COALESCE(sum(ol_amount), 0)
+        INTO line_amounts, total_amount
+        FROM delivered;
+
+        UPDATE customer1
+        SET c_balance = c_balance + total_amount, c_delivery_cnt = c_delivery_cnt + 1
+        WHERE c_w_id = warehouse_id AND c_d_id = district_id AND c_id = customer_id;
+
+        DELETE FROM new_orders1
+        WHERE no_w_id = warehouse_id AND no_d_id = district_id AND no_o_id = order_id;
+
+        RETURN NEXT;
+    END LOOP;
+END;
+$$;
+
+-- ORDER-STATUS
+-- customer_id NULL selects the customer by last name, as for Payment. The lines of the
+-- customer's latest order are returned as arrays in line number order.
+CREATE OR REPLACE FUNCTION tpcc_order_status(
+    warehouse_id smallint,
+    district_id smallint,
+    customer_id int,
+    customer_last_name varchar,
+    OUT c_id int,
+    OUT name_count bigint,
+    OUT c_first varchar,
+    OUT c_middle char(2),
+    OUT c_last varchar,
+    OUT c_balance numeric,
+    OUT o_id int,
+    OUT o_entry_d timestamp,
+    OUT o_carrier_id smallint,
+    OUT ol_i_ids int[],
+    OUT ol_supply_w_ids smallint[],
+    OUT ol_quantities smallint[],
+    OUT ol_amounts numeric[],
+    OUT ol_delivery_ds timestamp[]
+) LANGUAGE plpgsql AS $$
+#variable_conflict use_column
+DECLARE
+    customer_ids int[];
+BEGIN
+    IF customer_id IS NULL THEN
+        SELECT array_agg(c_id ORDER BY c_first) INTO customer_ids
+        FROM customer1
+        WHERE c_w_id = warehouse_id AND c_d_id = district_id AND c_last = customer_last_name;
+        IF customer_ids IS NULL THEN
+            RAISE EXCEPTION 'customer not found' USING ERRCODE = 'no_data_found', TABLE = 'customer';
+        END IF;
+        name_count := array_length(customer_ids, 1);
+        customer_id := customer_ids[(name_count - 1) / 2 + 1];
+    ELSE
+        name_count := 1;
+    END IF;
+
+    SELECT c_id, c_first, c_middle, c_last, c_balance
+    INTO c_id, c_first, c_middle, c_last, c_balance
+    FROM customer1
+    WHERE c_w_id = warehouse_id AND c_d_id = district_id AND c_id = customer_id;
+    IF NOT FOUND THEN
+        RAISE EXCEPTION 'customer not found' USING ERRCODE = 'no_data_found', TABLE = 'customer';
+    END IF;
+
+    SELECT o_id, o_entry_d, o_carrier_id INTO o_id, o_entry_d, o_carrier_id
+    FROM orders1
+    WHERE o_w_id = warehouse_id AND o_d_id = district_id AND o_c_id = customer_id
+    ORDER BY o_id DESC
+    LIMIT 1;
+    IF NOT FOUND THEN
+        RAISE EXCEPTION 'order not found' USING
+            ERRCODE = 'no_data_found', TABLE = 'orders', DETAIL = customer_id::text;
+    END IF;
+
+    SELECT COALESCE(array_agg(ol_i_id ORDER BY ol_number), '{}'),
+           COALESCE(array_agg(ol_supply_w_id ORDER BY ol_number), '{}'),
+           COALESCE(array_agg(ol_quantity ORDER BY ol_number), '{}'),
+           COALESCE(array_agg(ol_amount ORDER BY ol_number), '{}'),
+           COALESCE(array_agg(ol_delivery_d ORDER BY ol_number), '{}')
+    INTO ol_i_ids, ol_supply_w_ids, ol_quantities, ol_amounts, ol_delivery_ds
+    FROM order_line1
+    WHERE ol_w_id = warehouse_id AND ol_d_id = district_id AND ol_o_id = o_id;
+END;
+$$;
+
+-- STOCK-LEVEL
+-- Distinct items of the district's last 20 orders with stock below the threshold
+CREATE OR REPLACE FUNCTION tpcc_stock_level(
+    warehouse_id smallint,
+    district_id smallint,
+    threshold smallint,
+    OUT low_stock_count bigint
+) LANGUAGE plpgsql AS $$
+#variable_conflict use_column
+DECLARE
+    next_order_id int;
+BEGIN
+    SELECT d_next_o_id INTO next_order_id
+    FROM district1 WHERE d_id = district_id AND d_w_id = warehouse_id;
+    IF next_order_id IS NULL THEN
+        RAISE EXCEPTION 'district not found' USING ERRCODE = 'no_data_found', TABLE = 'district';
+    END IF;
+
+    SELECT COUNT(DISTINCT (s_i_id)) INTO low_stock_count
+    FROM order_line1 ol, stock1 s
+    WHERE ol.ol_w_id = warehouse_id
+      AND ol.ol_d_id = district_id
+      AND ol.ol_o_id < next_order_id
+      AND ol.ol_o_id >= next_order_id - 20
+      AND s.s_w_id = warehouse_id
+      AND s.s_i_id = ol.ol_i_id
+      AND s.s_quantity < threshold;
+END;
+$$;
@@ -0,0 +1,149 @@
+use clap::Parser;
+use dotenvy::dotenv;
+use rust_axum_rest_api::config::{Config, ConfigArgs, DatabaseArgs};
+use rust_axum_rest_api::drive::{self, DriveOptions, Executor, HttpClient};
+use rust_axum_rest_api::random::NuRandConstants;
+use rust_axum_rest_api::table_set::TableSets;
+use rust_axum_rest_api::telemetry;
+use rust_axum_rest_api::transaction::TransactionSettings;
+use std::path::PathBuf;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+// Run the TPC-C transaction mix against the REST API, or in-process against the
+// database, and report tpmC
+#[derive(Parser)]
+#[command(
+    name = "tpcc-drive",
+    about = "Drive the TPC-C workload through the REST API"
+)]
+struct Args {
+    /// Base URL of the REST API
+    #[arg(long, default_value = "http://127.0.0.1:8080")]
+    api: String,
+    /// Loaded warehouses per table set
+    #[arg(long, default_value_t = 10)]
+    warehouses: i16,
+    /// Number of sysbench-style table sets
+    #[arg(long, default_value_t = 1)]
+    tables: u32,
+    /// Emulated terminals per warehouse
+    #[arg(long, default_value_t = 10)]
+    terminals_per_warehouse: u16,
+    /// Measurement interval in seconds
+    #[arg(long, default_value_t = 300)]
+    duration: u64,
+    /// Seconds before the measurement interval starts
+    #[arg(long, default_value_t = 30)]
+    ramp_up: u64,
+    /// Scale keying and think times; 0 sends the next transaction right away
+    #[arg(long, default_value_t = 1.0)]
+    think_time_scale: f64,
+    /// Run Delivery inline instead of queueing it
+    #[arg(long)]
+    inline_delivery: bool,
+    /// C_LAST constant printed by tpcc-load; the run constant is chosen to fit it
+    #[arg(long)]
+    c_load: Option<u32>,
+    /// Call the transaction functions directly over DATABASE_URL instead of the API,
+    /// with the server's TX_* settings
+    #[arg(long)]
+    in_process: bool,
+    /// Seconds to wait for the API's /readyz before starting; 0 starts right away
+    #[arg(long, default_value_t = 60)]
+    wait_ready: u64,
+    /// The server's TOML configuration file; --in-process uses its [database] settings
+    #[arg(long, env = "TPCC_CONFIG")]
+    config: Option<PathBuf>,
+    #[command(flatten)]
+    database: DatabaseArgs,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), sqlx::Error> {
+    dotenv().ok();
+    let _telemetry = telemetry::init("tpcc-drive", None);
+
+    let args = Args::parse();
+    let mut rng = rand::thread_rng();
+    let mut load = NuRandConstants::random(&mut rng);
+    match args.c_load {
+        Some(c_last) => load.c_last = c_last,
+        None => warn!("--c-load not given; the C_LAST run constant may not fit the loaded data"),
+    }
+    let nurand = NuRandConstants::for_run(&load, &mut rng);
+
+    let (executor, target) = if args.in_process {
+        // The pools are sized and timed out like the server's
+        let config = match Config::load(&ConfigArgs {
+            config: args.config.clone(),
+            database: args.database.clone(),
+            ..ConfigArgs::default()
+        }) {
+            Ok(config) => config,
+            Err(e) => {
+                error!("Invalid configuration: {}", e);
+                std::process::exit(2);
+            }
+        };
+        let pool = config.database.connect().await?;
+        let settings = TransactionSettings::from_env();
+        let table_sets = match TableSets::discover(&pool)
+            .await
+            .with_driver(settings.driver, &config.database)
+        {
+            Ok(table_sets) => table_sets,
+            Err(e) => {
+                error!("TX_DRIVER={}: {}", settings.driver, e);
+                std::process::exit(2);
+            }
+        };
+        info!("Driver: {}", table_sets.driver());
+        let executor = Executor::InProcess {
+            table_sets,
+            settings,
+        };
+        (executor, "the database in-process".to_string())
+    } else {
+        let client = HttpClient::new(&args.api, !args.inline_delivery);
+        if args.wait_ready > 0 {
+            info!("Waiting for {} to become ready", args.api);
+            if let Err(reason) = client
+                .wait_ready(Duration::from_secs(args.wait_ready))
+                .await
+            {
+                error!(
+                    "{} is not ready after {}s: {}",
+                    args.api, args.wait_ready, reason
+                );
+                std::process::exit(1);
+            }
+        }
+        (Executor::Http(client), args.api)
+    };
+
+    info!(
+        "Driving {} with {} terminals for {}s after a {}s ramp-up",
+        target,
+        args.warehouses as u32 * args.terminals_per_warehouse as u32 * args.tables,
+        args.duration,
+        args.ramp_up
+    );
+    let report = drive::drive(DriveOptions {
+        executor,
+        warehouses: args.warehouses,
+        table_sets: args.tables,
+        terminals_per_warehouse: args.terminals_per_warehouse,
+        ramp_up: Duration::from_secs(args.ramp_up),
+        duration: Duration::from_secs(args.duration),
+        think_time_scale: args.think_time_scale,
+        nurand,
+    })
+    .await;
+
+    for error in report.errors.iter().take(10) {
+        warn!("{}", error);
+    }
+    println!("{}", report);
+    Ok(())
+}
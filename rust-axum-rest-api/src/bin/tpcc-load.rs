@@ -0,0 +1,73 @@
+use clap::Parser;
+use dotenvy::dotenv;
+use rust_axum_rest_api::load::{self, LoadOptions};
+use rust_axum_rest_api::telemetry;
+use sqlx::postgres::PgPoolOptions;
+use std::time::Instant;
+use tracing::info;
+
+// Create the TPC-C schema and populate it as described in clause 4.3 of the specification
+#[derive(Parser)]
+#[command(name = "tpcc-load", about = "Create and load the TPC-C tables")]
+struct Args {
+    /// Number of warehouses per table set
+    #[arg(long, default_value_t = 1)]
+    warehouses: i16,
+    /// Number of sysbench-style table sets (warehouse1..warehouseN, ...)
+    #[arg(long, default_value_t = 1)]
+    tables: u32,
+    /// Warehouses loaded in parallel
+    #[arg(long, default_value_t = 4)]
+    jobs: usize,
+    /// Percentage of customers with bad credit
+    #[arg(long, default_value_t = 10)]
+    bad_credit_percent: u32,
+    /// Truncate the tables before loading
+    #[arg(long)]
+    reset: bool,
+    /// PostgreSQL connection URL
+    #[arg(long, env = "DATABASE_URL", hide_env_values = true)]
+    database_url: String,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), sqlx::Error> {
+    dotenv().ok();
+    let _telemetry = telemetry::init("tpcc-load", None);
+
+    let args = Args::parse();
+    let pool = PgPoolOptions::new()
+        .max_connections(args.jobs as u32 + 1)
+        .connect(&args.database_url)
+        .await?;
+    info!("Connected to the database!");
+
+    let table_sets: Vec<u32> = (1..=args.tables).collect();
+    load::create_schema(&pool, &table_sets).await?;
+    if args.reset {
+        for &table_set in &table_sets {
+            load::truncate(&pool, table_set).await?;
+        }
+    }
+
+    let started = Instant::now();
+    let summary = load::load(
+        &pool,
+        &LoadOptions {
+            warehouses: args.warehouses,
+            table_sets,
+            jobs: args.jobs,
+            bad_credit_percent: args.bad_credit_percent,
+            original_percent: 10,
+        },
+    )
+    .await?;
+
+    info!(
+        "Loaded {} rows in {:.1}s (C_LAST load constant: {})",
+        summary.rows,
+        started.elapsed().as_secs_f64(),
+        summary.nurand.c_last
+    );
+    Ok(())
+}
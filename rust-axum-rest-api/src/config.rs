@@ -0,0 +1,260 @@
+use clap::builder::BoolishValueParser;
+use serde::Deserialize;
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use sqlx::{Pool, Postgres};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// Server configuration. Each setting comes from, in increasing precedence: the
+// defaults below, the TOML file given by --config, an environment variable and
+// a command line flag (see ConfigArgs). config.example.toml lists every key.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+    // Built frontend served in combined mode
+    pub frontend_dist: PathBuf,
+    // None serves the frontend in release builds only
+    pub serve_frontend: Option<bool>,
+    // Origins allowed in API-only mode; "*" allows any
+    pub cors_origins: Vec<String>,
+    // On SIGINT/SIGTERM, how long in-flight requests and queued deliveries
+    // may take to finish before they are aborted
+    pub shutdown_timeout_secs: u64,
+    // tracing filter such as "info" or "info,rust_axum_rest_api=debug";
+    // None falls back to RUST_LOG, then "info"
+    pub log_level: Option<String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            host: "127.0.0.1".to_string(),
+            port: 8080,
+            frontend_dist: PathBuf::from("../ui-vite-react/dist"),
+            serve_frontend: None,
+            cors_origins: vec!["*".to_string()],
+            shutdown_timeout_secs: 30,
+            log_level: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    // DATABASE_URL when not in the file
+    pub url: Option<String>,
+    pub min_connections: u32,
+    pub max_connections: u32,
+    pub acquire_timeout_secs: u64,
+    // 0 keeps idle connections open
+    pub idle_timeout_secs: u64,
+    // PostgreSQL statement_timeout of every connection; None keeps the server's
+    pub statement_timeout_ms: Option<u64>,
+    // Apply pending migrations before serving, and refuse to start on drift
+    pub run_migrations: bool,
+}
+
+// sqlx's pool defaults
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig {
+            url: None,
+            min_connections: 0,
+            max_connections: 10,
+            acquire_timeout_secs: 30,
+            idle_timeout_secs: 600,
+            statement_timeout_ms: None,
+            run_migrations: false,
+        }
+    }
+}
+
+impl DatabaseConfig {
+    pub fn pool_options(&self) -> PgPoolOptions {
+        PgPoolOptions::new()
+            .min_connections(self.min_connections)
+            .max_connections(self.max_connections)
+            .acquire_timeout(Duration::from_secs(self.acquire_timeout_secs))
+            .idle_timeout(match self.idle_timeout_secs {
+                0 => None,
+                secs => Some(Duration::from_secs(secs)),
+            })
+    }
+
+    pub fn connect_options(&self) -> Result<PgConnectOptions, sqlx::Error> {
+        let url = self
+            .url
+            .as_deref()
+            .ok_or_else(|| sqlx::Error::Configuration("DATABASE_URL must be set".into()))?;
+        let options: PgConnectOptions = url.parse()?;
+        Ok(match self.statement_timeout_ms {
+            Some(ms) => options.options([("statement_timeout", format!("{}ms", ms))]),
+            None => options,
+        })
+    }
+
+    pub async fn connect(&self) -> Result<Pool<Postgres>, sqlx::Error> {
+        self.pool_options()
+            .connect_with(self.connect_options()?)
+            .await
+    }
+}
+
+#[derive(Debug)]
+pub struct ConfigError(String);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    pub fn from_toml(toml: &str) -> Result<Self, ConfigError> {
+        toml::from_str(toml).map_err(|e| ConfigError(e.to_string()))
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let toml = std::fs::read_to_string(path)
+            .map_err(|e| ConfigError(format!("reading {}: {}", path.display(), e)))?;
+        Config::from_toml(&toml).map_err(|e| ConfigError(format!("{}: {}", path.display(), e)))
+    }
+
+    // The file named by --config (defaults without one), overridden by the
+    // environment and the command line
+    pub fn load(args: &ConfigArgs) -> Result<Self, ConfigError> {
+        let mut config = match &args.config {
+            Some(path) => Config::from_file(path)?,
+            None => Config::default(),
+        };
+        args.apply(&mut config);
+        Ok(config)
+    }
+}
+
+// Flags overriding the configuration file; each also reads the environment
+// variable named in `env`
+#[derive(clap::Args, Clone, Debug, Default)]
+pub struct ConfigArgs {
+    /// TOML configuration file
+    #[arg(long, env = "TPCC_CONFIG")]
+    pub config: Option<PathBuf>,
+    /// Address to listen on; 127.0.0.1 by default
+    #[arg(long, env = "LISTEN_HOST")]
+    pub host: Option<String>,
+    /// Port to listen on; 8080 by default
+    #[arg(long, env = "PORT")]
+    pub port: Option<u16>,
+    /// Built frontend served in combined mode; ../ui-vite-react/dist by default
+    #[arg(long, env = "FRONTEND_DIST")]
+    pub frontend_dist: Option<PathBuf>,
+    /// Serve the frontend with the API; by default only in release builds
+    #[arg(long, env = "SERVE_FRONTEND", value_parser = BoolishValueParser::new())]
+    pub serve_frontend: Option<bool>,
+    /// Origins allowed in API-only mode, comma-separated; "*" (the default) allows any
+    #[arg(long = "cors-origin", env = "CORS_ORIGINS", value_delimiter = ',')]
+    pub cors_origins: Vec<String>,
+    /// Seconds in-flight requests and queued deliveries may take to finish on
+    /// SIGINT/SIGTERM; 30 by default
+    #[arg(long, env = "SHUTDOWN_TIMEOUT_SECS")]
+    pub shutdown_timeout_secs: Option<u64>,
+    /// tracing filter such as "info,rust_axum_rest_api=debug"; RUST_LOG, then "info",
+    /// when not set
+    #[arg(long, env = "LOG_LEVEL")]
+    pub log_level: Option<String>,
+    #[command(flatten)]
+    pub database: DatabaseArgs,
+}
+
+// The [database] flags, shared with tpcc-drive --in-process
+#[derive(clap::Args, Clone, Debug, Default)]
+pub struct DatabaseArgs {
+    /// PostgreSQL connection URL
+    #[arg(long, env = "DATABASE_URL", hide_env_values = true)]
+    pub database_url: Option<String>,
+    /// Connections the pool keeps open; 0 by default
+    #[arg(long, env = "DB_MIN_CONNECTIONS")]
+    pub min_connections: Option<u32>,
+    /// Most connections the pool opens, shared by all table sets; 10 by default
+    #[arg(long, env = "DB_MAX_CONNECTIONS")]
+    pub max_connections: Option<u32>,
+    /// Seconds to wait for a free connection; 30 by default
+    #[arg(long, env = "DB_ACQUIRE_TIMEOUT_SECS")]
+    pub acquire_timeout_secs: Option<u64>,
+    /// Seconds before an idle connection is closed (600 by default); 0 keeps them open
+    #[arg(long, env = "DB_IDLE_TIMEOUT_SECS")]
+    pub idle_timeout_secs: Option<u64>,
+    /// PostgreSQL statement_timeout of every connection; the server's when not set
+    #[arg(long, env = "DB_STATEMENT_TIMEOUT_MS")]
+    pub statement_timeout_ms: Option<u64>,
+    /// Apply pending migrations before serving, and refuse to start on drift
+    #[arg(long, env = "DB_RUN_MIGRATIONS", value_parser = BoolishValueParser::new())]
+    pub run_migrations: Option<bool>,
+}
+
+impl ConfigArgs {
+    pub fn apply(&self, config: &mut Config) {
+        let server = &mut config.server;
+        if let Some(host) = &self.host {
+            server.host = host.clone();
+        }
+        if let Some(port) = self.port {
+            server.port = port;
+        }
+        if let Some(frontend_dist) = &self.frontend_dist {
+            server.frontend_dist = frontend_dist.clone();
+        }
+        if self.serve_frontend.is_some() {
+            server.serve_frontend = self.serve_frontend;
+        }
+        if !self.cors_origins.is_empty() {
+            server.cors_origins = self.cors_origins.clone();
+        }
+        if let Some(shutdown_timeout_secs) = self.shutdown_timeout_secs {
+            server.shutdown_timeout_secs = shutdown_timeout_secs;
+        }
+        if self.log_level.is_some() {
+            server.log_level = self.log_level.clone();
+        }
+        self.database.apply(&mut config.database);
+    }
+}
+
+impl DatabaseArgs {
+    pub fn apply(&self, database: &mut DatabaseConfig) {
+        if self.database_url.is_some() {
+            database.url = self.database_url.clone();
+        }
+        if let Some(min_connections) = self.min_connections {
+            database.min_connections = min_connections;
+        }
+        if let Some(max_connections) = self.max_connections {
+            database.max_connections = max_connections;
+        }
+        if let Some(acquire_timeout_secs) = self.acquire_timeout_secs {
+            database.acquire_timeout_secs = acquire_timeout_secs;
+        }
+        if let Some(idle_timeout_secs) = self.idle_timeout_secs {
+            database.idle_timeout_secs = idle_timeout_secs;
+        }
+        if self.statement_timeout_ms.is_some() {
+            database.statement_timeout_ms = self.statement_timeout_ms;
+        }
+        if let Some(run_migrations) = self.run_migrations {
+            database.run_migrations = run_migrations;
+        }
+    }
+}
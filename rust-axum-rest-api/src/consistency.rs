@@ -0,0 +1,343 @@
+// The twelve TPC-C consistency conditions (clause 3.3.2), the checks of
+// sysbench-tpcc's tpcc_check.lua plus conditions 6 and 11
+use serde::Serialize;
+use sqlx::{Pool, Postgres};
+
+use crate::error::{ApiError, Entity};
+use crate::transaction::{begin, IsolationLevel};
+
+// Offending rows reported per condition
+pub const MAX_VIOLATIONS: i64 = 100;
+
+#[derive(Serialize)]
+pub struct ConditionReport {
+    pub condition: u8,
+    pub description: &'static str,
+    // Condition 11 only holds until the first Delivery, so it does not count against `consistent`
+    pub required: bool,
+    pub passed: bool,
+    pub violations: Vec<serde_json::Value>,
+}
+
+#[derive(Serialize)]
+pub struct ConsistencyReport {
+    pub warehouse_id: Option<i16>,
+    pub consistent: bool,
+    pub conditions: Vec<ConditionReport>,
+}
+
+// Runs one condition: `$sql` selects the offending rows as JSON, filtered by warehouse $1
+// (NULL for every warehouse) and limited to $2 rows
+macro_rules! condition {
+    ($tx:expr, $warehouse_id:expr, $number:literal, $required:literal, $description:literal, $sql:literal) => {{
+        let violations = sqlx::query_scalar!($sql, $warehouse_id, MAX_VIOLATIONS)
+            .fetch_all(&mut *$tx)
+            .await
+            .map_err(|e| {
+                ApiError::database(concat!("checking consistency condition ", $number), e)
+            })?;
+        ConditionReport {
+            condition: $number,
+            description: $description,
+            required: $required,
+            passed: violations.is_empty(),
+            violations,
+        }
+    }};
+}
+
+// Check every condition for one warehouse, or for all of them, in a single snapshot
+pub async fn check(
+    pool: &Pool<Postgres>,
+    warehouse_id: Option<i16>,
+) -> Result<ConsistencyReport, ApiError> {
+    let mut tx = begin(pool, Some(IsolationLevel::RepeatableRead)).await?;
+
+    if let Some(warehouse_id) = warehouse_id {
+        sqlx::query_scalar!("SELECT w_id FROM warehouse1 WHERE w_id = $1", warehouse_id)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(|e| ApiError::database("fetching warehouse", e))?
+            .ok_or(ApiError::NotFound(Entity::Warehouse { warehouse_id }))?;
+    }
+
+    let conditions = vec![
+        condition!(
+            tx,
+            warehouse_id,
+            1,
+            true,
+            "W_YTD = sum(D_YTD)",
+            r#"
+            SELECT to_jsonb(v) AS "row!" FROM (
+                SELECT w.w_id, w.w_ytd, d.sum_d_ytd
+                FROM warehouse1 w
+                JOIN (SELECT d_w_id, SUM(d_ytd) AS sum_d_ytd FROM district1
+                      WHERE $1::smallint IS NULL OR d_w_id = $1
+                      GROUP BY d_w_id) d ON d.d_w_id = w.w_id
+                WHERE ($1::smallint IS NULL OR w.w_id = $1) AND w.w_ytd <> d.sum_d_ytd
+                ORDER BY w.w_id
+                LIMIT $2
+            ) v
+            "#
+        ),
+        condition!(
+            tx,
+            warehouse_id,
+            2,
+            true,
+            "D_NEXT_O_ID - 1 = max(O_ID) = max(NO_O_ID)",
+            r#"
+            SELECT to_jsonb(v) AS "row!" FROM (
+                SELECT d.d_w_id, d.d_id, d.d_next_o_id - 1 AS last_o_id, o.max_o_id, n.max_no_o_id
+                FROM district1 d
+                LEFT JOIN (SELECT o_w_id, o_d_id, MAX(o_id) AS max_o_id FROM orders1
+                           WHERE $1::smallint IS NULL OR o_w_id = $1
+                           GROUP BY o_w_id, o_d_id) o
+                       ON o.o_w_id = d.d_w_id AND o.o_d_id = d.d_id
+                LEFT JOIN (SELECT no_w_id, no_d_id, MAX(no_o_id) AS max_no_o_id FROM new_orders1
+                           WHERE $1::smallint IS NULL OR no_w_id = $1
+                           GROUP BY no_w_id, no_d_id) n
+                       ON n.no_w_id = d.d_w_id AND n.no_d_id = d.d_id
+                WHERE ($1::smallint IS NULL OR d.d_w_id = $1)
+                  AND (d.d_next_o_id - 1 <> COALESCE(o.max_o_id, 0)
+                       OR d.d_next_o_id - 1 <> n.max_no_o_id)
+                ORDER BY d.d_w_id, d.d_id
+                LIMIT $2
+            ) v
+            "#
+        ),
+        condition!(
+            tx,
+            warehouse_id,
+            3,
+            true,
+            "max(NO_O_ID) - min(NO_O_ID) + 1 = number of NEW-ORDER rows per district",
+            r#"
+            SELECT to_jsonb(v) AS "row!" FROM (
+                SELECT no_w_id, no_d_id, MIN(no_o_id) AS min_no_o_id, MAX(no_o_id) AS max_no_o_id,
+                       COUNT(*) AS new_orders
+                FROM new_orders1
+                WHERE $1::smallint IS NULL OR no_w_id = $1
+                GROUP BY no_w_id, no_d_id
+                HAVING MAX(no_o_id) - MIN(no_o_id) + 1 <> COUNT(*)
+                ORDER BY no_w_id, no_d_id
+                LIMIT $2
+            ) v
+            "#
+        ),
+        condition!(
+            tx,
+            warehouse_id,
+            4,
+            true,
+            "sum(O_OL_CNT) = number of ORDER-LINE rows per district",
+            r#"
+            SELECT to_jsonb(v) AS "row!" FROM (
+                SELECT o.o_w_id, o.o_d_id, o.sum_ol_cnt, COALESCE(ol.order_lines, 0) AS order_lines
+                FROM (SELECT o_w_id, o_d_id, SUM(o_ol_cnt) AS sum_ol_cnt FROM orders1
+                      WHERE $1::smallint IS NULL OR o_w_id = $1
+                      GROUP BY o_w_id, o_d_id) o
+                LEFT JOIN (SELECT ol_w_id, ol_d_id, COUNT(*) AS order_lines FROM order_line1
+                           WHERE $1::smallint IS NULL OR ol_w_id = $1
+                           GROUP BY ol_w_id, ol_d_id) ol
+                       ON ol.ol_w_id = o.o_w_id AND ol.ol_d_id = o.o_d_id
+                WHERE o.sum_ol_cnt <> COALESCE(ol.order_lines, 0)
+                ORDER BY o.o_w_id, o.o_d_id
+                LIMIT $2
+            ) v
+            "#
+        ),
+        condition!(
+            tx,
+            warehouse_id,
+            5,
+            true,
+            "O_CARRIER_ID is null exactly for orders with a NEW-ORDER row",
+            r#"
+            SELECT to_jsonb(v) AS "row!" FROM (
+                SELECT o.o_w_id, o.o_d_id, o.o_id, o.o_carrier_id, n.no_o_id IS NOT NULL AS has_new_order
+                FROM orders1 o
+                LEFT JOIN new_orders1 n
+                       ON n.no_w_id = o.o_w_id AND n.no_d_id = o.o_d_id AND n.no_o_id = o.o_id
+                WHERE ($1::smallint IS NULL OR o.o_w_id = $1)
+                  AND (o.o_carrier_id IS NULL) <> (n.no_o_id IS NOT NULL)
+                ORDER BY o.o_w_id, o.o_d_id, o.o_id
+                LIMIT $2
+            ) v
+            "#
+        ),
+        condition!(
+            tx,
+            warehouse_id,
+            6,
+            true,
+            "O_OL_CNT = number of ORDER-LINE rows per order",
+            r#"
+            SELECT to_jsonb(v) AS "row!" FROM (
+                SELECT o.o_w_id, o.o_d_id, o.o_id, o.o_ol_cnt, COALESCE(ol.order_lines, 0) AS order_lines
+                FROM orders1 o
+                LEFT JOIN (SELECT ol_w_id, ol_d_id, ol_o_id, COUNT(*) AS order_lines FROM order_line1
+                           WHERE $1::smallint IS NULL OR ol_w_id = $1
+                           GROUP BY ol_w_id, ol_d_id, ol_o_id) ol
+                       ON ol.ol_w_id = o.o_w_id AND ol.ol_d_id = o.o_d_id AND ol.ol_o_id = o.o_id
+                WHERE ($1::smallint IS NULL OR o.o_w_id = $1)
+                  AND o.o_ol_cnt <> COALESCE(ol.order_lines, 0)
+                ORDER BY o.o_w_id, o.o_d_id, o.o_id
+                LIMIT $2
+            ) v
+            "#
+        ),
+        condition!(
+            tx,
+            warehouse_id,
+            7,
+            true,
+            "OL_DELIVERY_D is null exactly when O_CARRIER_ID is null",
+            r#"
+            SELECT to_jsonb(v) AS "row!" FROM (
+                SELECT ol.ol_w_id, ol.ol_d_id, ol.ol_o_id, ol.ol_number, ol.ol_delivery_d, o.o_carrier_id
+                FROM order_line1 ol
+                JOIN orders1 o ON o.o_w_id = ol.ol_w_id AND o.o_d_id = ol.ol_d_id AND o.o_id = ol.ol_o_id
+                WHERE ($1::smallint IS NULL OR ol.ol_w_id = $1)
+                  AND (ol.ol_delivery_d IS NULL) <> (o.o_carrier_id IS NULL)
+                ORDER BY ol.ol_w_id, ol.ol_d_id, ol.ol_o_id, ol.ol_number
+                LIMIT $2
+            ) v
+            "#
+        ),
+        condition!(
+            tx,
+            warehouse_id,
+            8,
+            true,
+            "W_YTD = sum(H_AMOUNT) per warehouse",
+            r#"
+            SELECT to_jsonb(v) AS "row!" FROM (
+                SELECT w.w_id, w.w_ytd, COALESCE(h.sum_h_amount, 0) AS sum_h_amount
+                FROM warehouse1 w
+                LEFT JOIN (SELECT h_w_id, SUM(h_amount) AS sum_h_amount FROM history1
+                           WHERE $1::smallint IS NULL OR h_w_id = $1
+                           GROUP BY h_w_id) h ON h.h_w_id = w.w_id
+                WHERE ($1::smallint IS NULL OR w.w_id = $1)
+                  AND w.w_ytd <> COALESCE(h.sum_h_amount, 0)
+                ORDER BY w.w_id
+                LIMIT $2
+            ) v
+            "#
+        ),
+        condition!(
+            tx,
+            warehouse_id,
+            9,
+            true,
+            "D_YTD = sum(H_AMOUNT) per district",
+            r#"
+            SELECT to_jsonb(v) AS "row!" FROM (
+                SELECT d.d_w_id, d.d_id, d.d_ytd, COALESCE(h.sum_h_amount, 0) AS sum_h_amount
+                FROM district1 d
+                LEFT JOIN (SELECT h_w_id, h_d_id, SUM(h_amount) AS sum_h_amount FROM history1
+                           WHERE $1::smallint IS NULL OR h_w_id = $1
+                           GROUP BY h_w_id, h_d_id) h
+                       ON h.h_w_id = d.d_w_id AND h.h_d_id = d.d_id
+                WHERE ($1::smallint IS NULL OR d.d_w_id = $1)
+                  AND d.d_ytd <> COALESCE(h.sum_h_amount, 0)
+                ORDER BY d.d_w_id, d.d_id
+                LIMIT $2
+            ) v
+            "#
+        ),
+        condition!(
+            tx,
+            warehouse_id,
+            10,
+            true,
+            "C_BALANCE = sum(delivered OL_AMOUNT) - sum(H_AMOUNT) per customer",
+            r#"
+            SELECT to_jsonb(v) AS "row!" FROM (
+                SELECT c.c_w_id, c.c_d_id, c.c_id, c.c_balance,
+                       COALESCE(ol.delivered_amount, 0) AS delivered_amount,
+                       COALESCE(h.sum_h_amount, 0) AS sum_h_amount
+                FROM customer1 c
+                LEFT JOIN (SELECT o.o_w_id, o.o_d_id, o.o_c_id, SUM(ol.ol_amount) AS delivered_amount
+                           FROM orders1 o
+                           JOIN order_line1 ol
+                             ON ol.ol_w_id = o.o_w_id AND ol.ol_d_id = o.o_d_id AND ol.ol_o_id = o.o_id
+                           WHERE ($1::smallint IS NULL OR o.o_w_id = $1) AND ol.ol_delivery_d IS NOT NULL
+                           GROUP BY o.o_w_id, o.o_d_id, o.o_c_id) ol
+                       ON ol.o_w_id = c.c_w_id AND ol.o_d_id = c.c_d_id AND ol.o_c_id = c.c_id
+                LEFT JOIN (SELECT h_c_w_id, h_c_d_id, h_c_id, SUM(h_amount) AS sum_h_amount FROM history1
+                           WHERE $1::smallint IS NULL OR h_c_w_id = $1
+                           GROUP BY h_c_w_id, h_c_d_id, h_c_id) h
+                       ON h.h_c_w_id = c.c_w_id AND h.h_c_d_id = c.c_d_id AND h.h_c_id = c.c_id
+                WHERE ($1::smallint IS NULL OR c.c_w_id = $1)
+                  AND c.c_balance <> COALESCE(ol.delivered_amount, 0) - COALESCE(h.sum_h_amount, 0)
+                ORDER BY c.c_w_id, c.c_d_id, c.c_id
+                LIMIT $2
+            ) v
+            "#
+        ),
+        condition!(
+            tx,
+            warehouse_id,
+            11,
+            false,
+            "number of ORDER rows - number of NEW-ORDER rows = 2100 per district (before any Delivery)",
+            r#"
+            SELECT to_jsonb(v) AS "row!" FROM (
+                SELECT d.d_w_id, d.d_id, COALESCE(o.orders, 0) AS orders, COALESCE(n.new_orders, 0) AS new_orders
+                FROM district1 d
+                LEFT JOIN (SELECT o_w_id, o_d_id, COUNT(*) AS orders FROM orders1
+                           WHERE $1::smallint IS NULL OR o_w_id = $1
+                           GROUP BY o_w_id, o_d_id) o
+                       ON o.o_w_id = d.d_w_id AND o.o_d_id = d.d_id
+                LEFT JOIN (SELECT no_w_id, no_d_id, COUNT(*) AS new_orders FROM new_orders1
+                           WHERE $1::smallint IS NULL OR no_w_id = $1
+                           GROUP BY no_w_id, no_d_id) n
+                       ON n.no_w_id = d.d_w_id AND n.no_d_id = d.d_id
+                WHERE ($1::smallint IS NULL OR d.d_w_id = $1)
+                  AND COALESCE(o.orders, 0) - COALESCE(n.new_orders, 0) <> 2100
+                ORDER BY d.d_w_id, d.d_id
+                LIMIT $2
+            ) v
+            "#
+        ),
+        condition!(
+            tx,
+            warehouse_id,
+            12,
+            true,
+            "C_BALANCE + C_YTD_PAYMENT = sum(delivered OL_AMOUNT) per customer",
+            r#"
+            SELECT to_jsonb(v) AS "row!" FROM (
+                SELECT c.c_w_id, c.c_d_id, c.c_id, c.c_balance, c.c_ytd_payment,
+                       COALESCE(ol.delivered_amount, 0) AS delivered_amount
+                FROM customer1 c
+                LEFT JOIN (SELECT o.o_w_id, o.o_d_id, o.o_c_id, SUM(ol.ol_amount) AS delivered_amount
+                           FROM orders1 o
+                           JOIN order_line1 ol
+                             ON ol.ol_w_id = o.o_w_id AND ol.ol_d_id = o.o_d_id AND ol.ol_o_id = o.o_id
+                           WHERE ($1::smallint IS NULL OR o.o_w_id = $1) AND ol.ol_delivery_d IS NOT NULL
+                           GROUP BY o.o_w_id, o.o_d_id, o.o_c_id) ol
+                       ON ol.o_w_id = c.c_w_id AND ol.o_d_id = c.c_d_id AND ol.o_c_id = c.c_id
+                WHERE ($1::smallint IS NULL OR c.c_w_id = $1)
+                  AND c.c_balance + c.c_ytd_payment <> COALESCE(ol.delivered_amount, 0)
+                ORDER BY c.c_w_id, c.c_d_id, c.c_id
+                LIMIT $2
+            ) v
+            "#
+        ),
+    ];
+
+    tx.commit()
+        .await
+        .map_err(|e| ApiError::database("committing transaction", e))?;
+
+    Ok(ConsistencyReport {
+        warehouse_id,
+        consistent: conditions
+            .iter()
+            .all(|condition| condition.passed || !condition.required),
+        conditions,
+    })
+}
@@ -0,0 +1,337 @@
+use chrono::{NaiveDateTime, Utc};
+use serde::Serialize;
+use sqlx::{Pool, Postgres};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tracing::Instrument;
+
+use crate::error::ApiError;
+use crate::handlers::delivery::{deliver_districts, DeliveredOrder};
+use crate::transaction::{begin, run_transaction, ExecutionMode, IsolationLevel, RetryPolicy};
+
+// Deliveries waiting for a worker before POST /delivery?deferred=true is refused
+const QUEUE_CAPACITY: usize = 1024;
+
+// Number of finished deliveries kept in the result log
+const RESULT_LOG_CAPACITY: usize = 10_000;
+
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeferredDeliveryStatus {
+    Queued,
+    Started,
+    Completed,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum DistrictDeliveryOutcome {
+    Delivered {
+        order: DeliveredOrder,
+    },
+    Skipped {
+        district_id: i16,
+    },
+    Failed {
+        district_id: i16,
+        code: &'static str,
+        error: String,
+    },
+}
+
+// Result log entry for one deferred delivery (GET /delivery/{ticket})
+#[derive(Serialize, Clone)]
+pub struct DeferredDeliveryRecord {
+    pub ticket: u64,
+    pub table_set: u32,
+    pub warehouse_id: i16,
+    pub carrier_id: i16,
+    pub status: DeferredDeliveryStatus,
+    pub queued_at: NaiveDateTime,
+    pub started_at: Option<NaiveDateTime>,
+    pub completed_at: Option<NaiveDateTime>,
+    pub districts: Vec<DistrictDeliveryOutcome>,
+}
+
+struct QueuedDelivery {
+    ticket: u64,
+    pool: Pool<Postgres>,
+    warehouse_id: i16,
+    carrier_id: i16,
+    district_ids: Vec<i16>,
+}
+
+type ResultLog = Arc<Mutex<BTreeMap<u64, DeferredDeliveryRecord>>>;
+
+// In-process queue for TPC-C deferred Delivery. The terminal gets a ticket
+// immediately; worker tasks process each district in its own transaction
+// and record the outcome in the result log.
+#[derive(Clone)]
+pub struct DeliveryQueue {
+    sender: mpsc::Sender<QueuedDelivery>,
+    results: ResultLog,
+    next_ticket: Arc<AtomicU64>,
+    closed: Arc<AtomicBool>,
+    // Tells idle workers to exit once the queue is empty
+    shutdown: watch::Sender<bool>,
+    workers: Arc<Mutex<Vec<JoinHandle<()>>>>,
+}
+
+impl DeliveryQueue {
+    // Create the queue and spawn `workers` tokio tasks to drain it. Each queued
+    // delivery carries the pool of its table set.
+    pub fn start(
+        workers: usize,
+        retry_policy: RetryPolicy,
+        isolation: Option<IsolationLevel>,
+        mode: ExecutionMode,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel(QUEUE_CAPACITY);
+        let receiver = Arc::new(tokio::sync::Mutex::new(receiver));
+        let results: ResultLog = Arc::new(Mutex::new(BTreeMap::new()));
+        let (shutdown, shutdown_receiver) = watch::channel(false);
+
+        let workers = (0..workers.max(1))
+            .map(|_| {
+                tokio::spawn(run_worker(
+                    retry_policy,
+                    isolation,
+                    mode,
+                    Arc::clone(&receiver),
+                    Arc::clone(&results),
+                    shutdown_receiver.clone(),
+                ))
+            })
+            .collect();
+
+        DeliveryQueue {
+            sender,
+            results,
+            next_ticket: Arc::new(AtomicU64::new(1)),
+            closed: Arc::new(AtomicBool::new(false)),
+            shutdown,
+            workers: Arc::new(Mutex::new(workers)),
+        }
+    }
+
+    // Queue a delivery and return its initial log entry; fails when the queue
+    // is full or shut down
+    pub fn enqueue(
+        &self,
+        table_set: u32,
+        pool: Pool<Postgres>,
+        warehouse_id: i16,
+        carrier_id: i16,
+        district_ids: Vec<i16>,
+    ) -> Result<DeferredDeliveryRecord, ApiError> {
+        if self.closed.load(Ordering::Acquire) {
+            return Err(ApiError::ShuttingDown);
+        }
+        let ticket = self.next_ticket.fetch_add(1, Ordering::Relaxed);
+        let record = DeferredDeliveryRecord {
+            ticket,
+            table_set,
+            warehouse_id,
+            carrier_id,
+            status: DeferredDeliveryStatus::Queued,
+            queued_at: Utc::now().naive_utc(),
+            started_at: None,
+            completed_at: None,
+            districts: Vec::new(),
+        };
+
+        // Log before sending so a fast worker always finds the entry
+        insert_record(&self.results, record.clone());
+
+        let queued = QueuedDelivery {
+            ticket,
+            pool,
+            warehouse_id,
+            carrier_id,
+            district_ids,
+        };
+        if self.sender.try_send(queued).is_err() {
+            self.results.lock().unwrap().remove(&ticket);
+            return Err(ApiError::QueueFull);
+        }
+
+        Ok(record)
+    }
+
+    // Deliveries queued or in progress
+    pub fn pending(&self) -> usize {
+        self.results
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|record| record.status != DeferredDeliveryStatus::Completed)
+            .count()
+    }
+
+    // Refuse new deliveries and let the workers finish every queued one, waiting
+    // until `deadline` at most. Returns the number of deliveries left unfinished.
+    pub async fn shutdown(&self, deadline: Instant) -> usize {
+        self.closed.store(true, Ordering::Release);
+        self.shutdown.send_replace(true);
+
+        let workers = std::mem::take(&mut *self.workers.lock().unwrap());
+        let aborts: Vec<_> = workers.iter().map(JoinHandle::abort_handle).collect();
+        let join = async {
+            for worker in workers {
+                let _ = worker.await;
+            }
+        };
+        if tokio::time::timeout_at(deadline, join).await.is_err() {
+            // Stop them so they release their connections; open transactions roll back
+            tracing::warn!("Deferred delivery workers did not finish before the deadline");
+            aborts.iter().for_each(|abort| abort.abort());
+        }
+        self.pending()
+    }
+
+    pub fn record(&self, ticket: u64) -> Option<DeferredDeliveryRecord> {
+        self.results.lock().unwrap().get(&ticket).cloned()
+    }
+}
+
+// Log a new record, evicting the oldest completed ones past RESULT_LOG_CAPACITY.
+// Queued and started deliveries stay pollable; there are at most QUEUE_CAPACITY
+// plus one per worker of them, so the log only outgrows its capacity by as many.
+fn insert_record(results: &ResultLog, record: DeferredDeliveryRecord) {
+    let mut results = results.lock().unwrap();
+    results.insert(record.ticket, record);
+    let excess = results.len().saturating_sub(RESULT_LOG_CAPACITY);
+    let evicted: Vec<u64> = results
+        .values()
+        .filter(|record| record.status == DeferredDeliveryStatus::Completed)
+        .map(|record| record.ticket)
+        .take(excess)
+        .collect();
+    for ticket in evicted {
+        results.remove(&ticket);
+    }
+}
+
+fn update_record(
+    results: &ResultLog,
+    ticket: u64,
+    update: impl FnOnce(&mut DeferredDeliveryRecord),
+) {
+    if let Some(record) = results.lock().unwrap().get_mut(&ticket) {
+        update(record);
+    }
+}
+
+async fn run_worker(
+    retry_policy: RetryPolicy,
+    isolation: Option<IsolationLevel>,
+    mode: ExecutionMode,
+    receiver: Arc<tokio::sync::Mutex<mpsc::Receiver<QueuedDelivery>>>,
+    results: ResultLog,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    loop {
+        let next = {
+            let mut receiver = receiver.lock().await;
+            tokio::select! {
+                // Drain what is queued before honoring shutdown
+                biased;
+                job = receiver.recv() => job,
+                _ = shutdown.wait_for(|&shutdown| shutdown) => receiver.try_recv().ok(),
+            }
+        };
+        let Some(job) = next else {
+            break; // Queue closed or drained at shutdown
+        };
+
+        let delivery_date = Utc::now().naive_utc();
+        update_record(&results, job.ticket, |record| {
+            record.status = DeferredDeliveryStatus::Started;
+            record.started_at = Some(delivery_date);
+        });
+
+        let span = tracing::info_span!(
+            "deferred_delivery",
+            ticket = job.ticket,
+            w_id = job.warehouse_id,
+            carrier_id = job.carrier_id
+        );
+        let mut outcomes = Vec::with_capacity(job.district_ids.len());
+        for &district_id in &job.district_ids {
+            let outcome = deliver_district(
+                &job,
+                &retry_policy,
+                isolation,
+                mode,
+                district_id,
+                delivery_date,
+            )
+            .instrument(span.clone())
+            .await;
+            outcomes.push(outcome);
+        }
+
+        let delivered = outcomes
+            .iter()
+            .filter(|outcome| matches!(outcome, DistrictDeliveryOutcome::Delivered { .. }))
+            .count();
+        tracing::info!(
+            "Deferred delivery {} for warehouse {} completed: {} of {} districts delivered",
+            job.ticket,
+            job.warehouse_id,
+            delivered,
+            outcomes.len()
+        );
+
+        update_record(&results, job.ticket, |record| {
+            record.status = DeferredDeliveryStatus::Completed;
+            record.completed_at = Some(Utc::now().naive_utc());
+            record.districts = outcomes;
+        });
+    }
+}
+
+// Deliver one of the job's districts in its own transaction
+async fn deliver_district(
+    job: &QueuedDelivery,
+    retry_policy: &RetryPolicy,
+    isolation: Option<IsolationLevel>,
+    mode: ExecutionMode,
+    district_id: i16,
+    delivery_date: NaiveDateTime,
+) -> DistrictDeliveryOutcome {
+    let result = run_transaction(retry_policy, "deferred delivery", || async {
+        let mut tx = begin(&job.pool, isolation).await?;
+        let delivered = deliver_districts(
+            &mut tx,
+            mode,
+            job.warehouse_id,
+            &[district_id],
+            job.carrier_id,
+            delivery_date,
+        )
+        .await?
+        .pop()
+        .flatten();
+        tx.commit()
+            .await
+            .map_err(|e| ApiError::database("committing transaction", e))?;
+        Ok::<_, ApiError>(delivered)
+    })
+    .await
+    .map(|attempted| attempted.value);
+
+    match result {
+        Ok(Some(order)) => DistrictDeliveryOutcome::Delivered { order },
+        Ok(None) => DistrictDeliveryOutcome::Skipped { district_id },
+        Err(error) => DistrictDeliveryOutcome::Failed {
+            district_id,
+            code: error.code(),
+            error: error.to_string(),
+        },
+    }
+}
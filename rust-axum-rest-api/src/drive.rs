@@ -0,0 +1,609 @@
+// TPC-C remote terminal emulator (clause 5.2): every terminal is bound to a warehouse and
+// district, draws transactions from a shuffled card deck, keys them in, waits for the
+// response and thinks before the next one
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::handlers::delivery::{DeliveryRequest, DISTRICTS_PER_WAREHOUSE};
+use crate::handlers::new_order::{NewOrderOutcome, NewOrderRequest, OrderLineRequest};
+use crate::handlers::order_status::OrderStatusQuery;
+use crate::handlers::payment::PaymentRequest;
+use crate::handlers::stock_level::StockLevelQuery;
+use crate::load::ITEMS;
+use crate::random::{self, NuRandConstants};
+use crate::table_set::{TableSets, TABLE_SET_HEADER};
+use crate::transaction::{TransactionKind, TransactionSettings};
+use crate::transaction_pool::TransactionPool;
+
+pub struct DriveOptions {
+    pub executor: Executor,
+    pub warehouses: i16,
+    pub table_sets: u32,
+    // Terminals per warehouse, bound to districts 1..=10 in turn; 10 in the specification
+    pub terminals_per_warehouse: u16,
+    // Transactions started before the ramp-up ends are not measured
+    pub ramp_up: Duration,
+    pub duration: Duration,
+    // Factor applied to keying and think times; 1.0 follows the specification, 0.0 disables them
+    pub think_time_scale: f64,
+    pub nurand: NuRandConstants,
+}
+
+// Keying time (clause 5.2.5.2) and mean think time (clause 5.2.5.4) in seconds
+fn keying_and_mean_think_time(kind: TransactionKind) -> (f64, f64) {
+    match kind {
+        TransactionKind::NewOrder => (18.0, 12.0),
+        TransactionKind::Payment => (3.0, 12.0),
+        TransactionKind::OrderStatus => (2.0, 10.0),
+        TransactionKind::Delivery => (2.0, 5.0),
+        TransactionKind::StockLevel => (2.0, 5.0),
+    }
+}
+
+// Negative exponential think time, truncated at ten times the mean
+fn think_time(rng: &mut impl Rng, mean: f64) -> f64 {
+    let r: f64 = rng.gen_range(f64::EPSILON..1.0);
+    (-r.ln() * mean).min(10.0 * mean)
+}
+
+// The 23-card deck of clause 5.2.4.2: 10 New-Order, 10 Payment and one of each other type,
+// reshuffled whenever it runs out
+pub struct Deck {
+    cards: Vec<TransactionKind>,
+    next: usize,
+}
+
+impl Deck {
+    pub fn new() -> Self {
+        let mut cards = vec![TransactionKind::NewOrder; 10];
+        cards.extend([TransactionKind::Payment; 10]);
+        cards.extend([
+            TransactionKind::OrderStatus,
+            TransactionKind::Delivery,
+            TransactionKind::StockLevel,
+        ]);
+        Deck {
+            next: cards.len(),
+            cards,
+        }
+    }
+
+    pub fn draw(&mut self, rng: &mut impl Rng) -> TransactionKind {
+        if self.next == self.cards.len() {
+            self.cards.shuffle(rng);
+            self.next = 0;
+        }
+        self.next += 1;
+        self.cards[self.next - 1]
+    }
+}
+
+impl Default for Deck {
+    fn default() -> Self {
+        Deck::new()
+    }
+}
+
+// Input of one transaction, as sent to the API
+pub enum TransactionInput {
+    NewOrder(NewOrderRequest),
+    Payment(PaymentRequest),
+    OrderStatus(OrderStatusQuery),
+    Delivery(DeliveryRequest),
+    StockLevel(StockLevelQuery),
+}
+
+impl TransactionInput {
+    pub fn kind(&self) -> TransactionKind {
+        match self {
+            TransactionInput::NewOrder(_) => TransactionKind::NewOrder,
+            TransactionInput::Payment(_) => TransactionKind::Payment,
+            TransactionInput::OrderStatus(_) => TransactionKind::OrderStatus,
+            TransactionInput::Delivery(_) => TransactionKind::Delivery,
+            TransactionInput::StockLevel(_) => TransactionKind::StockLevel,
+        }
+    }
+}
+
+// A terminal's home warehouse and district, and how it picks transaction inputs
+pub struct Terminal {
+    pub table_set: u32,
+    pub warehouse_id: i16,
+    pub district_id: i16,
+    pub warehouses: i16,
+    pub nurand: NuRandConstants,
+}
+
+impl Terminal {
+    fn random_district(rng: &mut impl Rng) -> i16 {
+        rng.gen_range(1..=DISTRICTS_PER_WAREHOUSE)
+    }
+
+    fn other_warehouse(&self, rng: &mut impl Rng) -> i16 {
+        let other = rng.gen_range(1..self.warehouses);
+        if other >= self.warehouse_id {
+            other + 1
+        } else {
+            other
+        }
+    }
+
+    // 60% by last name, 40% by customer number (clauses 2.5.1.2 and 2.6.1.2)
+    fn customer(&self, rng: &mut impl Rng) -> (Option<i32>, Option<String>) {
+        if rng.gen_range(1..=100) <= 60 {
+            let number = self.nurand.customer_last_name_number(rng);
+            (None, Some(random::last_name(number)))
+        } else {
+            (Some(self.nurand.customer_id(rng) as i32), None)
+        }
+    }
+
+    pub fn input(&self, kind: TransactionKind, rng: &mut impl Rng) -> TransactionInput {
+        match kind {
+            // Clause 2.4.1
+            TransactionKind::NewOrder => {
+                let line_count = rng.gen_range(5..=15);
+                let rollback = rng.gen_range(1..=100) == 1;
+                let order_lines = (1..=line_count)
+                    .map(|number| {
+                        let remote = self.warehouses > 1 && rng.gen_range(1..=100) == 1;
+                        OrderLineRequest {
+                            // 1% of orders end with an unused item and roll back
+                            item_id: if rollback && number == line_count {
+                                ITEMS + 1
+                            } else {
+                                self.nurand.item_id(rng) as i32
+                            },
+                            supply_warehouse_id: if remote {
+                                self.other_warehouse(rng)
+                            } else {
+                                self.warehouse_id
+                            },
+                            quantity: rng.gen_range(1..=10),
+                        }
+                    })
+                    .collect();
+                TransactionInput::NewOrder(NewOrderRequest {
+                    warehouse_id: self.warehouse_id,
+                    district_id: Self::random_district(rng),
+                    customer_id: self.nurand.customer_id(rng) as i32,
+                    order_lines,
+                })
+            }
+            // Clause 2.5.1: 15% of payments are for a customer of another warehouse
+            TransactionKind::Payment => {
+                let district_id = Self::random_district(rng);
+                let (customer_warehouse_id, customer_district_id) =
+                    if self.warehouses > 1 && rng.gen_range(1..=100) > 85 {
+                        (self.other_warehouse(rng), Self::random_district(rng))
+                    } else {
+                        (self.warehouse_id, district_id)
+                    };
+                let (customer_id, customer_last_name) = self.customer(rng);
+                TransactionInput::Payment(PaymentRequest {
+                    warehouse_id: self.warehouse_id,
+                    district_id,
+                    customer_id,
+                    customer_last_name,
+                    customer_warehouse_id: Some(customer_warehouse_id),
+                    customer_district_id: Some(customer_district_id),
+                    amount: rng.gen_range(100..=500_000) as f64 / 100.0,
+                })
+            }
+            // Clause 2.6.1
+            TransactionKind::OrderStatus => {
+                let (customer_id, customer_last_name) = self.customer(rng);
+                TransactionInput::OrderStatus(OrderStatusQuery {
+                    warehouse_id: self.warehouse_id,
+                    district_id: Self::random_district(rng),
+                    customer_id,
+                    customer_last_name,
+                })
+            }
+            // Clause 2.7.1
+            TransactionKind::Delivery => TransactionInput::Delivery(DeliveryRequest {
+                warehouse_id: self.warehouse_id,
+                district_id: None,
+                carrier_id: Some(rng.gen_range(1..=10)),
+            }),
+            // Clause 2.8.1: the terminal's own district
+            TransactionKind::StockLevel => TransactionInput::StockLevel(StockLevelQuery {
+                warehouse_id: self.warehouse_id,
+                district_id: self.district_id,
+                threshold: rng.gen_range(10..=20),
+            }),
+        }
+    }
+}
+
+// How a transaction ended
+pub enum Outcome {
+    Completed,
+    // A New-Order that rolled back for its unused item, as the specification requires
+    RolledBack,
+    // Error code of the API, or the transport error
+    Failed(String),
+}
+
+// Where the terminals' transactions go
+#[derive(Clone)]
+pub enum Executor {
+    // Through the REST API
+    Http(HttpClient),
+    // Straight to the library functions behind the handlers, skipping HTTP, JSON and routing
+    InProcess {
+        table_sets: TableSets,
+        settings: TransactionSettings,
+    },
+}
+
+impl Executor {
+    pub async fn execute(&self, table_set: u32, input: &TransactionInput) -> Outcome {
+        match self {
+            Executor::Http(client) => client.execute(table_set, input).await,
+            Executor::InProcess {
+                table_sets,
+                settings,
+            } => {
+                let Some(transactions) = table_sets.transaction_pool(table_set) else {
+                    return Outcome::Failed("table_set_not_found".to_string());
+                };
+                execute_in_process(&transactions, settings, input).await
+            }
+        }
+    }
+}
+
+// Delivery always runs inline here; there is no queue without the server
+async fn execute_in_process(
+    transactions: &TransactionPool,
+    settings: &TransactionSettings,
+    input: &TransactionInput,
+) -> Outcome {
+    let result = match input {
+        TransactionInput::NewOrder(request) => transactions
+            .new_order(settings, request)
+            .await
+            .map(|attempted| matches!(attempted.value, NewOrderOutcome::RolledBack(_))),
+        TransactionInput::Payment(request) => {
+            transactions.payment(settings, request).await.map(|_| false)
+        }
+        TransactionInput::OrderStatus(query) => transactions
+            .order_status(settings, query)
+            .await
+            .map(|_| false),
+        TransactionInput::Delivery(request) => transactions
+            .delivery(settings, request)
+            .await
+            .map(|_| false),
+        TransactionInput::StockLevel(query) => transactions
+            .stock_level(settings, query)
+            .await
+            .map(|_| false),
+    };
+    match result {
+        Ok(false) => Outcome::Completed,
+        Ok(true) => Outcome::RolledBack,
+        Err(e) => Outcome::Failed(e.code().to_string()),
+    }
+}
+
+// Sends transactions to the REST API
+#[derive(Clone)]
+pub struct HttpClient {
+    client: reqwest::Client,
+    api_base: String,
+    deferred_delivery: bool,
+}
+
+impl HttpClient {
+    // `deferred_delivery` queues Delivery for the server's background workers (clause 2.7.2)
+    pub fn new(api_base: &str, deferred_delivery: bool) -> Self {
+        HttpClient {
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(60))
+                .build()
+                .expect("failed to build HTTP client"),
+            api_base: api_base.trim_end_matches('/').to_string(),
+            deferred_delivery,
+        }
+    }
+
+    // Poll /readyz until the server reports its database ready, or give up after
+    // `timeout` with the last reason it was not
+    pub async fn wait_ready(&self, timeout: Duration) -> Result<(), String> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let reason = match self
+                .client
+                .get(format!("{}/readyz", self.api_base))
+                .send()
+                .await
+            {
+                Ok(response) if response.status().is_success() => return Ok(()),
+                Ok(response) => match response.json::<serde_json::Value>().await {
+                    Ok(report) => format!("not ready: {}", report),
+                    Err(e) => e.to_string(),
+                },
+                Err(e) => e.to_string(),
+            };
+            if Instant::now() >= deadline {
+                return Err(reason);
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    }
+
+    pub async fn execute(&self, table_set: u32, input: &TransactionInput) -> Outcome {
+        let url = |path: &str| format!("{}{}", self.api_base, path);
+        let request = match input {
+            TransactionInput::NewOrder(body) => self.client.post(url("/new-order")).json(body),
+            TransactionInput::Payment(body) => self.client.post(url("/payment")).json(body),
+            TransactionInput::OrderStatus(query) => {
+                self.client.get(url("/order-status")).query(query)
+            }
+            TransactionInput::Delivery(body) => self
+                .client
+                .post(url("/delivery"))
+                .query(&[("deferred", self.deferred_delivery)])
+                .json(body),
+            TransactionInput::StockLevel(query) => {
+                self.client.get(url("/stock-level")).query(query)
+            }
+        };
+
+        let response = match request
+            .header(TABLE_SET_HEADER, table_set.to_string())
+            .send()
+            .await
+        {
+            Ok(response) => response,
+            Err(e) => return Outcome::Failed(e.to_string()),
+        };
+        let success = response.status().is_success();
+        let body: serde_json::Value = match response.json().await {
+            Ok(body) => body,
+            Err(e) => return Outcome::Failed(e.to_string()),
+        };
+        if !success {
+            return Outcome::Failed(body["code"].as_str().unwrap_or("unknown").to_string());
+        }
+        if body["outcome"] == "rolled_back" {
+            Outcome::RolledBack
+        } else {
+            Outcome::Completed
+        }
+    }
+}
+
+// Response times and failures of one transaction type
+#[derive(Default)]
+pub struct TransactionStats {
+    pub response_times: Vec<Duration>,
+    pub rolled_back: u64,
+    pub failed: u64,
+}
+
+impl TransactionStats {
+    fn merge(&mut self, other: TransactionStats) {
+        self.response_times.extend(other.response_times);
+        self.rolled_back += other.rolled_back;
+        self.failed += other.failed;
+    }
+
+    // Nearest-rank percentile of the response times
+    pub fn percentile(&self, percent: f64) -> Option<Duration> {
+        let mut sorted = self.response_times.clone();
+        sorted.sort_unstable();
+        let rank = ((percent / 100.0) * sorted.len() as f64).ceil() as usize;
+        sorted.get(rank.max(1) - 1).copied()
+    }
+}
+
+pub struct DriveReport {
+    pub terminals: usize,
+    pub measured: Duration,
+    // Indexed like TransactionKind::ALL
+    pub stats: [TransactionStats; 5],
+    pub errors: Vec<String>,
+}
+
+impl DriveReport {
+    pub fn stats(&self, kind: TransactionKind) -> &TransactionStats {
+        &self.stats[kind as usize]
+    }
+
+    // New-Order transactions per minute of the measurement interval; rolled back
+    // New-Orders are complete business transactions and count as well
+    pub fn tpmc(&self) -> f64 {
+        let new_orders = self.stats(TransactionKind::NewOrder).response_times.len();
+        new_orders as f64 * 60.0 / self.measured.as_secs_f64()
+    }
+}
+
+// Emulate the terminals of every warehouse and table set and report the measurement interval
+pub async fn drive(options: DriveOptions) -> DriveReport {
+    let started = Instant::now();
+    let measure_from = started + options.ramp_up;
+    let measure_until = measure_from + options.duration;
+    let think_time_scale = options.think_time_scale;
+    let errors = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+    let mut tasks = tokio::task::JoinSet::new();
+    for table_set in 1..=options.table_sets {
+        for warehouse_id in 1..=options.warehouses {
+            for number in 0..options.terminals_per_warehouse {
+                let terminal = Terminal {
+                    table_set,
+                    warehouse_id,
+                    district_id: (number % DISTRICTS_PER_WAREHOUSE as u16) as i16 + 1,
+                    warehouses: options.warehouses,
+                    nurand: options.nurand,
+                };
+                let executor = options.executor.clone();
+                let errors = Arc::clone(&errors);
+                tasks.spawn(async move {
+                    let mut rng = StdRng::from_entropy();
+                    let mut deck = Deck::new();
+                    let mut stats: [TransactionStats; 5] = Default::default();
+                    while Instant::now() < measure_until {
+                        let kind = deck.draw(&mut rng);
+                        let input = terminal.input(kind, &mut rng);
+                        let (keying, mean_think) = keying_and_mean_think_time(kind);
+                        sleep_scaled(keying, think_time_scale, measure_until).await;
+
+                        let start = Instant::now();
+                        let outcome = executor.execute(terminal.table_set, &input).await;
+                        let end = Instant::now();
+                        if start >= measure_from && end <= measure_until {
+                            let stats = &mut stats[kind as usize];
+                            match outcome {
+                                Outcome::Completed => stats.response_times.push(end - start),
+                                Outcome::RolledBack => {
+                                    stats.response_times.push(end - start);
+                                    stats.rolled_back += 1;
+                                }
+                                Outcome::Failed(error) => {
+                                    stats.failed += 1;
+                                    errors.lock().unwrap().push(format!(
+                                        "{}: {}",
+                                        kind.name(),
+                                        error
+                                    ));
+                                }
+                            }
+                        }
+
+                        let think = think_time(&mut rng, mean_think);
+                        sleep_scaled(think, think_time_scale, measure_until).await;
+                    }
+                    stats
+                });
+            }
+        }
+    }
+
+    let terminals = tasks.len();
+    let mut stats: [TransactionStats; 5] = Default::default();
+    while let Some(result) = tasks.join_next().await {
+        let terminal_stats = result.expect("terminal task panicked");
+        for (total, terminal) in stats.iter_mut().zip(terminal_stats) {
+            total.merge(terminal);
+        }
+    }
+
+    let errors = std::mem::take(&mut *errors.lock().unwrap());
+    DriveReport {
+        terminals,
+        measured: options.duration,
+        stats,
+        errors,
+    }
+}
+
+async fn sleep_scaled(seconds: f64, scale: f64, until: Instant) {
+    if scale > 0.0 {
+        let wake = Instant::now() + Duration::from_secs_f64(seconds * scale);
+        tokio::time::sleep_until(wake.min(until).into()).await;
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+// Report in the layout of sysbench's summary (results/m1-pro-axum/results.txt)
+impl fmt::Display for DriveReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let seconds = self.measured.as_secs_f64();
+        let all: Vec<Duration> = self
+            .stats
+            .iter()
+            .flat_map(|stats| stats.response_times.iter().copied())
+            .collect();
+        let total = all.len();
+        let failed: u64 = self.stats.iter().map(|stats| stats.failed).sum();
+
+        writeln!(f, "TPC-C statistics:")?;
+        writeln!(f, "    transactions:")?;
+        for kind in TransactionKind::ALL {
+            let stats = self.stats(kind);
+            writeln!(
+                f,
+                "        {:<33}{} ({:.2}%)",
+                format!("{}:", kind.name()),
+                stats.response_times.len(),
+                percent(stats.response_times.len(), total)
+            )?;
+        }
+        writeln!(
+            f,
+            "        {:<33}{} ({:.2} per sec.)",
+            "total:",
+            total,
+            total as f64 / seconds
+        )?;
+        writeln!(
+            f,
+            "    {:<37}{} ({:.2} per sec.)",
+            "rolled back New-Orders:",
+            self.stats(TransactionKind::NewOrder).rolled_back,
+            self.stats(TransactionKind::NewOrder).rolled_back as f64 / seconds
+        )?;
+        writeln!(
+            f,
+            "    {:<37}{} ({:.2} per sec.)",
+            "failed transactions:",
+            failed,
+            failed as f64 / seconds
+        )?;
+        writeln!(f)?;
+
+        writeln!(f, "General statistics:")?;
+        writeln!(f, "    {:<37}{:.4}s", "total time:", seconds)?;
+        writeln!(f, "    {:<37}{}", "total number of events:", total)?;
+        writeln!(f, "    {:<37}{}", "terminals:", self.terminals)?;
+        writeln!(f)?;
+
+        writeln!(f, "Latency (ms):")?;
+        let sum: Duration = all.iter().sum();
+        let min = all.iter().min().copied().unwrap_or_default();
+        let max = all.iter().max().copied().unwrap_or_default();
+        let avg = if total > 0 {
+            sum / total as u32
+        } else {
+            Duration::ZERO
+        };
+        for (label, value) in [("min:", min), ("avg:", avg), ("max:", max)] {
+            writeln!(f, "         {:<30}{:>12.2}", label, millis(value))?;
+        }
+        writeln!(f, "         {:<30}{:>12.2}", "sum:", millis(sum))?;
+        writeln!(f)?;
+
+        writeln!(f, "90th percentile response time (ms):")?;
+        for kind in TransactionKind::ALL {
+            let p90 = self.stats(kind).percentile(90.0).unwrap_or_default();
+            writeln!(
+                f,
+                "         {:<30}{:>12.2}",
+                format!("{}:", kind.name()),
+                millis(p90)
+            )?;
+        }
+        writeln!(f)?;
+
+        writeln!(f, "    {:<37}{:.2}", "tpmC:", self.tpmc())
+    }
+}
+
+fn percent(part: usize, total: usize) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        part as f64 * 100.0 / total as f64
+    }
+}
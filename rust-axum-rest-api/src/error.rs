@@ -0,0 +1,397 @@
+use axum::{
+    extract::rejection::{JsonRejection, PathRejection, QueryRejection},
+    http::{HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use sqlx::postgres::PgDatabaseError;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::transaction::ATTEMPTS_HEADER;
+
+// PostgreSQL SQLSTATEs the client may retry
+const SERIALIZATION_FAILURE: &str = "40001";
+const DEADLOCK_DETECTED: &str = "40P01";
+// Raised by the tpcc_* functions for a row that does not exist
+const NO_DATA_FOUND: &str = "P0002";
+
+// The row an error refers to, serialized as its key fields
+// e.g. {"item_id": 123} or {"warehouse_id": 1, "district_id": 2, "customer_id": 3}
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum Entity {
+    Warehouse {
+        warehouse_id: i16,
+    },
+    District {
+        warehouse_id: i16,
+        district_id: i16,
+    },
+    Customer {
+        warehouse_id: i16,
+        district_id: i16,
+        customer_id: i32,
+    },
+    CustomerLastName {
+        warehouse_id: i16,
+        district_id: i16,
+        customer_last_name: String,
+    },
+    Item {
+        item_id: i32,
+    },
+    Stock {
+        warehouse_id: i16,
+        item_id: i32,
+    },
+    Order {
+        warehouse_id: i16,
+        district_id: i16,
+        order_id: i32,
+    },
+    // The orders of a customer, when the customer has none
+    CustomerOrders {
+        warehouse_id: i16,
+        district_id: i16,
+        customer_id: i32,
+    },
+    DeliveryTicket {
+        ticket: u64,
+    },
+    TableSet {
+        table_set: u32,
+    },
+}
+
+impl fmt::Display for Entity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Entity::Warehouse { warehouse_id } => write!(f, "warehouse {}", warehouse_id),
+            Entity::District {
+                warehouse_id,
+                district_id,
+            } => write!(f, "district {} of warehouse {}", district_id, warehouse_id),
+            Entity::Customer {
+                warehouse_id,
+                district_id,
+                customer_id,
+            } => write!(
+                f,
+                "customer {} in warehouse {} district {}",
+                customer_id, warehouse_id, district_id
+            ),
+            Entity::CustomerLastName {
+                warehouse_id,
+                district_id,
+                customer_last_name,
+            } => write!(
+                f,
+                "customer with last name {} in warehouse {} district {}",
+                customer_last_name, warehouse_id, district_id
+            ),
+            Entity::Item { item_id } => write!(f, "item {}", item_id),
+            Entity::Stock {
+                warehouse_id,
+                item_id,
+            } => write!(f, "stock of item {} in warehouse {}", item_id, warehouse_id),
+            Entity::Order {
+                warehouse_id,
+                district_id,
+                order_id,
+            } => write!(
+                f,
+                "order {} in warehouse {} district {}",
+                order_id, warehouse_id, district_id
+            ),
+            Entity::CustomerOrders {
+                warehouse_id,
+                district_id,
+                customer_id,
+            } => write!(
+                f,
+                "order of customer {} in warehouse {} district {}",
+                customer_id, warehouse_id, district_id
+            ),
+            Entity::DeliveryTicket { ticket } => write!(f, "delivery ticket {}", ticket),
+            Entity::TableSet { table_set } => write!(f, "table set {}", table_set),
+        }
+    }
+}
+
+// A row a tpcc_* function (003_create_procedures.sql) did not find: the table it
+// raised no_data_found for and, when the caller cannot know it, the row's key
+pub struct MissingRow {
+    pub table: String,
+    pub key: Option<String>,
+    pub message: String,
+}
+
+impl MissingRow {
+    pub fn from_error(error: &sqlx::Error) -> Option<Self> {
+        let db_error = error
+            .as_database_error()?
+            .try_downcast_ref::<PgDatabaseError>()?;
+        if db_error.code() != NO_DATA_FOUND {
+            return None;
+        }
+        Some(MissingRow {
+            table: db_error.table()?.to_string(),
+            key: db_error.detail().map(str::to_string),
+            message: db_error.message().to_string(),
+        })
+    }
+
+    pub fn key<T: FromStr>(&self) -> Option<T> {
+        self.key.as_deref()?.parse().ok()
+    }
+}
+
+// Error type shared by all handlers. Rendered as a JSON body:
+// {"code": "item_not_found", "message": "...", "entity": {"item_id": 123}}
+#[derive(Debug)]
+pub enum ApiError {
+    // Malformed or invalid request (400, or the extractor's own status for rejections)
+    InvalidRequest {
+        status: StatusCode,
+        message: String,
+    },
+    // A referenced row does not exist
+    NotFound(Entity),
+    // The deferred delivery queue is full
+    QueueFull,
+    // The server is shutting down and accepts no new deferred work
+    ShuttingDown,
+    // Data that should exist is inconsistent
+    Internal(String),
+    // Database failure; `context` says what the handler was doing
+    Database {
+        context: &'static str,
+        source: sqlx::Error,
+    },
+    // Database or connection pool failure of the pipelined driver
+    #[cfg(feature = "pipelined-driver")]
+    Pipelined {
+        context: &'static str,
+        source: deadpool_postgres::PoolError,
+    },
+    // The last error of a transaction that was attempted more than once
+    Retried {
+        attempts: u32,
+        last: Box<ApiError>,
+    },
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    code: &'static str,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    entity: Option<Entity>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    attempts: Option<u32>,
+}
+
+impl ApiError {
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        ApiError::InvalidRequest {
+            status: StatusCode::BAD_REQUEST,
+            message: message.into(),
+        }
+    }
+
+    pub fn database(context: &'static str, source: sqlx::Error) -> Self {
+        ApiError::Database { context, source }
+    }
+
+    #[cfg(feature = "pipelined-driver")]
+    pub fn pipelined(
+        context: &'static str,
+        source: impl Into<deadpool_postgres::PoolError>,
+    ) -> Self {
+        ApiError::Pipelined {
+            context,
+            source: source.into(),
+        }
+    }
+
+    // A failed tpcc_* function call: not found when it raised no_data_found for a row
+    // `entity` identifies, a database error otherwise
+    pub fn procedure(
+        context: &'static str,
+        source: sqlx::Error,
+        entity: impl FnOnce(&MissingRow) -> Option<Entity>,
+    ) -> Self {
+        match MissingRow::from_error(&source).as_ref().and_then(entity) {
+            Some(entity) => ApiError::NotFound(entity),
+            None => ApiError::database(context, source),
+        }
+    }
+
+    // SQLSTATE reported by PostgreSQL, if this is a database error
+    pub fn sqlstate(&self) -> Option<String> {
+        match self {
+            ApiError::Database {
+                source: sqlx::Error::Database(db_error),
+                ..
+            } => db_error.code().map(|code| code.into_owned()),
+            #[cfg(feature = "pipelined-driver")]
+            ApiError::Pipelined {
+                source: deadpool_postgres::PoolError::Backend(error),
+                ..
+            } => error.code().map(|code| code.code().to_string()),
+            ApiError::Retried { last, .. } => last.sqlstate(),
+            _ => None,
+        }
+    }
+
+    // Serialization failures and deadlocks succeed when the transaction is re-run
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self.sqlstate().as_deref(),
+            Some(SERIALIZATION_FAILURE) | Some(DEADLOCK_DETECTED)
+        )
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::InvalidRequest { status, .. } => *status,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::QueueFull | ApiError::ShuttingDown => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::Database { .. } => match self.sqlstate().as_deref() {
+                Some(SERIALIZATION_FAILURE) | Some(DEADLOCK_DETECTED) => StatusCode::CONFLICT,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            },
+            #[cfg(feature = "pipelined-driver")]
+            ApiError::Pipelined { .. } => match self.sqlstate().as_deref() {
+                Some(SERIALIZATION_FAILURE) | Some(DEADLOCK_DETECTED) => StatusCode::CONFLICT,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            },
+            ApiError::Retried { last, .. } => last.status(),
+        }
+    }
+
+    // Machine-readable error code
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::InvalidRequest { .. } => "invalid_request",
+            ApiError::NotFound(entity) => match entity {
+                Entity::Warehouse { .. } => "warehouse_not_found",
+                Entity::District { .. } => "district_not_found",
+                Entity::Customer { .. } | Entity::CustomerLastName { .. } => "customer_not_found",
+                Entity::Item { .. } => "item_not_found",
+                Entity::Stock { .. } => "stock_not_found",
+                Entity::Order { .. } | Entity::CustomerOrders { .. } => "order_not_found",
+                Entity::DeliveryTicket { .. } => "delivery_ticket_not_found",
+                Entity::TableSet { .. } => "table_set_not_found",
+            },
+            ApiError::QueueFull => "delivery_queue_full",
+            ApiError::ShuttingDown => "shutting_down",
+            ApiError::Internal(_) => "internal_error",
+            ApiError::Database { .. } => match self.sqlstate().as_deref() {
+                Some(SERIALIZATION_FAILURE) => "serialization_failure",
+                Some(DEADLOCK_DETECTED) => "deadlock_detected",
+                _ => "database_error",
+            },
+            #[cfg(feature = "pipelined-driver")]
+            ApiError::Pipelined { .. } => match self.sqlstate().as_deref() {
+                Some(SERIALIZATION_FAILURE) => "serialization_failure",
+                Some(DEADLOCK_DETECTED) => "deadlock_detected",
+                _ => "database_error",
+            },
+            ApiError::Retried { last, .. } => last.code(),
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::InvalidRequest { message, .. } => write!(f, "{}", message),
+            ApiError::NotFound(entity) => write!(f, "{} not found", entity),
+            ApiError::QueueFull => write!(f, "deferred delivery queue is full"),
+            ApiError::ShuttingDown => write!(f, "server is shutting down"),
+            ApiError::Internal(message) => write!(f, "{}", message),
+            ApiError::Database { context, source } => {
+                write!(f, "database error {}: {}", context, source)
+            }
+            #[cfg(feature = "pipelined-driver")]
+            ApiError::Pipelined { context, source } => match source {
+                // Query errors come wrapped as Backend, which deadpool describes as a
+                // failure to create a connection
+                deadpool_postgres::PoolError::Backend(error) => {
+                    write!(f, "database error {}: {}", context, error)
+                }
+                _ => write!(f, "database error {}: {}", context, source),
+            },
+            ApiError::Retried { attempts, last } => {
+                write!(f, "{} (after {} attempts)", last, attempts)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+// Error code attached to error responses, for the metrics middleware
+#[derive(Clone, Copy, Debug)]
+pub struct ErrorCode(pub &'static str);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        if status.is_server_error() {
+            tracing::error!(code = self.code(), "{}", self);
+        } else if status == StatusCode::CONFLICT {
+            tracing::warn!(code = self.code(), "{}", self);
+        }
+
+        let code = self.code();
+        let (attempts, error) = match self {
+            ApiError::Retried { attempts, last } => (Some(attempts), *last),
+            error => (None, error),
+        };
+        let body = ErrorBody {
+            code,
+            // Don't leak database internals to the client
+            message: match &error {
+                ApiError::Database { context, .. } => format!("database error {}", context),
+                #[cfg(feature = "pipelined-driver")]
+                ApiError::Pipelined { context, .. } => format!("database error {}", context),
+                _ => error.to_string(),
+            },
+            entity: match error {
+                ApiError::NotFound(entity) => Some(entity),
+                _ => None,
+            },
+            attempts,
+        };
+
+        let mut response = (status, Json(body)).into_response();
+        response.extensions_mut().insert(ErrorCode(code));
+        if let Some(attempts) = attempts {
+            response
+                .headers_mut()
+                .insert(ATTEMPTS_HEADER, HeaderValue::from(attempts));
+        }
+        response
+    }
+}
+
+// Extractor rejections keep their status code but get a JSON body
+macro_rules! impl_from_rejection {
+    ($($rejection:ty),*) => {
+        $(impl From<$rejection> for ApiError {
+            fn from(rejection: $rejection) -> Self {
+                ApiError::InvalidRequest {
+                    status: rejection.status(),
+                    message: rejection.body_text(),
+                }
+            }
+        })*
+    };
+}
+
+impl_from_rejection!(JsonRejection, QueryRejection, PathRejection);
@@ -1,7 +1,10 @@
+pub mod consistency;
 pub mod customers;
 pub mod delivery;
 pub mod districts;
+pub mod health;
 pub mod items;
+pub mod metrics;
 pub mod new_order;
 pub mod order_status;
 pub mod orders;
@@ -9,10 +12,13 @@ pub mod payment;
 pub mod stock_level;
 pub mod warehouses;
 
+pub use consistency::*;
 pub use customers::*;
 pub use delivery::*;
 pub use districts::*;
+pub use health::*;
 pub use items::*;
+pub use metrics::*;
 pub use new_order::*;
 pub use order_status::*;
 pub use orders::*;
@@ -0,0 +1,20 @@
+use axum::Json;
+use serde::Deserialize;
+
+use crate::consistency::{self, ConsistencyReport};
+use crate::error::ApiError;
+use crate::extract::ApiQuery;
+use crate::table_set::TableSet;
+
+#[derive(Deserialize)]
+pub struct ConsistencyQuery {
+    // Every warehouse when omitted
+    pub warehouse_id: Option<i16>,
+}
+
+pub async fn check_consistency(
+    TableSet { pool, .. }: TableSet,
+    ApiQuery(query): ApiQuery<ConsistencyQuery>,
+) -> Result<Json<ConsistencyReport>, ApiError> {
+    Ok(Json(consistency::check(&pool, query.warehouse_id).await?))
+}
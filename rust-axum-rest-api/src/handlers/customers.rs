@@ -1,13 +1,12 @@
-use axum::{
-    extract::{Query, State},
-    http::StatusCode,
-    Json,
-};
+use axum::Json;
 use chrono::NaiveDateTime;
 use serde::Deserialize;
-use sqlx::{Pool, Postgres};
+use sqlx::{Executor, Postgres};
 
+use crate::error::ApiError;
+use crate::extract::ApiQuery;
 use crate::models::Customer;
+use crate::table_set::TableSet;
 
 #[derive(Deserialize)]
 pub struct CustomerSearchQuery {
@@ -18,9 +17,9 @@ pub struct CustomerSearchQuery {
 }
 
 pub async fn search_customers(
-    State(pool): State<Pool<Postgres>>,
-    Query(params): Query<CustomerSearchQuery>,
-) -> Result<Json<Vec<Customer>>, StatusCode> {
+    TableSet { pool, .. }: TableSet,
+    ApiQuery(params): ApiQuery<CustomerSearchQuery>,
+) -> Result<Json<Vec<Customer>>, ApiError> {
     let search_term = params.search.unwrap_or_default();
     let limit = params.limit.unwrap_or(10).min(50) as i64; // Default 10, max 50 for performance, cast to i64
 
@@ -91,10 +90,7 @@ pub async fn search_customers(
         .fetch_all(&pool)
         .await
     }
-    .map_err(|e| {
-        eprintln!("Database error searching customers: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    .map_err(|e| ApiError::database("searching customers", e))?;
 
     // Convert CustomerRow to Customer (handling DateTime conversion)
     let customers: Vec<Customer> = customer_rows
@@ -126,3 +122,73 @@ pub async fn search_customers(
 
     Ok(Json(customers))
 }
+
+// How a TPC-C transaction identifies its customer. Payment and Order-Status
+// select by last name 60% of the time and by customer ID otherwise.
+pub enum CustomerSelector {
+    Id(i32),
+    LastName(String),
+}
+
+impl CustomerSelector {
+    // Build a selector from the optional request fields; exactly one must be set
+    pub fn from_request(
+        customer_id: Option<i32>,
+        customer_last_name: Option<String>,
+    ) -> Option<Self> {
+        match (customer_id, customer_last_name) {
+            (Some(id), None) => Some(CustomerSelector::Id(id)),
+            (None, Some(last_name)) if !last_name.is_empty() => {
+                Some(CustomerSelector::LastName(last_name))
+            }
+            _ => None,
+        }
+    }
+}
+
+// Result of a by-last-name lookup: the chosen customer and how many matched
+pub struct CustomerNameMatch {
+    pub c_id: i32,
+    pub name_count: i64,
+}
+
+// TPC-C by-name selection: take all customers with the given last name in
+// (c_last, c_first) order and pick the one at position ceil(n / 2).
+// Uses idx_customer1 (c_w_id, c_d_id, c_last, c_first).
+#[tracing::instrument(
+    skip_all,
+    fields(c_w_id = warehouse_id, c_d_id = district_id, c_last = last_name)
+)]
+pub async fn find_customer_by_last_name<'e, E>(
+    executor: E,
+    warehouse_id: i16,
+    district_id: i16,
+    last_name: &str,
+) -> Result<Option<CustomerNameMatch>, sqlx::Error>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    let customer_ids = sqlx::query_scalar!(
+        r#"
+        SELECT c_id
+        FROM customer1
+        WHERE c_w_id = $1 AND c_d_id = $2 AND c_last = $3
+        ORDER BY c_first
+        "#,
+        warehouse_id,
+        district_id,
+        last_name
+    )
+    .fetch_all(executor)
+    .await?;
+
+    if customer_ids.is_empty() {
+        return Ok(None);
+    }
+
+    let name_count = customer_ids.len();
+    Ok(Some(CustomerNameMatch {
+        c_id: customer_ids[(name_count - 1) / 2],
+        name_count: name_count as i64,
+    }))
+}
@@ -1,28 +1,60 @@
-use axum::{extract::State, http::StatusCode, Json};
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
 use bigdecimal::{BigDecimal, FromPrimitive};
 use chrono::{NaiveDateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::{Pool, Postgres, Transaction};
 
+use crate::error::{ApiError, Entity, MissingRow};
+use crate::extract::{ApiJson, ApiPath, ApiQuery};
+
+use crate::delivery_queue::{DeferredDeliveryRecord, DeliveryQueue};
+use crate::table_set::TableSet;
+use crate::transaction::{
+    begin, run_transaction, Attempted, ExecutionMode, IsolationLevel, TransactionSettings,
+};
+
+// TPC-C: every warehouse has ten districts
+pub const DISTRICTS_PER_WAREHOUSE: i16 = 10;
+
 // Request Structure
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 pub struct DeliveryRequest {
     pub warehouse_id: i16,
-    pub district_id: i16,
+    // Deliver a single district; when omitted every district of the warehouse
+    // is processed, as in the TPC-C Delivery business transaction
+    pub district_id: Option<i16>,
+    // Carrier 1-10, chosen by the client
+    #[serde(alias = "o_carrier_id")]
+    pub carrier_id: Option<i16>,
+}
+
+#[derive(Deserialize)]
+pub struct DeliveryParams {
+    // Queue the delivery for a background worker instead of running it inline
+    pub deferred: Option<bool>,
 }
 
 // Response Structures
 #[derive(Serialize)]
 pub struct DeliveryResponse {
     pub warehouse_id: i16,
-    pub district_id: i16,
+    pub district_id: Option<i16>,
+    pub carrier_id: i16,
     pub delivery_date: NaiveDateTime,
     pub delivered_orders: Vec<DeliveredOrder>,
     pub total_orders_delivered: usize,
+    // Districts with no undelivered new-order
+    pub skipped_districts: Vec<i16>,
 }
 
 #[derive(Serialize, Clone)]
 pub struct DeliveredOrder {
+    pub district_id: i16,
     pub order_id: i32,
     pub customer_id: i32,
     pub carrier_id: i16,
@@ -48,59 +80,223 @@ struct OrderLineInfo {
 
 // Handler function
 pub async fn delivery(
-    State(pool): State<Pool<Postgres>>,
-    Json(request): Json<DeliveryRequest>,
-) -> Result<Json<DeliveryResponse>, StatusCode> {
+    TableSet {
+        number: table_set,
+        pool,
+        transactions,
+    }: TableSet,
+    State(delivery_queue): State<DeliveryQueue>,
+    State(settings): State<TransactionSettings>,
+    ApiQuery(params): ApiQuery<DeliveryParams>,
+    ApiJson(request): ApiJson<DeliveryRequest>,
+) -> Result<Response, ApiError> {
+    // Deferred mode: acknowledge with a ticket, a background worker does the work
+    if params.deferred.unwrap_or(false) {
+        let (carrier_id, district_ids) = validate_delivery(&request)?;
+        let record = delivery_queue.enqueue(
+            table_set,
+            pool,
+            request.warehouse_id,
+            carrier_id,
+            district_ids,
+        )?;
+        return Ok((StatusCode::ACCEPTED, Json(record)).into_response());
+    }
+
+    let attempted = transactions.delivery(&settings, &request).await?;
+    Ok(attempted.map(Json).into_response())
+}
+
+// Carrier and districts of a Delivery request
+pub(crate) fn validate_delivery(request: &DeliveryRequest) -> Result<(i16, Vec<i16>), ApiError> {
+    let carrier_id = request
+        .carrier_id
+        .ok_or_else(|| ApiError::bad_request("carrier_id is required"))?;
+
+    if !(1..=10).contains(&carrier_id) {
+        return Err(ApiError::bad_request("carrier_id must be between 1 and 10"));
+    }
+
+    let district_ids: Vec<i16> = match request.district_id {
+        Some(district_id) => vec![district_id],
+        None => (1..=DISTRICTS_PER_WAREHOUSE).collect(),
+    };
+    Ok((carrier_id, district_ids))
+}
+
+// Delivery run inline with validation and retries, without HTTP; the handler and the
+// in-process driver call this
+pub async fn execute_delivery(
+    pool: &Pool<Postgres>,
+    settings: &TransactionSettings,
+    request: &DeliveryRequest,
+) -> Result<Attempted<DeliveryResponse>, ApiError> {
+    let (carrier_id, district_ids) = validate_delivery(request)?;
+
+    let attempted = run_transaction(&settings.retry_policy, "delivery", || {
+        process_delivery(
+            pool,
+            settings.isolation_levels.delivery,
+            settings.execution_mode,
+            request.warehouse_id,
+            &district_ids,
+            carrier_id,
+        )
+    })
+    .await?;
+
+    Ok(attempted.map(|response| DeliveryResponse {
+        district_id: request.district_id,
+        ..response
+    }))
+}
+
+// Deliver the given districts of a warehouse in one transaction
+#[tracing::instrument(name = "delivery", skip_all, fields(w_id = warehouse_id, carrier_id))]
+async fn process_delivery(
+    pool: &Pool<Postgres>,
+    isolation: Option<IsolationLevel>,
+    mode: ExecutionMode,
+    warehouse_id: i16,
+    district_ids: &[i16],
+    carrier_id: i16,
+) -> Result<DeliveryResponse, ApiError> {
     let delivery_date = Utc::now().naive_utc();
-    let carrier_id: i16 = 1; // TPC-C uses random carrier_id 1-10, we'll use 1 for simplicity
 
     // Start transaction - TPC-C Delivery processes multiple orders atomically
-    let mut tx = pool.begin().await.map_err(|e| {
-        eprintln!("Failed to start transaction: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    let mut tx = begin(pool, isolation).await?;
 
-    // TPC-C Delivery processes one order per district, but for simplicity
-    // we'll process orders for the specified district only
+    // TPC-C Delivery processes the oldest undelivered order of each district
     let mut delivered_orders = Vec::new();
+    let mut skipped_districts = Vec::new();
 
-    // Step 1: Find the oldest undelivered order for this district
-    if let Some(delivered_order) = process_district_delivery(
+    let outcomes = deliver_districts(
         &mut tx,
-        request.warehouse_id,
-        request.district_id,
+        mode,
+        warehouse_id,
+        district_ids,
         carrier_id,
         delivery_date,
     )
-    .await?
-    {
-        delivered_orders.push(delivered_order);
+    .await?;
+    for (&district_id, outcome) in district_ids.iter().zip(outcomes) {
+        match outcome {
+            Some(delivered_order) => delivered_orders.push(delivered_order),
+            None => skipped_districts.push(district_id),
+        }
     }
 
     // Commit transaction
-    tx.commit().await.map_err(|e| {
-        eprintln!("Failed to commit transaction: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    tx.commit()
+        .await
+        .map_err(|e| ApiError::database("committing transaction", e))?;
 
-    Ok(Json(DeliveryResponse {
-        warehouse_id: request.warehouse_id,
-        district_id: request.district_id,
+    Ok(DeliveryResponse {
+        warehouse_id,
+        district_id: None,
+        carrier_id,
         delivery_date,
-        delivered_orders: delivered_orders.clone(),
         total_orders_delivered: delivered_orders.len(),
-    }))
+        delivered_orders,
+        skipped_districts,
+    })
+}
+
+// Look up the result log entry of a deferred delivery
+pub async fn deferred_delivery_status(
+    State(delivery_queue): State<DeliveryQueue>,
+    ApiPath(ticket): ApiPath<u64>,
+) -> Result<Json<DeferredDeliveryRecord>, ApiError> {
+    delivery_queue
+        .record(ticket)
+        .map(Json)
+        .ok_or(ApiError::NotFound(Entity::DeliveryTicket { ticket }))
+}
+
+// Deliver the given districts within `tx`, one district at a time or in one call
+// to tpcc_delivery. One entry per district, None when it had nothing to deliver.
+pub(crate) async fn deliver_districts(
+    tx: &mut Transaction<'_, Postgres>,
+    mode: ExecutionMode,
+    warehouse_id: i16,
+    district_ids: &[i16],
+    carrier_id: i16,
+    delivery_date: NaiveDateTime,
+) -> Result<Vec<Option<DeliveredOrder>>, ApiError> {
+    if mode == ExecutionMode::StoredProcedures {
+        return call_delivery(tx, warehouse_id, district_ids, carrier_id, delivery_date).await;
+    }
+
+    let mut outcomes = Vec::with_capacity(district_ids.len());
+    for &district_id in district_ids {
+        outcomes.push(
+            process_district_delivery(tx, warehouse_id, district_id, carrier_id, delivery_date)
+                .await?,
+        );
+    }
+    Ok(outcomes)
+}
+
+#[tracing::instrument(name = "tpcc_delivery", skip_all, fields(w_id = warehouse_id, carrier_id))]
+async fn call_delivery(
+    tx: &mut Transaction<'_, Postgres>,
+    warehouse_id: i16,
+    district_ids: &[i16],
+    carrier_id: i16,
+    delivery_date: NaiveDateTime,
+) -> Result<Vec<Option<DeliveredOrder>>, ApiError> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT district_id AS "district_id!", order_id, customer_id,
+               line_amounts AS "line_amounts: Vec<Option<BigDecimal>>"
+        FROM tpcc_delivery($1, $2, $3, $4)
+        "#,
+        warehouse_id,
+        district_ids,
+        carrier_id,
+        delivery_date
+    )
+    .fetch_all(&mut **tx)
+    .await
+    .map_err(|e| match MissingRow::from_error(&e) {
+        // The new order's order row is missing
+        Some(row) if row.table == "orders" => ApiError::Internal(row.message),
+        _ => ApiError::database("calling tpcc_delivery", e),
+    })?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            // Counted and summed like the inline path's order lines
+            let line_amounts = row.line_amounts.unwrap_or_default();
+            let mut total_amount = BigDecimal::from_f64(0.0).unwrap();
+            for amount in line_amounts.iter().flatten() {
+                total_amount += amount;
+            }
+            Some(DeliveredOrder {
+                district_id: row.district_id,
+                order_id: row.order_id?,
+                customer_id: row.customer_id.unwrap_or(0),
+                carrier_id,
+                order_line_count: line_amounts.len(),
+                total_amount,
+            })
+        })
+        .collect())
 }
 
 // Process delivery for a single district
-async fn process_district_delivery(
+#[tracing::instrument(skip_all, fields(w_id = warehouse_id, d_id = district_id, o_id = tracing::field::Empty, c_id = tracing::field::Empty))]
+pub(crate) async fn process_district_delivery(
     tx: &mut Transaction<'_, Postgres>,
     warehouse_id: i16,
     district_id: i16,
     carrier_id: i16,
     delivery_date: NaiveDateTime,
-) -> Result<Option<DeliveredOrder>, StatusCode> {
+) -> Result<Option<DeliveredOrder>, ApiError> {
     // Step 1: Find the oldest undelivered order (smallest order ID in new_orders)
+    // and lock it; a concurrent Delivery that picked the same order waits, then
+    // finds it deleted and skips the district instead of delivering it twice
     let new_order_row = sqlx::query!(
         r#"
         SELECT no_o_id
@@ -108,21 +304,20 @@ async fn process_district_delivery(
         WHERE no_w_id = $1 AND no_d_id = $2
         ORDER BY no_o_id ASC
         LIMIT 1
+        FOR UPDATE
         "#,
         warehouse_id,
         district_id
     )
     .fetch_optional(&mut **tx)
     .await
-    .map_err(|e| {
-        eprintln!("Database error finding new order: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    .map_err(|e| ApiError::database("finding new order", e))?;
 
     let order_id = match new_order_row {
         Some(row) => row.no_o_id,
         None => return Ok(None), // No undelivered orders for this district
     };
+    tracing::Span::current().record("o_id", order_id);
 
     // Step 2: Get order information (customer_id)
     let order_row = sqlx::query!(
@@ -133,15 +328,19 @@ async fn process_district_delivery(
     )
     .fetch_optional(&mut **tx)
     .await
-    .map_err(|e| {
-        eprintln!("Database error fetching order: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    .map_err(|e| ApiError::database("fetching order", e))?;
 
     let customer_id = match order_row {
         Some(row) => row.o_c_id.unwrap_or(0),
-        None => return Err(StatusCode::INTERNAL_SERVER_ERROR), // Order should exist
+        None => {
+            // Order should exist
+            return Err(ApiError::Internal(format!(
+                "new order {} in warehouse {} district {} has no order row",
+                order_id, warehouse_id, district_id
+            )));
+        }
     };
+    tracing::Span::current().record("c_id", customer_id);
 
     // Step 3: Update the order with carrier_id
     sqlx::query!(
@@ -153,10 +352,7 @@ async fn process_district_delivery(
     )
     .execute(&mut **tx)
     .await
-    .map_err(|e| {
-        eprintln!("Database error updating order: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    .map_err(|e| ApiError::database("updating order", e))?;
 
     // Step 4: Update all order lines with delivery date and get total amount
     let order_lines_rows = sqlx::query!(
@@ -171,10 +367,7 @@ async fn process_district_delivery(
     )
     .fetch_all(&mut **tx)
     .await
-    .map_err(|e| {
-        eprintln!("Database error fetching order lines: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    .map_err(|e| ApiError::database("fetching order lines", e))?;
 
     // Calculate total amount and count order lines
     let mut total_amount = BigDecimal::from_f64(0.0).unwrap();
@@ -200,10 +393,7 @@ async fn process_district_delivery(
     )
     .execute(&mut **tx)
     .await
-    .map_err(|e| {
-        eprintln!("Database error updating order lines: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    .map_err(|e| ApiError::database("updating order lines", e))?;
 
     // Step 5: Update customer balance and delivery count
     sqlx::query!(
@@ -219,10 +409,7 @@ async fn process_district_delivery(
     )
     .execute(&mut **tx)
     .await
-    .map_err(|e| {
-        eprintln!("Database error updating customer: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    .map_err(|e| ApiError::database("updating customer", e))?;
 
     // Step 6: Remove the order from new_orders (it's now delivered)
     sqlx::query!(
@@ -233,12 +420,10 @@ async fn process_district_delivery(
     )
     .execute(&mut **tx)
     .await
-    .map_err(|e| {
-        eprintln!("Database error removing from new_orders: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    .map_err(|e| ApiError::database("removing from new_orders", e))?;
 
     Ok(Some(DeliveredOrder {
+        district_id,
         order_id,
         customer_id,
         carrier_id,
@@ -1,12 +1,10 @@
-use axum::{
-    extract::{Query, State},
-    http::StatusCode,
-    Json,
-};
+use axum::Json;
 use serde::Deserialize;
-use sqlx::{Pool, Postgres};
 
+use crate::error::ApiError;
+use crate::extract::ApiQuery;
 use crate::models::District;
+use crate::table_set::TableSet;
 
 #[derive(Deserialize)]
 pub struct DistrictsQuery {
@@ -14,9 +12,9 @@ pub struct DistrictsQuery {
 }
 
 pub async fn get_districts(
-    State(pool): State<Pool<Postgres>>,
-    Query(params): Query<DistrictsQuery>,
-) -> Result<Json<Vec<District>>, StatusCode> {
+    TableSet { pool, .. }: TableSet,
+    ApiQuery(params): ApiQuery<DistrictsQuery>,
+) -> Result<Json<Vec<District>>, ApiError> {
     let districts = sqlx::query_as!(
         District,
         "SELECT d_id, d_w_id, d_name, d_street_1, d_street_2, d_city, d_state, d_zip, d_tax, d_ytd, d_next_o_id FROM district1 WHERE d_w_id = $1 ORDER BY d_id ASC",
@@ -24,10 +22,7 @@ pub async fn get_districts(
     )
     .fetch_all(&pool)
     .await
-    .map_err(|e| {
-        eprintln!("Database error fetching districts: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    .map_err(|e| ApiError::database("fetching districts", e))?;
 
     Ok(Json(districts))
 }
@@ -0,0 +1,30 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use sqlx::{Pool, Postgres};
+
+use crate::health;
+use crate::table_set::TableSets;
+
+// GET /healthz: the process is up and serving; never touches the database
+pub async fn healthz() -> &'static str {
+    "ok"
+}
+
+// GET /readyz: 200 once the database is reachable and every table set has its
+// tables, indexes and data, 503 with the same report otherwise
+pub async fn readyz(
+    State(pool): State<Pool<Postgres>>,
+    State(table_sets): State<TableSets>,
+) -> Response {
+    let report = health::check(&pool, &table_sets).await;
+    let status = if report.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(report)).into_response()
+}
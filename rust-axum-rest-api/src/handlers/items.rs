@@ -1,12 +1,10 @@
-use axum::{
-    extract::{Query, State},
-    http::StatusCode,
-    Json,
-};
+use axum::Json;
 use serde::Deserialize;
-use sqlx::{Pool, Postgres};
 
+use crate::error::{ApiError, Entity};
+use crate::extract::ApiQuery;
 use crate::models::Item;
+use crate::table_set::TableSet;
 
 #[derive(Deserialize)]
 pub struct ItemSearchQuery {
@@ -16,9 +14,9 @@ pub struct ItemSearchQuery {
 }
 
 pub async fn search_items(
-    State(pool): State<Pool<Postgres>>,
-    Query(params): Query<ItemSearchQuery>,
-) -> Result<Json<Vec<Item>>, StatusCode> {
+    TableSet { pool, .. }: TableSet,
+    ApiQuery(params): ApiQuery<ItemSearchQuery>,
+) -> Result<Json<Vec<Item>>, ApiError> {
     let search_term = params.search.unwrap_or_default();
     let limit = params.limit.unwrap_or(20).min(100) as i64; // Default 20, max 100 for performance
 
@@ -65,10 +63,7 @@ pub async fn search_items(
         .fetch_all(&pool)
         .await
     }
-    .map_err(|e| {
-        eprintln!("Database error searching items: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    .map_err(|e| ApiError::database("searching items", e))?;
 
     // Convert ItemRow to Item
     let items: Vec<Item> = item_rows
@@ -92,9 +87,9 @@ pub struct StockQuery {
 }
 
 pub async fn get_stock_info(
-    State(pool): State<Pool<Postgres>>,
-    Query(params): Query<StockQuery>,
-) -> Result<Json<StockInfo>, StatusCode> {
+    TableSet { pool, .. }: TableSet,
+    ApiQuery(params): ApiQuery<StockQuery>,
+) -> Result<Json<StockInfo>, ApiError> {
     let stock = sqlx::query!(
         r#"
         SELECT s_quantity, s_ytd, s_order_cnt, s_remote_cnt, s_data
@@ -106,10 +101,7 @@ pub async fn get_stock_info(
     )
     .fetch_optional(&pool)
     .await
-    .map_err(|e| {
-        eprintln!("Database error getting stock info: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    .map_err(|e| ApiError::database("getting stock info", e))?;
 
     match stock {
         Some(record) => Ok(Json(StockInfo {
@@ -119,7 +111,10 @@ pub async fn get_stock_info(
             s_remote_cnt: record.s_remote_cnt.unwrap_or(0),
             s_data: record.s_data,
         })),
-        None => Err(StatusCode::NOT_FOUND),
+        None => Err(ApiError::NotFound(Entity::Stock {
+            warehouse_id: params.warehouse_id,
+            item_id: params.item_id,
+        })),
     }
 }
 
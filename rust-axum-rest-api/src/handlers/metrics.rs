@@ -0,0 +1,17 @@
+use axum::{
+    extract::State,
+    http::header::CONTENT_TYPE,
+    response::{IntoResponse, Response},
+};
+
+use crate::metrics::metrics;
+use crate::table_set::TableSets;
+
+// GET /metrics in the Prometheus text format
+pub async fn get_metrics(State(table_sets): State<TableSets>) -> Response {
+    (
+        [(CONTENT_TYPE, prometheus::TEXT_FORMAT)],
+        metrics().encode(&table_sets),
+    )
+        .into_response()
+}
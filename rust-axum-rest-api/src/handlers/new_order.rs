@@ -1,11 +1,22 @@
-use axum::{extract::State, http::StatusCode, Json};
+use axum::{extract::State, Json};
 use bigdecimal::{BigDecimal, FromPrimitive};
 use chrono::{NaiveDateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::{Pool, Postgres, Transaction};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::error::{ApiError, Entity};
+use crate::extract::ApiJson;
+use crate::table_set::TableSet;
+use crate::transaction::{
+    begin, run_transaction, Attempted, ExecutionMode, IsolationLevel, NewOrderPath,
+    TransactionSettings,
+};
 
 // Request Structure
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 pub struct NewOrderRequest {
     pub warehouse_id: i16,
     pub district_id: i16,
@@ -13,7 +24,7 @@ pub struct NewOrderRequest {
     pub order_lines: Vec<OrderLineRequest>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 pub struct OrderLineRequest {
     pub item_id: i32,
     pub supply_warehouse_id: i16,
@@ -32,6 +43,28 @@ pub struct NewOrderResponse {
     pub order_lines: Vec<OrderLineSummary>,
 }
 
+// TPC-C status message for a New-Order rolled back because of an unused item
+pub const ITEM_NOT_VALID: &str = "Item number is not valid";
+
+// Either the committed order, or the expected rollback when an order line
+// names an unused item ID (TPC-C 2.4.2.3, 1% of New-Order transactions)
+#[derive(Serialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum NewOrderOutcome {
+    Committed(NewOrderResponse),
+    RolledBack(NewOrderRollback),
+}
+
+#[derive(Serialize)]
+pub struct NewOrderRollback {
+    pub warehouse_id: i16,
+    pub district_id: i16,
+    pub order_id: i32,
+    pub customer: CustomerSummary,
+    pub item_id: i32,
+    pub message: &'static str,
+}
+
 #[derive(Serialize)]
 pub struct CustomerSummary {
     pub customer_id: i32,
@@ -84,21 +117,119 @@ struct StockData {
     s_data: String,
 }
 
+// Primary key of the order being entered
+#[derive(Clone, Copy)]
+struct OrderKey {
+    warehouse_id: i16,
+    district_id: i16,
+    order_id: i32,
+}
+
+// The order lines as entered, or the first line's unused item ID
+enum EnteredLines {
+    Entered {
+        summaries: Vec<OrderLineSummary>,
+        total_amount: BigDecimal,
+    },
+    UnusedItem(i32),
+}
+
+// New-Order outcome counters, so expected rollbacks are not mistaken for failures
+#[derive(Clone, Default)]
+pub struct NewOrderStats {
+    counters: Arc<NewOrderCounters>,
+}
+
+#[derive(Default)]
+struct NewOrderCounters {
+    committed: AtomicU64,
+    rolled_back: AtomicU64,
+    failed: AtomicU64,
+}
+
+#[derive(Serialize)]
+pub struct NewOrderStatsResponse {
+    pub committed: u64,
+    pub rolled_back: u64,
+    pub failed: u64,
+}
+
+impl NewOrderStats {
+    fn record(&self, result: &Result<Attempted<NewOrderOutcome>, ApiError>) {
+        let counter = match result.as_ref().map(|attempted| &attempted.value) {
+            Ok(NewOrderOutcome::Committed(_)) => &self.counters.committed,
+            Ok(NewOrderOutcome::RolledBack(_)) => &self.counters.rolled_back,
+            Err(_) => &self.counters.failed,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> NewOrderStatsResponse {
+        NewOrderStatsResponse {
+            committed: self.counters.committed.load(Ordering::Relaxed),
+            rolled_back: self.counters.rolled_back.load(Ordering::Relaxed),
+            failed: self.counters.failed.load(Ordering::Relaxed),
+        }
+    }
+}
+
 // Handler function
 pub async fn new_order(
-    State(pool): State<Pool<Postgres>>,
-    Json(request): Json<NewOrderRequest>,
-) -> Result<Json<NewOrderResponse>, StatusCode> {
-    // Start transaction - TPC-C New Order is a complex multi-table transaction
-    let mut tx = pool.begin().await.map_err(|e| {
-        eprintln!("Failed to start transaction: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    TableSet { transactions, .. }: TableSet,
+    State(stats): State<NewOrderStats>,
+    State(settings): State<TransactionSettings>,
+    ApiJson(request): ApiJson<NewOrderRequest>,
+) -> Result<Attempted<Json<NewOrderOutcome>>, ApiError> {
+    let result = transactions.new_order(&settings, &request).await;
+    stats.record(&result);
+    result.map(|attempted| attempted.map(Json))
+}
 
-    // Validate request
-    if request.order_lines.is_empty() || request.order_lines.len() > 15 {
-        return Err(StatusCode::BAD_REQUEST);
-    }
+// New-Order with retries, without HTTP; the handler and the in-process driver call this
+pub async fn execute_new_order(
+    pool: &Pool<Postgres>,
+    settings: &TransactionSettings,
+    request: &NewOrderRequest,
+) -> Result<Attempted<NewOrderOutcome>, ApiError> {
+    validate_order_lines(request)?;
+
+    let isolation = settings.isolation_levels.new_order;
+    run_transaction(&settings.retry_policy, "new-order", || async move {
+        match settings.execution_mode {
+            ExecutionMode::Inline => {
+                process_new_order(pool, isolation, settings.new_order_path, request).await
+            }
+            ExecutionMode::StoredProcedures => call_new_order(pool, isolation, request).await,
+        }
+    })
+    .await
+}
+
+// GET /new-order/stats
+pub async fn new_order_stats(State(stats): State<NewOrderStats>) -> Json<NewOrderStatsResponse> {
+    Json(stats.snapshot())
+}
+
+#[tracing::instrument(
+    name = "new_order",
+    skip_all,
+    fields(
+        w_id = request.warehouse_id,
+        d_id = request.district_id,
+        c_id = request.customer_id,
+        o_id = tracing::field::Empty,
+        ol_cnt = request.order_lines.len(),
+        path = %path
+    )
+)]
+async fn process_new_order(
+    pool: &Pool<Postgres>,
+    isolation: Option<IsolationLevel>,
+    path: NewOrderPath,
+    request: &NewOrderRequest,
+) -> Result<NewOrderOutcome, ApiError> {
+    // Start transaction - TPC-C New Order is a complex multi-table transaction
+    let mut tx = begin(pool, isolation).await?;
 
     let entry_date = Utc::now().naive_utc();
 
@@ -108,6 +239,7 @@ pub async fn new_order(
     // Step 2: Get district data and update next order ID
     let (district, order_id) =
         get_and_update_district(&mut tx, request.warehouse_id, request.district_id).await?;
+    tracing::Span::current().record("o_id", order_id);
 
     // Step 3: Get customer data
     let customer = get_customer_data(
@@ -118,62 +250,336 @@ pub async fn new_order(
     )
     .await?;
 
-    // Step 4: Insert new order record
+    // Step 4: Insert new order record. The batched path knows the final line
+    // count and all-local flag up front; the per-line path sets them at the end.
+    let all_local = request
+        .order_lines
+        .iter()
+        .all(|line| line.supply_warehouse_id == request.warehouse_id);
+    // A stock row listed twice must be updated twice, which a single UPDATE cannot do
+    let path = match path {
+        NewOrderPath::Batched if has_duplicate_stock(&request.order_lines) => NewOrderPath::PerLine,
+        path => path,
+    };
+    let order = OrderKey {
+        warehouse_id: request.warehouse_id,
+        district_id: request.district_id,
+        order_id,
+    };
     insert_new_order(
         &mut tx,
-        request.warehouse_id,
-        request.district_id,
-        order_id,
+        order,
         request.customer_id,
         entry_date,
         &request.order_lines,
+        (path == NewOrderPath::Batched).then_some(all_local),
     )
     .await?;
 
-    // Step 5: Process each order line
-    let mut order_line_summaries = Vec::new();
-    let mut total_amount = BigDecimal::from_f64(0.0).unwrap();
-    let mut all_local = true;
-
-    for (line_number, order_line) in request.order_lines.iter().enumerate() {
-        // Check if this is a remote order line
-        if order_line.supply_warehouse_id != request.warehouse_id {
-            all_local = false;
+    // Steps 5 and 6: Lock the stock rows, then enter the order lines
+    let lines = match path {
+        NewOrderPath::PerLine => enter_lines_per_line(&mut tx, order, &request.order_lines).await?,
+        NewOrderPath::Batched => enter_lines_batched(&mut tx, order, &request.order_lines).await?,
+    };
+    let (order_line_summaries, total_amount) = match lines {
+        EnteredLines::Entered {
+            summaries,
+            total_amount,
+        } => (summaries, total_amount),
+        // An unused item ID rolls back the whole order
+        EnteredLines::UnusedItem(item_id) => {
+            tx.rollback()
+                .await
+                .map_err(|e| ApiError::database("rolling back transaction", e))?;
+            tracing::debug!(
+                "New-Order {} for warehouse {} district {} rolled back: unused item {}",
+                order_id,
+                request.warehouse_id,
+                request.district_id,
+                item_id
+            );
+            return Ok(NewOrderOutcome::RolledBack(NewOrderRollback {
+                warehouse_id: request.warehouse_id,
+                district_id: request.district_id,
+                order_id,
+                customer: CustomerSummary {
+                    customer_id: request.customer_id,
+                    last_name: customer.c_last,
+                    credit: customer.c_credit,
+                    discount: customer.c_discount,
+                },
+                item_id,
+                message: ITEM_NOT_VALID,
+            }));
         }
+    };
 
-        // Get item data
-        let item = get_item_data(&mut tx, order_line.item_id).await?;
+    let total_amount = order_total(
+        total_amount,
+        &warehouse.w_tax,
+        &district.d_tax,
+        &customer.c_discount,
+    );
 
-        // Get and update stock data
-        let stock = get_and_update_stock(
+    // Update order with final details
+    if path == NewOrderPath::PerLine {
+        update_order_totals(
             &mut tx,
+            request.warehouse_id,
+            request.district_id,
+            order_id,
+            request.order_lines.len() as i16,
+            all_local,
+        )
+        .await?;
+    }
+
+    // Commit transaction
+    tx.commit()
+        .await
+        .map_err(|e| ApiError::database("committing transaction", e))?;
+
+    Ok(NewOrderOutcome::Committed(NewOrderResponse {
+        order_id,
+        customer: CustomerSummary {
+            customer_id: request.customer_id,
+            last_name: customer.c_last,
+            credit: customer.c_credit,
+            discount: customer.c_discount,
+        },
+        warehouse_tax: warehouse.w_tax,
+        district_tax: district.d_tax,
+        order_entry_date: entry_date,
+        total_amount,
+        order_lines: order_line_summaries,
+    }))
+}
+
+pub(crate) fn validate_order_lines(request: &NewOrderRequest) -> Result<(), ApiError> {
+    if request.order_lines.is_empty() || request.order_lines.len() > 15 {
+        return Err(ApiError::bad_request(
+            "an order must have between 1 and 15 order lines",
+        ));
+    }
+    Ok(())
+}
+
+// New-Order as one call to tpcc_new_order, which runs the same statements as the
+// per-line path
+#[tracing::instrument(
+    name = "tpcc_new_order",
+    skip_all,
+    fields(
+        w_id = request.warehouse_id,
+        d_id = request.district_id,
+        c_id = request.customer_id,
+        o_id = tracing::field::Empty,
+        ol_cnt = request.order_lines.len()
+    )
+)]
+async fn call_new_order(
+    pool: &Pool<Postgres>,
+    isolation: Option<IsolationLevel>,
+    request: &NewOrderRequest,
+) -> Result<NewOrderOutcome, ApiError> {
+    let mut tx = begin(pool, isolation).await?;
+
+    let entry_date = Utc::now().naive_utc();
+    let item_ids: Vec<i32> = request
+        .order_lines
+        .iter()
+        .map(|line| line.item_id)
+        .collect();
+    let supply_warehouse_ids: Vec<i16> = request
+        .order_lines
+        .iter()
+        .map(|line| line.supply_warehouse_id)
+        .collect();
+    let quantities: Vec<i16> = request
+        .order_lines
+        .iter()
+        .map(|line| line.quantity)
+        .collect();
+
+    let row = sqlx::query!(
+        r#"
+        SELECT order_id AS "order_id!", w_tax AS "w_tax!", d_tax AS "d_tax!",
+               c_last AS "c_last!", c_credit AS "c_credit!", c_discount AS "c_discount!",
+               unused_item_id,
+               item_names AS "item_names!", item_prices AS "item_prices!",
+               stock_quantities AS "stock_quantities!", brand_generic AS "brand_generic!"
+        FROM tpcc_new_order($1, $2, $3, $4, $5, $6, $7)
+        "#,
+        request.warehouse_id,
+        request.district_id,
+        request.customer_id,
+        entry_date,
+        &item_ids,
+        &supply_warehouse_ids,
+        &quantities
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| {
+        ApiError::procedure("calling tpcc_new_order", e, |row| {
+            match row.table.as_str() {
+                "warehouse" => Some(Entity::Warehouse {
+                    warehouse_id: request.warehouse_id,
+                }),
+                "district" => Some(Entity::District {
+                    warehouse_id: request.warehouse_id,
+                    district_id: request.district_id,
+                }),
+                "customer" => Some(Entity::Customer {
+                    warehouse_id: request.warehouse_id,
+                    district_id: request.district_id,
+                    customer_id: request.customer_id,
+                }),
+                // Keyed by line number
+                "stock" => {
+                    let line = request
+                        .order_lines
+                        .get(row.key::<usize>()?.checked_sub(1)?)?;
+                    Some(Entity::Stock {
+                        warehouse_id: line.supply_warehouse_id,
+                        item_id: line.item_id,
+                    })
+                }
+                _ => None,
+            }
+        })
+    })?;
+    tracing::Span::current().record("o_id", row.order_id);
+
+    let customer = CustomerSummary {
+        customer_id: request.customer_id,
+        last_name: row.c_last,
+        credit: row.c_credit,
+        discount: row.c_discount,
+    };
+
+    // An unused item ID rolls back the whole order
+    if let Some(item_id) = row.unused_item_id {
+        tx.rollback()
+            .await
+            .map_err(|e| ApiError::database("rolling back transaction", e))?;
+        tracing::debug!(
+            "New-Order {} for warehouse {} district {} rolled back: unused item {}",
+            row.order_id,
+            request.warehouse_id,
+            request.district_id,
+            item_id
+        );
+        return Ok(NewOrderOutcome::RolledBack(NewOrderRollback {
+            warehouse_id: request.warehouse_id,
+            district_id: request.district_id,
+            order_id: row.order_id,
+            customer,
+            item_id,
+            message: ITEM_NOT_VALID,
+        }));
+    }
+
+    tx.commit()
+        .await
+        .map_err(|e| ApiError::database("committing transaction", e))?;
+
+    let order_lines: Vec<OrderLineSummary> = request
+        .order_lines
+        .iter()
+        .enumerate()
+        .map(|(n, line)| OrderLineSummary {
+            item_id: line.item_id,
+            supply_warehouse_id: line.supply_warehouse_id,
+            quantity: line.quantity,
+            item_name: row.item_names[n].clone(),
+            item_price: row.item_prices[n].clone(),
+            stock_quantity: row.stock_quantities[n],
+            brand_generic: row.brand_generic[n].clone(),
+            line_amount: &row.item_prices[n] * BigDecimal::from(line.quantity),
+        })
+        .collect();
+    let total_amount = order_total(
+        order_lines.iter().map(|line| &line.line_amount).sum(),
+        &row.w_tax,
+        &row.d_tax,
+        &customer.discount,
+    );
+
+    Ok(NewOrderOutcome::Committed(NewOrderResponse {
+        order_id: row.order_id,
+        customer,
+        warehouse_tax: row.w_tax,
+        district_tax: row.d_tax,
+        order_entry_date: entry_date,
+        total_amount,
+        order_lines,
+    }))
+}
+
+// The sum of the line amounts with taxes added and the customer's discount taken off
+pub(crate) fn order_total(
+    lines_total: BigDecimal,
+    w_tax: &BigDecimal,
+    d_tax: &BigDecimal,
+    c_discount: &BigDecimal,
+) -> BigDecimal {
+    let tax_amount = (w_tax + d_tax) * &lines_total;
+    let discount_amount = c_discount * &lines_total;
+    lines_total + tax_amount - discount_amount
+}
+
+fn has_duplicate_stock(order_lines: &[OrderLineRequest]) -> bool {
+    let mut seen = HashSet::new();
+    !order_lines
+        .iter()
+        .all(|line| seen.insert((line.supply_warehouse_id, line.item_id)))
+}
+
+// "B" when both the item name and the stock data contain "ORIGINAL"
+pub(crate) fn brand_generic(i_name: &str, s_data: &str) -> String {
+    if i_name.contains("ORIGINAL") && s_data.contains("ORIGINAL") {
+        "B".to_string()
+    } else {
+        "G".to_string()
+    }
+}
+
+// Item lookup, stock update and order line insert for each line in turn
+async fn enter_lines_per_line(
+    tx: &mut Transaction<'_, Postgres>,
+    order: OrderKey,
+    order_lines: &[OrderLineRequest],
+) -> Result<EnteredLines, ApiError> {
+    lock_stock(tx, order_lines).await?;
+
+    let mut summaries = Vec::new();
+    let mut total_amount = BigDecimal::from_f64(0.0).unwrap();
+    for (line_number, order_line) in order_lines.iter().enumerate() {
+        let Some(item) = get_item_data(tx, order_line.item_id).await? else {
+            return Ok(EnteredLines::UnusedItem(order_line.item_id));
+        };
+
+        let stock = get_and_update_stock(
+            tx,
             order_line.item_id,
             order_line.supply_warehouse_id,
             order_line.quantity,
-            request.district_id,
-            order_line.supply_warehouse_id != request.warehouse_id,
+            order.district_id,
+            order_line.supply_warehouse_id != order.warehouse_id,
         )
         .await?;
 
-        // Calculate line amount
         let line_amount = &item.i_price * BigDecimal::from(order_line.quantity);
         total_amount += &line_amount;
+        let brand_generic = brand_generic(&item.i_name, &stock.s_data);
 
-        // Determine brand/generic indicator
-        let brand_generic = if item.i_name.contains("ORIGINAL") && stock.s_data.contains("ORIGINAL")
-        {
-            "B".to_string()
-        } else {
-            "G".to_string()
-        };
-
-        // Insert order line
         insert_order_line(
-            &mut tx,
+            tx,
             OrderLineParams {
-                warehouse_id: request.warehouse_id,
-                district_id: request.district_id,
-                order_id,
+                warehouse_id: order.warehouse_id,
+                district_id: order.district_id,
+                order_id: order.order_id,
                 line_number: (line_number + 1) as i16,
                 item_id: order_line.item_id,
                 supply_warehouse_id: order_line.supply_warehouse_id,
@@ -184,7 +590,7 @@ pub async fn new_order(
         )
         .await?;
 
-        order_line_summaries.push(OrderLineSummary {
+        summaries.push(OrderLineSummary {
             item_id: order_line.item_id,
             supply_warehouse_id: order_line.supply_warehouse_id,
             quantity: order_line.quantity,
@@ -196,56 +602,75 @@ pub async fn new_order(
         });
     }
 
-    // Apply taxes and discount
-    let tax_rate = (&warehouse.w_tax + &district.d_tax) * &total_amount;
-    let discount_amount = &customer.c_discount * &total_amount;
-    total_amount = total_amount + tax_rate - discount_amount;
+    Ok(EnteredLines::Entered {
+        summaries,
+        total_amount,
+    })
+}
 
-    // Update order with final details
-    update_order_totals(
-        &mut tx,
-        request.warehouse_id,
-        request.district_id,
-        order_id,
-        request.order_lines.len() as i16,
-        all_local,
-    )
-    .await?;
+// One statement per table for all lines: the items by ID, the stock rows with
+// UPDATE ... FROM unnest(...) and the order lines with INSERT ... SELECT unnest(...).
+// Lines must not repeat a (supply warehouse, item) pair.
+async fn enter_lines_batched(
+    tx: &mut Transaction<'_, Postgres>,
+    order: OrderKey,
+    order_lines: &[OrderLineRequest],
+) -> Result<EnteredLines, ApiError> {
+    let items = get_items(tx, order_lines).await?;
+    if let Some(line) = order_lines
+        .iter()
+        .find(|line| !items.contains_key(&line.item_id))
+    {
+        return Ok(EnteredLines::UnusedItem(line.item_id));
+    }
 
-    // Commit transaction
-    tx.commit().await.map_err(|e| {
-        eprintln!("Failed to commit transaction: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    lock_stock(tx, order_lines).await?;
+    let mut stock = update_stock(tx, order, order_lines).await?;
 
-    Ok(Json(NewOrderResponse {
-        order_id,
-        customer: CustomerSummary {
-            customer_id: request.customer_id,
-            last_name: customer.c_last,
-            credit: customer.c_credit,
-            discount: customer.c_discount,
-        },
-        warehouse_tax: warehouse.w_tax,
-        district_tax: district.d_tax,
-        order_entry_date: entry_date,
+    let mut summaries = Vec::new();
+    let mut dist_infos = Vec::new();
+    let mut total_amount = BigDecimal::from_f64(0.0).unwrap();
+    for order_line in order_lines {
+        let item = &items[&order_line.item_id];
+        let stock = stock
+            .remove(&(order_line.supply_warehouse_id, order_line.item_id))
+            .ok_or(ApiError::NotFound(Entity::Stock {
+                warehouse_id: order_line.supply_warehouse_id,
+                item_id: order_line.item_id,
+            }))?;
+
+        let line_amount = &item.i_price * BigDecimal::from(order_line.quantity);
+        total_amount += &line_amount;
+        summaries.push(OrderLineSummary {
+            item_id: order_line.item_id,
+            supply_warehouse_id: order_line.supply_warehouse_id,
+            quantity: order_line.quantity,
+            item_name: item.i_name.clone(),
+            item_price: item.i_price.clone(),
+            stock_quantity: stock.s_quantity,
+            brand_generic: brand_generic(&item.i_name, &stock.s_data),
+            line_amount,
+        });
+        dist_infos.push(stock.s_dist_info);
+    }
+
+    insert_order_lines(tx, order, &summaries, &dist_infos).await?;
+    Ok(EnteredLines::Entered {
+        summaries,
         total_amount,
-        order_lines: order_line_summaries,
-    }))
+    })
 }
 
 // Database helper functions
+#[tracing::instrument(skip_all, fields(w_id = warehouse_id))]
 async fn get_warehouse_data(
     tx: &mut Transaction<'_, Postgres>,
     warehouse_id: i16,
-) -> Result<WarehouseData, StatusCode> {
+) -> Result<WarehouseData, ApiError> {
     let row = sqlx::query!("SELECT w_tax FROM warehouse1 WHERE w_id = $1", warehouse_id)
         .fetch_optional(&mut **tx)
         .await
-        .map_err(|e| {
-            eprintln!("Database error fetching warehouse: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
+        .map_err(|e| ApiError::database("fetching warehouse", e))?;
 
     match row {
         Some(row) => Ok(WarehouseData {
@@ -253,48 +678,43 @@ async fn get_warehouse_data(
                 .w_tax
                 .unwrap_or_else(|| BigDecimal::from_f64(0.0).unwrap()),
         }),
-        None => Err(StatusCode::NOT_FOUND),
+        None => Err(ApiError::NotFound(Entity::Warehouse { warehouse_id })),
     }
 }
 
+#[tracing::instrument(skip_all, fields(w_id = warehouse_id, d_id = district_id, o_id = tracing::field::Empty))]
 async fn get_and_update_district(
     tx: &mut Transaction<'_, Postgres>,
     warehouse_id: i16,
     district_id: i16,
-) -> Result<(DistrictData, i32), StatusCode> {
-    // Get current district data
+) -> Result<(DistrictData, i32), ApiError> {
+    // Take the next order ID and advance it in one statement; the row lock it
+    // holds until commit keeps concurrent New-Orders from taking the same ID
     let row = sqlx::query!(
-        "SELECT d_tax, d_next_o_id FROM district1 WHERE d_w_id = $1 AND d_id = $2",
+        r#"
+        UPDATE district1 SET d_next_o_id = COALESCE(d_next_o_id, 1) + 1
+        WHERE d_w_id = $1 AND d_id = $2
+        RETURNING d_tax, d_next_o_id - 1 AS "order_id!"
+        "#,
         warehouse_id,
         district_id
     )
     .fetch_optional(&mut **tx)
     .await
-    .map_err(|e| {
-        eprintln!("Database error fetching district: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    .map_err(|e| ApiError::database("updating district", e))?;
 
     let district_row = match row {
         Some(row) => row,
-        None => return Err(StatusCode::NOT_FOUND),
+        None => {
+            return Err(ApiError::NotFound(Entity::District {
+                warehouse_id,
+                district_id,
+            }))
+        }
     };
 
-    let next_order_id = district_row.d_next_o_id.unwrap_or(1);
-
-    // Update district with incremented order ID
-    sqlx::query!(
-        "UPDATE district1 SET d_next_o_id = $1 WHERE d_w_id = $2 AND d_id = $3",
-        next_order_id + 1,
-        warehouse_id,
-        district_id
-    )
-    .execute(&mut **tx)
-    .await
-    .map_err(|e| {
-        eprintln!("Database error updating district: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    let next_order_id = district_row.order_id;
+    tracing::Span::current().record("o_id", next_order_id);
 
     Ok((
         DistrictData {
@@ -307,12 +727,13 @@ async fn get_and_update_district(
     ))
 }
 
+#[tracing::instrument(skip_all, fields(w_id = warehouse_id, d_id = district_id, c_id = customer_id))]
 async fn get_customer_data(
     tx: &mut Transaction<'_, Postgres>,
     warehouse_id: i16,
     district_id: i16,
     customer_id: i32,
-) -> Result<CustomerData, StatusCode> {
+) -> Result<CustomerData, ApiError> {
     let row = sqlx::query!(
         "SELECT c_last, c_credit, c_discount FROM customer1 WHERE c_w_id = $1 AND c_d_id = $2 AND c_id = $3",
         warehouse_id,
@@ -321,10 +742,7 @@ async fn get_customer_data(
     )
     .fetch_optional(&mut **tx)
     .await
-    .map_err(|e| {
-        eprintln!("Database error fetching customer: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    .map_err(|e| ApiError::database("fetching customer", e))?;
 
     match row {
         Some(row) => Ok(CustomerData {
@@ -334,38 +752,47 @@ async fn get_customer_data(
                 .c_discount
                 .unwrap_or_else(|| BigDecimal::from_f64(0.0).unwrap()),
         }),
-        None => Err(StatusCode::NOT_FOUND),
+        None => Err(ApiError::NotFound(Entity::Customer {
+            warehouse_id,
+            district_id,
+            customer_id,
+        })),
     }
 }
 
+// `all_local` is None when the caller sets O_ALL_LOCAL once the lines are entered
+#[tracing::instrument(skip_all, fields(w_id = order.warehouse_id, d_id = order.district_id, o_id = order.order_id))]
 async fn insert_new_order(
     tx: &mut Transaction<'_, Postgres>,
-    warehouse_id: i16,
-    district_id: i16,
-    order_id: i32,
+    order: OrderKey,
     customer_id: i32,
     entry_date: NaiveDateTime,
     order_lines: &[OrderLineRequest],
-) -> Result<(), StatusCode> {
+    all_local: Option<bool>,
+) -> Result<(), ApiError> {
+    let OrderKey {
+        warehouse_id,
+        district_id,
+        order_id,
+    } = order;
+
     // Insert into orders table
     sqlx::query!(
         r#"
         INSERT INTO orders1 (o_id, o_d_id, o_w_id, o_c_id, o_entry_d, o_carrier_id, o_ol_cnt, o_all_local)
-        VALUES ($1, $2, $3, $4, $5, NULL, $6, NULL)
+        VALUES ($1, $2, $3, $4, $5, NULL, $6, $7)
         "#,
         order_id,
         district_id,
         warehouse_id,
         customer_id,
         entry_date,
-        order_lines.len() as i16
+        order_lines.len() as i16,
+        all_local.map(i16::from)
     )
     .execute(&mut **tx)
     .await
-    .map_err(|e| {
-        eprintln!("Database error inserting order: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    .map_err(|e| ApiError::database("inserting order", e))?;
 
     // Insert into new_orders table
     sqlx::query!(
@@ -376,37 +803,96 @@ async fn insert_new_order(
     )
     .execute(&mut **tx)
     .await
-    .map_err(|e| {
-        eprintln!("Database error inserting new order: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    .map_err(|e| ApiError::database("inserting new order", e))?;
 
     Ok(())
 }
 
+// None for an unused item ID
+#[tracing::instrument(skip_all, fields(i_id = item_id))]
 async fn get_item_data(
     tx: &mut Transaction<'_, Postgres>,
     item_id: i32,
-) -> Result<ItemData, StatusCode> {
+) -> Result<Option<ItemData>, ApiError> {
     let row = sqlx::query!("SELECT i_name, i_price FROM item1 WHERE i_id = $1", item_id)
         .fetch_optional(&mut **tx)
         .await
-        .map_err(|e| {
-            eprintln!("Database error fetching item: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
+        .map_err(|e| ApiError::database("fetching item", e))?;
 
-    match row {
-        Some(row) => Ok(ItemData {
-            i_name: row.i_name.unwrap_or_default(),
-            i_price: row
-                .i_price
-                .unwrap_or_else(|| BigDecimal::from_f64(0.0).unwrap()),
-        }),
-        None => Err(StatusCode::NOT_FOUND), // TPC-C: 1% of items should be invalid
+    Ok(row.map(|row| ItemData {
+        i_name: row.i_name.unwrap_or_default(),
+        i_price: row
+            .i_price
+            .unwrap_or_else(|| BigDecimal::from_f64(0.0).unwrap()),
+    }))
+}
+
+// The items of all lines by ID; unused item IDs are missing from the map
+#[tracing::instrument(skip_all, fields(ol_cnt = order_lines.len()))]
+async fn get_items(
+    tx: &mut Transaction<'_, Postgres>,
+    order_lines: &[OrderLineRequest],
+) -> Result<HashMap<i32, ItemData>, ApiError> {
+    let item_ids: Vec<i32> = order_lines.iter().map(|line| line.item_id).collect();
+    let rows = sqlx::query!(
+        "SELECT i_id, i_name, i_price FROM item1 WHERE i_id = ANY($1)",
+        &item_ids
+    )
+    .fetch_all(&mut **tx)
+    .await
+    .map_err(|e| ApiError::database("fetching items", e))?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            (
+                row.i_id,
+                ItemData {
+                    i_name: row.i_name.unwrap_or_default(),
+                    i_price: row
+                        .i_price
+                        .unwrap_or_else(|| BigDecimal::from_f64(0.0).unwrap()),
+                },
+            )
+        })
+        .collect())
+}
+
+// Lock the stock rows of an order in (supply warehouse, item ID) order, one
+// batch per supply warehouse. Locking them line by line in the order the client
+// listed them lets two orders sharing items deadlock; with a single global order
+// the later one just waits.
+#[tracing::instrument(skip_all, fields(ol_cnt = order_lines.len()))]
+async fn lock_stock(
+    tx: &mut Transaction<'_, Postgres>,
+    order_lines: &[OrderLineRequest],
+) -> Result<(), ApiError> {
+    let mut items_by_warehouse: BTreeMap<i16, Vec<i32>> = BTreeMap::new();
+    for line in order_lines {
+        items_by_warehouse
+            .entry(line.supply_warehouse_id)
+            .or_default()
+            .push(line.item_id);
     }
+
+    // FOR UPDATE locks rows as the sorted result is read, so in s_i_id order
+    for (warehouse_id, item_ids) in items_by_warehouse {
+        sqlx::query!(
+            "SELECT s_i_id FROM stock1 WHERE s_w_id = $1 AND s_i_id = ANY($2) ORDER BY s_i_id FOR UPDATE",
+            warehouse_id,
+            &item_ids
+        )
+        .fetch_all(&mut **tx)
+        .await
+        .map_err(|e| ApiError::database("locking stock", e))?;
+    }
+    Ok(())
 }
 
+#[tracing::instrument(
+    skip_all,
+    fields(i_id = item_id, supply_w_id = warehouse_id, d_id = district_id, remote = is_remote)
+)]
 async fn get_and_update_stock(
     tx: &mut Transaction<'_, Postgres>,
     item_id: i32,
@@ -414,93 +900,127 @@ async fn get_and_update_stock(
     quantity: i16,
     district_id: i16,
     is_remote: bool,
-) -> Result<StockData, StatusCode> {
-    // Get current stock data
+) -> Result<StockData, ApiError> {
+    // Quantity, YTD and counters are updated from the row's current values, so
+    // concurrent orders for the same item wait on the row lock instead of
+    // overwriting each other's changes
     let row = sqlx::query!(
         r#"
-        SELECT s_quantity, s_dist_01, s_dist_02, s_dist_03, s_dist_04, s_dist_05,
-               s_dist_06, s_dist_07, s_dist_08, s_dist_09, s_dist_10,
-               s_ytd, s_order_cnt, s_remote_cnt, s_data
-        FROM stock1 WHERE s_i_id = $1 AND s_w_id = $2
+        UPDATE stock1
+        SET s_quantity = CASE WHEN COALESCE(s_quantity, 0) >= $3::smallint
+                              THEN COALESCE(s_quantity, 0) - $3
+                              ELSE COALESCE(s_quantity, 0) - $3 + 91 -- TPC-C specification
+                         END,
+            s_ytd = COALESCE(s_ytd, 0) + $3,
+            s_order_cnt = COALESCE(s_order_cnt, 0) + 1,
+            s_remote_cnt = COALESCE(s_remote_cnt, 0) + CASE WHEN $4 THEN 1 ELSE 0 END
+        WHERE s_i_id = $1 AND s_w_id = $2
+        RETURNING s_quantity AS "s_quantity!", s_ytd AS "s_ytd!", s_order_cnt AS "s_order_cnt!",
+                  s_remote_cnt AS "s_remote_cnt!", s_data,
+                  CASE $5::smallint
+                      WHEN 1 THEN s_dist_01 WHEN 2 THEN s_dist_02 WHEN 3 THEN s_dist_03
+                      WHEN 4 THEN s_dist_04 WHEN 5 THEN s_dist_05 WHEN 6 THEN s_dist_06
+                      WHEN 7 THEN s_dist_07 WHEN 8 THEN s_dist_08 WHEN 9 THEN s_dist_09
+                      WHEN 10 THEN s_dist_10
+                  END AS dist_info
         "#,
         item_id,
-        warehouse_id
+        warehouse_id,
+        quantity,
+        is_remote,
+        district_id
     )
     .fetch_optional(&mut **tx)
     .await
-    .map_err(|e| {
-        eprintln!("Database error fetching stock: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    .map_err(|e| ApiError::database("updating stock", e))?;
 
     let stock_row = match row {
         Some(row) => row,
-        None => return Err(StatusCode::NOT_FOUND),
+        None => {
+            return Err(ApiError::NotFound(Entity::Stock {
+                warehouse_id,
+                item_id,
+            }))
+        }
     };
 
-    let current_quantity = stock_row.s_quantity.unwrap_or(0);
-    let new_quantity = if current_quantity >= quantity {
-        current_quantity - quantity
-    } else {
-        current_quantity - quantity + 91 // TPC-C specification
-    };
+    Ok(StockData {
+        s_quantity: stock_row.s_quantity,
+        s_dist_info: stock_row.dist_info.unwrap_or_default(),
+        s_ytd: stock_row.s_ytd,
+        s_order_cnt: stock_row.s_order_cnt,
+        s_remote_cnt: stock_row.s_remote_cnt,
+        s_data: stock_row.s_data.unwrap_or_default(),
+    })
+}
 
-    // Get the appropriate district info
-    let district_info = match district_id {
-        1 => stock_row.s_dist_01,
-        2 => stock_row.s_dist_02,
-        3 => stock_row.s_dist_03,
-        4 => stock_row.s_dist_04,
-        5 => stock_row.s_dist_05,
-        6 => stock_row.s_dist_06,
-        7 => stock_row.s_dist_07,
-        8 => stock_row.s_dist_08,
-        9 => stock_row.s_dist_09,
-        10 => stock_row.s_dist_10,
-        _ => None,
-    }
-    .unwrap_or_default();
-
-    // Update stock
-    let new_ytd = stock_row
-        .s_ytd
-        .unwrap_or_else(|| BigDecimal::from_f64(0.0).unwrap())
-        + BigDecimal::from(quantity);
-    let new_order_cnt = stock_row.s_order_cnt.unwrap_or(0) + 1;
-    let new_remote_cnt = if is_remote {
-        stock_row.s_remote_cnt.unwrap_or(0) + 1
-    } else {
-        stock_row.s_remote_cnt.unwrap_or(0)
-    };
+// The stock rows of all lines, updated as get_and_update_stock does, keyed by
+// (supply warehouse, item ID); rows that do not exist are missing from the map
+#[tracing::instrument(
+    skip_all,
+    fields(w_id = order.warehouse_id, d_id = order.district_id, ol_cnt = order_lines.len())
+)]
+async fn update_stock(
+    tx: &mut Transaction<'_, Postgres>,
+    order: OrderKey,
+    order_lines: &[OrderLineRequest],
+) -> Result<HashMap<(i16, i32), StockData>, ApiError> {
+    let supply_warehouse_ids: Vec<i16> = order_lines
+        .iter()
+        .map(|line| line.supply_warehouse_id)
+        .collect();
+    let item_ids: Vec<i32> = order_lines.iter().map(|line| line.item_id).collect();
+    let quantities: Vec<i16> = order_lines.iter().map(|line| line.quantity).collect();
 
-    sqlx::query!(
+    let rows = sqlx::query!(
         r#"
-        UPDATE stock1 
-        SET s_quantity = $1, s_ytd = $2, s_order_cnt = $3, s_remote_cnt = $4
-        WHERE s_i_id = $5 AND s_w_id = $6
+        UPDATE stock1 AS s
+        SET s_quantity = CASE WHEN COALESCE(s.s_quantity, 0) >= l.quantity
+                              THEN COALESCE(s.s_quantity, 0) - l.quantity
+                              ELSE COALESCE(s.s_quantity, 0) - l.quantity + 91 -- TPC-C specification
+                         END,
+            s_ytd = COALESCE(s.s_ytd, 0) + l.quantity,
+            s_order_cnt = COALESCE(s.s_order_cnt, 0) + 1,
+            s_remote_cnt = COALESCE(s.s_remote_cnt, 0)
+                           + CASE WHEN l.supply_w_id <> $4 THEN 1 ELSE 0 END
+        FROM unnest($1::smallint[], $2::int[], $3::smallint[]) AS l(supply_w_id, item_id, quantity)
+        WHERE s.s_w_id = l.supply_w_id AND s.s_i_id = l.item_id
+        RETURNING s.s_w_id AS "s_w_id!", s.s_i_id AS "s_i_id!", s.s_quantity AS "s_quantity!",
+                  s.s_ytd AS "s_ytd!", s.s_order_cnt AS "s_order_cnt!",
+                  s.s_remote_cnt AS "s_remote_cnt!", s.s_data,
+                  CASE $5::smallint
+                      WHEN 1 THEN s.s_dist_01 WHEN 2 THEN s.s_dist_02 WHEN 3 THEN s.s_dist_03
+                      WHEN 4 THEN s.s_dist_04 WHEN 5 THEN s.s_dist_05 WHEN 6 THEN s.s_dist_06
+                      WHEN 7 THEN s.s_dist_07 WHEN 8 THEN s.s_dist_08 WHEN 9 THEN s.s_dist_09
+                      WHEN 10 THEN s.s_dist_10
+                  END AS dist_info
         "#,
-        new_quantity,
-        new_ytd,
-        new_order_cnt,
-        new_remote_cnt,
-        item_id,
-        warehouse_id
+        &supply_warehouse_ids,
+        &item_ids,
+        &quantities,
+        order.warehouse_id,
+        order.district_id
     )
-    .execute(&mut **tx)
+    .fetch_all(&mut **tx)
     .await
-    .map_err(|e| {
-        eprintln!("Database error updating stock: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    .map_err(|e| ApiError::database("updating stock", e))?;
 
-    Ok(StockData {
-        s_quantity: new_quantity,
-        s_dist_info: district_info,
-        s_ytd: new_ytd,
-        s_order_cnt: new_order_cnt,
-        s_remote_cnt: new_remote_cnt,
-        s_data: stock_row.s_data.unwrap_or_default(),
-    })
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            (
+                (row.s_w_id, row.s_i_id),
+                StockData {
+                    s_quantity: row.s_quantity,
+                    s_dist_info: row.dist_info.unwrap_or_default(),
+                    s_ytd: row.s_ytd,
+                    s_order_cnt: row.s_order_cnt,
+                    s_remote_cnt: row.s_remote_cnt,
+                    s_data: row.s_data.unwrap_or_default(),
+                },
+            )
+        })
+        .collect())
 }
 
 struct OrderLineParams {
@@ -515,10 +1035,20 @@ struct OrderLineParams {
     dist_info: String,
 }
 
+#[tracing::instrument(
+    skip_all,
+    fields(
+        w_id = params.warehouse_id,
+        d_id = params.district_id,
+        o_id = params.order_id,
+        ol_number = params.line_number,
+        i_id = params.item_id
+    )
+)]
 async fn insert_order_line(
     tx: &mut Transaction<'_, Postgres>,
     params: OrderLineParams,
-) -> Result<(), StatusCode> {
+) -> Result<(), ApiError> {
     sqlx::query!(
         r#"
         INSERT INTO order_line1 (ol_o_id, ol_d_id, ol_w_id, ol_number, ol_i_id, 
@@ -537,14 +1067,55 @@ async fn insert_order_line(
     )
     .execute(&mut **tx)
     .await
-    .map_err(|e| {
-        eprintln!("Database error inserting order line: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    .map_err(|e| ApiError::database("inserting order line", e))?;
 
     Ok(())
 }
 
+// All order lines in one INSERT, numbered in request order
+#[tracing::instrument(
+    skip_all,
+    fields(w_id = order.warehouse_id, d_id = order.district_id, o_id = order.order_id, ol_cnt = lines.len())
+)]
+async fn insert_order_lines(
+    tx: &mut Transaction<'_, Postgres>,
+    order: OrderKey,
+    lines: &[OrderLineSummary],
+    dist_infos: &[String],
+) -> Result<(), ApiError> {
+    let line_numbers: Vec<i16> = (1..=lines.len() as i16).collect();
+    let item_ids: Vec<i32> = lines.iter().map(|line| line.item_id).collect();
+    let supply_warehouse_ids: Vec<i16> =
+        lines.iter().map(|line| line.supply_warehouse_id).collect();
+    let quantities: Vec<i16> = lines.iter().map(|line| line.quantity).collect();
+    let amounts: Vec<BigDecimal> = lines.iter().map(|line| line.line_amount.clone()).collect();
+
+    sqlx::query!(
+        r#"
+        INSERT INTO order_line1 (ol_o_id, ol_d_id, ol_w_id, ol_number, ol_i_id, ol_supply_w_id,
+                                 ol_delivery_d, ol_quantity, ol_amount, ol_dist_info)
+        SELECT $1, $2, $3, l.number, l.item_id, l.supply_w_id, NULL, l.quantity, l.amount, l.dist_info
+        FROM unnest($4::smallint[], $5::int[], $6::smallint[], $7::smallint[], $8::numeric[], $9::text[])
+             AS l(number, item_id, supply_w_id, quantity, amount, dist_info)
+        "#,
+        order.order_id,
+        order.district_id,
+        order.warehouse_id,
+        &line_numbers,
+        &item_ids,
+        &supply_warehouse_ids,
+        &quantities,
+        &amounts,
+        dist_infos
+    )
+    .execute(&mut **tx)
+    .await
+    .map_err(|e| ApiError::database("inserting order lines", e))?;
+
+    Ok(())
+}
+
+#[tracing::instrument(skip_all, fields(w_id = warehouse_id, d_id = district_id, o_id = order_id))]
 async fn update_order_totals(
     tx: &mut Transaction<'_, Postgres>,
     warehouse_id: i16,
@@ -552,7 +1123,7 @@ async fn update_order_totals(
     order_id: i32,
     line_count: i16,
     all_local: bool,
-) -> Result<(), StatusCode> {
+) -> Result<(), ApiError> {
     sqlx::query!(
         "UPDATE orders1 SET o_ol_cnt = $1, o_all_local = $2 WHERE o_w_id = $3 AND o_d_id = $4 AND o_id = $5",
         line_count,
@@ -563,10 +1134,7 @@ async fn update_order_totals(
     )
     .execute(&mut **tx)
     .await
-    .map_err(|e| {
-        eprintln!("Database error updating order totals: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    .map_err(|e| ApiError::database("updating order totals", e))?;
 
     Ok(())
 }
@@ -1,29 +1,52 @@
-use axum::{
-    extract::{Query, State},
-    http::StatusCode,
-    Json,
-};
+use axum::{extract::State, Json};
 use bigdecimal::BigDecimal;
 use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
 use sqlx::{Pool, Postgres};
 
+use crate::error::{ApiError, Entity};
+use crate::extract::ApiQuery;
+use crate::table_set::TableSet;
+use crate::transaction::{
+    begin, run_transaction, Attempted, ExecutionMode, IsolationLevel, TransactionSettings,
+};
+
+use super::customers::{find_customer_by_last_name, CustomerSelector};
+
 // Request Query Parameters
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 pub struct OrderStatusQuery {
     pub warehouse_id: i16,
     pub district_id: i16,
-    pub customer_id: i32,
+    // Exactly one of customer_id / customer_last_name must be provided
+    pub customer_id: Option<i32>,
+    pub customer_last_name: Option<String>,
 }
 
 // Response Structures
 #[derive(Serialize)]
 pub struct OrderStatusResponse {
+    pub customer_selection: CustomerSelectionInfo,
     pub customer: CustomerInfo,
     pub latest_order: LatestOrderInfo,
     pub order_lines: Vec<OrderLineInfo>,
 }
 
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CustomerSelectionMethod {
+    CustomerId,
+    LastName,
+}
+
+#[derive(Serialize)]
+pub struct CustomerSelectionInfo {
+    pub selected_by: CustomerSelectionMethod,
+    pub c_id: i32,
+    // Number of customers sharing the requested last name (1 when selected by ID)
+    pub name_count: i64,
+}
+
 #[derive(Serialize)]
 pub struct CustomerInfo {
     pub c_id: i32,
@@ -51,26 +74,115 @@ pub struct OrderLineInfo {
 
 // Handler function
 pub async fn order_status(
-    State(pool): State<Pool<Postgres>>,
-    Query(params): Query<OrderStatusQuery>,
-) -> Result<Json<OrderStatusResponse>, StatusCode> {
-    // 1. Get customer details
+    TableSet { transactions, .. }: TableSet,
+    State(settings): State<TransactionSettings>,
+    ApiQuery(params): ApiQuery<OrderStatusQuery>,
+) -> Result<Attempted<Json<OrderStatusResponse>>, ApiError> {
+    let attempted = transactions.order_status(&settings, &params).await?;
+    Ok(attempted.map(Json))
+}
+
+// Order-Status with validation and retries, without HTTP; the handler and the in-process
+// driver call this
+pub async fn execute_order_status(
+    pool: &Pool<Postgres>,
+    settings: &TransactionSettings,
+    params: &OrderStatusQuery,
+) -> Result<Attempted<OrderStatusResponse>, ApiError> {
+    let customer_selector =
+        CustomerSelector::from_request(params.customer_id, params.customer_last_name.clone())
+            .ok_or_else(|| {
+                ApiError::bad_request(
+                    "exactly one of customer_id or customer_last_name must be provided",
+                )
+            })?;
+
+    let isolation = settings.isolation_levels.order_status;
+    run_transaction(&settings.retry_policy, "order-status", || async {
+        match settings.execution_mode {
+            ExecutionMode::Inline => {
+                process_order_status(
+                    pool,
+                    isolation,
+                    params.warehouse_id,
+                    params.district_id,
+                    &customer_selector,
+                )
+                .await
+            }
+            ExecutionMode::StoredProcedures => {
+                call_order_status(
+                    pool,
+                    isolation,
+                    params.warehouse_id,
+                    params.district_id,
+                    &customer_selector,
+                )
+                .await
+            }
+        }
+    })
+    .await
+}
+
+#[tracing::instrument(
+    name = "order_status",
+    skip_all,
+    fields(w_id = warehouse_id, d_id = district_id, c_id = tracing::field::Empty, o_id = tracing::field::Empty)
+)]
+async fn process_order_status(
+    pool: &Pool<Postgres>,
+    isolation: Option<IsolationLevel>,
+    warehouse_id: i16,
+    district_id: i16,
+    customer_selector: &CustomerSelector,
+) -> Result<OrderStatusResponse, ApiError> {
+    let mut tx = begin(pool, isolation).await?;
+
+    // 1. Resolve the customer, picking the middle customer when selecting by last name
+    let customer_selection = match customer_selector {
+        CustomerSelector::Id(customer_id) => CustomerSelectionInfo {
+            selected_by: CustomerSelectionMethod::CustomerId,
+            c_id: *customer_id,
+            name_count: 1,
+        },
+        CustomerSelector::LastName(last_name) => {
+            let name_match =
+                find_customer_by_last_name(&mut *tx, warehouse_id, district_id, last_name)
+                    .await
+                    .map_err(|e| ApiError::database("selecting customer by last name", e))?
+                    .ok_or_else(|| {
+                        ApiError::NotFound(Entity::CustomerLastName {
+                            warehouse_id,
+                            district_id,
+                            customer_last_name: last_name.clone(),
+                        })
+                    })?;
+
+            CustomerSelectionInfo {
+                selected_by: CustomerSelectionMethod::LastName,
+                c_id: name_match.c_id,
+                name_count: name_match.name_count,
+            }
+        }
+    };
+    let customer_id = customer_selection.c_id;
+    tracing::Span::current().record("c_id", customer_id);
+
+    // 2. Get customer details
     let customer_row = sqlx::query!(
         r#"
         SELECT c_id, c_first, c_middle, c_last, c_balance
         FROM customer1
         WHERE c_w_id = $1 AND c_d_id = $2 AND c_id = $3
         "#,
-        params.warehouse_id,
-        params.district_id,
-        params.customer_id
+        warehouse_id,
+        district_id,
+        customer_id
     )
-    .fetch_optional(&pool)
+    .fetch_optional(&mut *tx)
     .await
-    .map_err(|e| {
-        eprintln!("Database error fetching customer: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    .map_err(|e| ApiError::database("fetching customer", e))?;
 
     let customer_info = match customer_row {
         Some(row) => CustomerInfo {
@@ -80,10 +192,16 @@ pub async fn order_status(
             c_last: row.c_last,
             c_balance: row.c_balance,
         },
-        None => return Err(StatusCode::NOT_FOUND), // Customer not found
+        None => {
+            return Err(ApiError::NotFound(Entity::Customer {
+                warehouse_id,
+                district_id,
+                customer_id,
+            }))
+        }
     };
 
-    // 2. Get the latest order for the customer
+    // 3. Get the latest order for the customer
     // TPC-C specification: SELECT o_id, o_carrier_id, o_entry_d FROM orders ORDER BY o_id DESC;
     let latest_order_row = sqlx::query!(
         r#"
@@ -93,16 +211,13 @@ pub async fn order_status(
         ORDER BY o_id DESC
         LIMIT 1
         "#,
-        params.warehouse_id,
-        params.district_id,
-        params.customer_id
+        warehouse_id,
+        district_id,
+        customer_id
     )
-    .fetch_optional(&pool)
+    .fetch_optional(&mut *tx)
     .await
-    .map_err(|e| {
-        eprintln!("Database error fetching latest order: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    .map_err(|e| ApiError::database("fetching latest order", e))?;
 
     let latest_order_info = match latest_order_row {
         Some(row) => LatestOrderInfo {
@@ -110,10 +225,18 @@ pub async fn order_status(
             o_entry_d: row.o_entry_d,
             o_carrier_id: row.o_carrier_id,
         },
-        None => return Err(StatusCode::NOT_FOUND), // No orders found for customer
+        None => {
+            // No orders found for customer
+            return Err(ApiError::NotFound(Entity::CustomerOrders {
+                warehouse_id,
+                district_id,
+                customer_id,
+            }));
+        }
     };
+    tracing::Span::current().record("o_id", latest_order_info.o_id);
 
-    // 3. Get all order lines for the latest order
+    // 4. Get all order lines for the latest order
     let order_lines_rows = sqlx::query!(
         r#"
         SELECT ol_i_id, ol_supply_w_id, ol_quantity, ol_amount, ol_delivery_d
@@ -121,16 +244,13 @@ pub async fn order_status(
         WHERE ol_w_id = $1 AND ol_d_id = $2 AND ol_o_id = $3
         ORDER BY ol_number ASC
         "#,
-        params.warehouse_id,
-        params.district_id,
+        warehouse_id,
+        district_id,
         latest_order_info.o_id
     )
-    .fetch_all(&pool)
+    .fetch_all(&mut *tx)
     .await
-    .map_err(|e| {
-        eprintln!("Database error fetching order lines: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    .map_err(|e| ApiError::database("fetching order lines", e))?;
 
     let order_lines_info: Vec<OrderLineInfo> = order_lines_rows
         .into_iter()
@@ -143,9 +263,123 @@ pub async fn order_status(
         })
         .collect();
 
-    Ok(Json(OrderStatusResponse {
+    tx.commit()
+        .await
+        .map_err(|e| ApiError::database("committing transaction", e))?;
+
+    Ok(OrderStatusResponse {
+        customer_selection,
         customer: customer_info,
         latest_order: latest_order_info,
         order_lines: order_lines_info,
-    }))
+    })
+}
+
+// Order-Status as one call to tpcc_order_status
+#[tracing::instrument(
+    name = "tpcc_order_status",
+    skip_all,
+    fields(w_id = warehouse_id, d_id = district_id, c_id = tracing::field::Empty, o_id = tracing::field::Empty)
+)]
+async fn call_order_status(
+    pool: &Pool<Postgres>,
+    isolation: Option<IsolationLevel>,
+    warehouse_id: i16,
+    district_id: i16,
+    customer_selector: &CustomerSelector,
+) -> Result<OrderStatusResponse, ApiError> {
+    let (customer_id, customer_last_name, selected_by) = match customer_selector {
+        CustomerSelector::Id(customer_id) => (
+            Some(*customer_id),
+            None,
+            CustomerSelectionMethod::CustomerId,
+        ),
+        CustomerSelector::LastName(last_name) => (
+            None,
+            Some(last_name.as_str()),
+            CustomerSelectionMethod::LastName,
+        ),
+    };
+
+    let mut tx = begin(pool, isolation).await?;
+    let row = sqlx::query!(
+        r#"
+        SELECT c_id AS "c_id!", name_count AS "name_count!", c_first, c_middle, c_last,
+               c_balance, o_id AS "o_id!", o_entry_d, o_carrier_id,
+               ol_i_ids AS "ol_i_ids!: Vec<Option<i32>>",
+               ol_supply_w_ids AS "ol_supply_w_ids!: Vec<Option<i16>>",
+               ol_quantities AS "ol_quantities!: Vec<Option<i16>>",
+               ol_amounts AS "ol_amounts!: Vec<Option<BigDecimal>>",
+               ol_delivery_ds AS "ol_delivery_ds!: Vec<Option<NaiveDateTime>>"
+        FROM tpcc_order_status($1, $2, $3, $4)
+        "#,
+        warehouse_id,
+        district_id,
+        customer_id,
+        customer_last_name
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| {
+        ApiError::procedure("calling tpcc_order_status", e, |row| {
+            match (row.table.as_str(), customer_selector) {
+                ("customer", CustomerSelector::Id(customer_id)) => Some(Entity::Customer {
+                    warehouse_id,
+                    district_id,
+                    customer_id: *customer_id,
+                }),
+                ("customer", CustomerSelector::LastName(last_name)) => {
+                    Some(Entity::CustomerLastName {
+                        warehouse_id,
+                        district_id,
+                        customer_last_name: last_name.clone(),
+                    })
+                }
+                // Keyed by the selected customer's ID
+                ("orders", _) => Some(Entity::CustomerOrders {
+                    warehouse_id,
+                    district_id,
+                    customer_id: row.key()?,
+                }),
+                _ => None,
+            }
+        })
+    })?;
+    tracing::Span::current().record("c_id", row.c_id);
+    tracing::Span::current().record("o_id", row.o_id);
+
+    tx.commit()
+        .await
+        .map_err(|e| ApiError::database("committing transaction", e))?;
+
+    let order_lines = (0..row.ol_i_ids.len())
+        .map(|n| OrderLineInfo {
+            ol_i_id: row.ol_i_ids[n],
+            ol_supply_w_id: row.ol_supply_w_ids[n],
+            ol_quantity: row.ol_quantities[n],
+            ol_amount: row.ol_amounts[n].clone(),
+            ol_delivery_d: row.ol_delivery_ds[n],
+        })
+        .collect();
+
+    Ok(OrderStatusResponse {
+        customer_selection: CustomerSelectionInfo {
+            selected_by,
+            c_id: row.c_id,
+            name_count: row.name_count,
+        },
+        customer: CustomerInfo {
+            c_id: row.c_id,
+            c_first: row.c_first,
+            c_middle: row.c_middle,
+            c_last: row.c_last,
+            c_balance: row.c_balance,
+        },
+        latest_order: LatestOrderInfo {
+            o_id: row.o_id,
+            o_entry_d: row.o_entry_d,
+            o_carrier_id: row.o_carrier_id,
+        },
+        order_lines,
+    })
 }
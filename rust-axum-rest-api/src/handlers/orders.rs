@@ -1,14 +1,14 @@
-use axum::{
-    extract::{Query, State},
-    http::StatusCode,
-    Json,
-};
+use axum::Json;
 use bigdecimal::BigDecimal;
 use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
-use sqlx::{Pool, Postgres, QueryBuilder};
+use sqlx::{Postgres, QueryBuilder};
 use std::collections::HashMap;
 
+use crate::error::ApiError;
+use crate::extract::ApiQuery;
+use crate::table_set::TableSet;
+
 // Enum types for type-safe query parameters
 #[derive(Deserialize, Debug, Clone, Copy)]
 #[serde(rename_all = "snake_case")]
@@ -86,9 +86,9 @@ pub struct OrderSummary {
 
 // Handler function for listing orders
 pub async fn list_orders(
-    State(pool): State<Pool<Postgres>>,
-    Query(params): Query<OrdersQuery>,
-) -> Result<Json<OrdersListResponse>, StatusCode> {
+    TableSet { pool, .. }: TableSet,
+    ApiQuery(params): ApiQuery<OrdersQuery>,
+) -> Result<Json<OrdersListResponse>, ApiError> {
     // Set defaults for pagination
     let page = params.page.unwrap_or(1);
     let per_page = params.per_page.unwrap_or(20).min(100); // Cap at 100 per page
@@ -126,10 +126,7 @@ pub async fn list_orders(
         .build_query_scalar::<i64>()
         .fetch_one(&pool)
         .await
-        .map_err(|e| {
-            eprintln!("Database error counting orders: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
+        .map_err(|e| ApiError::database("counting orders", e))?;
 
     let total_count = total_count_result;
 
@@ -186,10 +183,7 @@ pub async fn list_orders(
         )>()
         .fetch_all(&pool)
         .await
-        .map_err(|e| {
-            eprintln!("Database error fetching orders: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
+        .map_err(|e| ApiError::database("fetching orders", e))?;
 
     // If we have orders, calculate totals efficiently for only these orders
     let mut orders: Vec<OrderSummary> = orders_rows
@@ -245,10 +239,7 @@ pub async fn list_orders(
             .build_query_as::<(i16, i16, i32, Option<bigdecimal::BigDecimal>, i64)>()
             .fetch_all(&pool)
             .await
-            .map_err(|e| {
-                eprintln!("Database error fetching order totals: {}", e);
-                StatusCode::INTERNAL_SERVER_ERROR
-            })?;
+            .map_err(|e| ApiError::database("fetching order totals", e))?;
 
         // Map totals back to orders efficiently
         let mut totals_map: HashMap<(i16, i16, i32), (Option<bigdecimal::BigDecimal>, i64)> =
@@ -1,15 +1,29 @@
-use axum::{extract::State, http::StatusCode, Json};
+use axum::{extract::State, Json};
 use bigdecimal::{BigDecimal, FromPrimitive};
 use chrono::{NaiveDateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::{Pool, Postgres, Transaction};
 
+use crate::error::{ApiError, Entity};
+use crate::extract::ApiJson;
+use crate::table_set::TableSet;
+use crate::transaction::{
+    begin, run_transaction, Attempted, ExecutionMode, IsolationLevel, TransactionSettings,
+};
+
+use super::customers::{find_customer_by_last_name, CustomerSelector};
+
 // Request Structure
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 pub struct PaymentRequest {
     pub warehouse_id: i16,
     pub district_id: i16,
-    pub customer_id: i32,
+    // Exactly one of customer_id / customer_last_name must be provided
+    pub customer_id: Option<i32>,
+    pub customer_last_name: Option<String>,
+    // Home warehouse/district of a remote customer; default to the paying warehouse/district
+    pub customer_warehouse_id: Option<i16>,
+    pub customer_district_id: Option<i16>,
     pub amount: f64,
 }
 
@@ -48,6 +62,8 @@ pub struct DistrictInfo {
 #[derive(Serialize)]
 pub struct PaymentCustomerInfo {
     pub c_id: i32,
+    pub c_d_id: i16,
+    pub c_w_id: i16,
     pub c_first: String,
     pub c_middle: String,
     pub c_last: String,
@@ -89,6 +105,7 @@ struct DistrictData {
 
 #[allow(dead_code)]
 struct CustomerData {
+    c_id: i32,
     c_first: String,
     c_middle: String,
     c_last: String,
@@ -108,56 +125,136 @@ struct CustomerData {
     c_data: String,
 }
 
+// Validated Payment request
+pub(crate) struct PaymentInput {
+    pub(crate) warehouse_id: i16,
+    pub(crate) district_id: i16,
+    pub(crate) customer_warehouse_id: i16,
+    pub(crate) customer_district_id: i16,
+    pub(crate) customer: CustomerSelector,
+    pub(crate) amount: BigDecimal,
+}
+
+impl PaymentInput {
+    pub(crate) fn from_request(request: &PaymentRequest) -> Result<Self, ApiError> {
+        // Convert payment amount to BigDecimal for precise calculations
+        let payment_amount = BigDecimal::from_f64(request.amount)
+            .ok_or_else(|| ApiError::bad_request("amount must be a finite number"))?;
+
+        if payment_amount <= BigDecimal::from(0) {
+            return Err(ApiError::bad_request("amount must be positive"));
+        }
+
+        let customer_selector =
+            CustomerSelector::from_request(request.customer_id, request.customer_last_name.clone())
+                .ok_or_else(|| {
+                    ApiError::bad_request(
+                        "exactly one of customer_id or customer_last_name must be provided",
+                    )
+                })?;
+
+        // 15% of TPC-C payments are made by a customer of another warehouse
+        Ok(PaymentInput {
+            warehouse_id: request.warehouse_id,
+            district_id: request.district_id,
+            customer_warehouse_id: request
+                .customer_warehouse_id
+                .unwrap_or(request.warehouse_id),
+            customer_district_id: request.customer_district_id.unwrap_or(request.district_id),
+            customer: customer_selector,
+            amount: payment_amount,
+        })
+    }
+}
+
 // Handler function
 pub async fn payment(
-    State(pool): State<Pool<Postgres>>,
-    Json(request): Json<PaymentRequest>,
-) -> Result<Json<PaymentResponse>, StatusCode> {
-    // Convert payment amount to BigDecimal for precise calculations
-    let payment_amount = BigDecimal::from_f64(request.amount).ok_or(StatusCode::BAD_REQUEST)?;
-
-    if payment_amount <= BigDecimal::from(0) {
-        return Err(StatusCode::BAD_REQUEST);
-    }
+    TableSet { transactions, .. }: TableSet,
+    State(settings): State<TransactionSettings>,
+    ApiJson(request): ApiJson<PaymentRequest>,
+) -> Result<Attempted<Json<PaymentResponse>>, ApiError> {
+    let attempted = transactions.payment(&settings, &request).await?;
+    Ok(attempted.map(Json))
+}
+
+// Payment with validation and retries, without HTTP; the handler and the in-process
+// driver call this
+pub async fn execute_payment(
+    pool: &Pool<Postgres>,
+    settings: &TransactionSettings,
+    request: &PaymentRequest,
+) -> Result<Attempted<PaymentResponse>, ApiError> {
+    let input = PaymentInput::from_request(request)?;
+
+    let isolation = settings.isolation_levels.payment;
+    run_transaction(&settings.retry_policy, "payment", || async {
+        match settings.execution_mode {
+            ExecutionMode::Inline => process_payment(pool, isolation, &input).await,
+            ExecutionMode::StoredProcedures => call_payment(pool, isolation, &input).await,
+        }
+    })
+    .await
+}
+
+#[tracing::instrument(
+    name = "payment",
+    skip_all,
+    fields(
+        w_id = input.warehouse_id,
+        d_id = input.district_id,
+        c_w_id = input.customer_warehouse_id,
+        c_d_id = input.customer_district_id,
+        c_id = tracing::field::Empty
+    )
+)]
+async fn process_payment(
+    pool: &Pool<Postgres>,
+    isolation: Option<IsolationLevel>,
+    input: &PaymentInput,
+) -> Result<PaymentResponse, ApiError> {
+    let PaymentInput {
+        warehouse_id,
+        district_id,
+        customer_warehouse_id,
+        customer_district_id,
+        customer: ref customer_selector,
+        amount: ref payment_amount,
+    } = *input;
 
     let payment_date = Utc::now().naive_utc();
 
     // Start transaction - TPC-C Payment is a multi-table transaction
-    let mut tx = pool.begin().await.map_err(|e| {
-        eprintln!("Failed to start transaction: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    let mut tx = begin(pool, isolation).await?;
 
     // Step 1: Get and update warehouse data
-    let warehouse =
-        get_and_update_warehouse(&mut tx, request.warehouse_id, &payment_amount).await?;
+    let warehouse = get_and_update_warehouse(&mut tx, warehouse_id, payment_amount).await?;
 
     // Step 2: Get and update district data
-    let district = get_and_update_district(
-        &mut tx,
-        request.warehouse_id,
-        request.district_id,
-        &payment_amount,
-    )
-    .await?;
+    let district =
+        get_and_update_district(&mut tx, warehouse_id, district_id, payment_amount).await?;
 
-    // Step 3: Get and update customer data
+    // Step 3: Get and update customer data in the customer's home warehouse
     let customer = get_and_update_customer(
         &mut tx,
-        request.warehouse_id,
-        request.district_id,
-        request.customer_id,
-        &payment_amount,
+        warehouse_id,
+        district_id,
+        customer_warehouse_id,
+        customer_district_id,
+        customer_selector,
+        payment_amount,
     )
     .await?;
+    tracing::Span::current().record("c_id", customer.c_id);
 
     // Step 4: Insert history record
     insert_payment_history(
         &mut tx,
         PaymentHistoryParams {
-            warehouse_id: request.warehouse_id,
-            district_id: request.district_id,
-            customer_id: request.customer_id,
+            warehouse_id,
+            district_id,
+            customer_warehouse_id,
+            customer_district_id,
+            customer_id: customer.c_id,
             payment_amount: payment_amount.clone(),
             payment_date,
             warehouse_name: warehouse.w_name.clone(),
@@ -167,14 +264,13 @@ pub async fn payment(
     .await?;
 
     // Commit transaction
-    tx.commit().await.map_err(|e| {
-        eprintln!("Failed to commit transaction: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    tx.commit()
+        .await
+        .map_err(|e| ApiError::database("committing transaction", e))?;
 
-    Ok(Json(PaymentResponse {
+    Ok(PaymentResponse {
         warehouse: WarehouseInfo {
-            w_id: request.warehouse_id,
+            w_id: warehouse_id,
             w_name: warehouse.w_name,
             w_street_1: warehouse.w_street_1,
             w_street_2: warehouse.w_street_2,
@@ -183,7 +279,7 @@ pub async fn payment(
             w_zip: warehouse.w_zip,
         },
         district: DistrictInfo {
-            d_id: request.district_id,
+            d_id: district_id,
             d_name: district.d_name,
             d_street_1: district.d_street_1,
             d_street_2: district.d_street_2,
@@ -192,7 +288,9 @@ pub async fn payment(
             d_zip: district.d_zip,
         },
         customer: PaymentCustomerInfo {
-            c_id: request.customer_id,
+            c_id: customer.c_id,
+            c_d_id: customer_district_id,
+            c_w_id: customer_warehouse_id,
             c_first: customer.c_first,
             c_middle: customer.c_middle,
             c_last: customer.c_last,
@@ -209,52 +307,154 @@ pub async fn payment(
             c_balance: customer.c_balance,
         },
         payment_date,
-        payment_amount,
-    }))
+        payment_amount: payment_amount.clone(),
+    })
+}
+
+// Payment as one call to tpcc_payment
+#[tracing::instrument(
+    name = "tpcc_payment",
+    skip_all,
+    fields(
+        w_id = input.warehouse_id,
+        d_id = input.district_id,
+        c_w_id = input.customer_warehouse_id,
+        c_d_id = input.customer_district_id,
+        c_id = tracing::field::Empty
+    )
+)]
+async fn call_payment(
+    pool: &Pool<Postgres>,
+    isolation: Option<IsolationLevel>,
+    input: &PaymentInput,
+) -> Result<PaymentResponse, ApiError> {
+    let (customer_id, customer_last_name) = match &input.customer {
+        CustomerSelector::Id(customer_id) => (Some(*customer_id), None),
+        CustomerSelector::LastName(last_name) => (None, Some(last_name.as_str())),
+    };
+    let payment_date = Utc::now().naive_utc();
+
+    let mut tx = begin(pool, isolation).await?;
+    let row = sqlx::query!(
+        r#"
+        SELECT w_name, w_street_1, w_street_2, w_city, w_state, w_zip,
+               d_name, d_street_1, d_street_2, d_city, d_state, d_zip,
+               c_id AS "c_id!", c_first, c_middle, c_last, c_street_1, c_street_2, c_city,
+               c_state, c_zip, c_phone, c_since, c_credit, c_credit_lim, c_discount,
+               c_balance AS "c_balance!"
+        FROM tpcc_payment($1, $2, $3, $4, $5, $6, $7, $8)
+        "#,
+        input.warehouse_id,
+        input.district_id,
+        input.customer_warehouse_id,
+        input.customer_district_id,
+        customer_id,
+        customer_last_name,
+        input.amount,
+        payment_date
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| {
+        ApiError::procedure("calling tpcc_payment", e, |row| match row.table.as_str() {
+            "warehouse" => Some(Entity::Warehouse {
+                warehouse_id: input.warehouse_id,
+            }),
+            "district" => Some(Entity::District {
+                warehouse_id: input.warehouse_id,
+                district_id: input.district_id,
+            }),
+            "customer" => Some(match &input.customer {
+                CustomerSelector::Id(customer_id) => Entity::Customer {
+                    warehouse_id: input.customer_warehouse_id,
+                    district_id: input.customer_district_id,
+                    customer_id: *customer_id,
+                },
+                CustomerSelector::LastName(last_name) => Entity::CustomerLastName {
+                    warehouse_id: input.customer_warehouse_id,
+                    district_id: input.customer_district_id,
+                    customer_last_name: last_name.clone(),
+                },
+            }),
+            _ => None,
+        })
+    })?;
+    tracing::Span::current().record("c_id", row.c_id);
+
+    tx.commit()
+        .await
+        .map_err(|e| ApiError::database("committing transaction", e))?;
+
+    Ok(PaymentResponse {
+        warehouse: WarehouseInfo {
+            w_id: input.warehouse_id,
+            w_name: row.w_name.unwrap_or_default(),
+            w_street_1: row.w_street_1.unwrap_or_default(),
+            w_street_2: row.w_street_2.unwrap_or_default(),
+            w_city: row.w_city.unwrap_or_default(),
+            w_state: row.w_state.unwrap_or_default(),
+            w_zip: row.w_zip.unwrap_or_default(),
+        },
+        district: DistrictInfo {
+            d_id: input.district_id,
+            d_name: row.d_name.unwrap_or_default(),
+            d_street_1: row.d_street_1.unwrap_or_default(),
+            d_street_2: row.d_street_2.unwrap_or_default(),
+            d_city: row.d_city.unwrap_or_default(),
+            d_state: row.d_state.unwrap_or_default(),
+            d_zip: row.d_zip.unwrap_or_default(),
+        },
+        customer: PaymentCustomerInfo {
+            c_id: row.c_id,
+            c_d_id: input.customer_district_id,
+            c_w_id: input.customer_warehouse_id,
+            c_first: row.c_first.unwrap_or_default(),
+            c_middle: row.c_middle.unwrap_or_default(),
+            c_last: row.c_last.unwrap_or_default(),
+            c_street_1: row.c_street_1.unwrap_or_default(),
+            c_street_2: row.c_street_2.unwrap_or_default(),
+            c_city: row.c_city.unwrap_or_default(),
+            c_state: row.c_state.unwrap_or_default(),
+            c_zip: row.c_zip.unwrap_or_default(),
+            c_phone: row.c_phone.unwrap_or_default(),
+            c_since: row.c_since.unwrap_or_default(),
+            c_credit: row.c_credit.unwrap_or_default(),
+            c_credit_lim: row.c_credit_lim.unwrap_or(0),
+            c_discount: row.c_discount.unwrap_or_else(|| BigDecimal::from(0)),
+            c_balance: row.c_balance,
+        },
+        payment_date,
+        payment_amount: input.amount.clone(),
+    })
 }
 
 // Database helper functions
+#[tracing::instrument(skip_all, fields(w_id = warehouse_id))]
 async fn get_and_update_warehouse(
     tx: &mut Transaction<'_, Postgres>,
     warehouse_id: i16,
     payment_amount: &BigDecimal,
-) -> Result<WarehouseData, StatusCode> {
-    // Get current warehouse data
+) -> Result<WarehouseData, ApiError> {
+    // Add the payment to the current YTD in the UPDATE itself, so concurrent
+    // payments to the warehouse queue on the row lock rather than lose increments
     let row = sqlx::query!(
         r#"
-        SELECT w_name, w_street_1, w_street_2, w_city, w_state, w_zip, w_ytd
-        FROM warehouse1 WHERE w_id = $1
+        UPDATE warehouse1 SET w_ytd = COALESCE(w_ytd, 0) + $1
+        WHERE w_id = $2
+        RETURNING w_name, w_street_1, w_street_2, w_city, w_state, w_zip, w_ytd AS "w_ytd!"
         "#,
+        payment_amount,
         warehouse_id
     )
     .fetch_optional(&mut **tx)
     .await
-    .map_err(|e| {
-        eprintln!("Database error fetching warehouse: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    .map_err(|e| ApiError::database("updating warehouse", e))?;
 
     let warehouse_row = match row {
         Some(row) => row,
-        None => return Err(StatusCode::NOT_FOUND),
+        None => return Err(ApiError::NotFound(Entity::Warehouse { warehouse_id })),
     };
 
-    let current_ytd = warehouse_row.w_ytd.unwrap_or_else(|| BigDecimal::from(0));
-    let new_ytd = &current_ytd + payment_amount;
-
-    // Update warehouse YTD
-    sqlx::query!(
-        "UPDATE warehouse1 SET w_ytd = $1 WHERE w_id = $2",
-        new_ytd,
-        warehouse_id
-    )
-    .execute(&mut **tx)
-    .await
-    .map_err(|e| {
-        eprintln!("Database error updating warehouse: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
-
     Ok(WarehouseData {
         w_name: warehouse_row.w_name.unwrap_or_default(),
         w_street_1: warehouse_row.w_street_1.unwrap_or_default(),
@@ -262,54 +462,42 @@ async fn get_and_update_warehouse(
         w_city: warehouse_row.w_city.unwrap_or_default(),
         w_state: warehouse_row.w_state.unwrap_or_default(),
         w_zip: warehouse_row.w_zip.unwrap_or_default(),
-        w_ytd: new_ytd,
+        w_ytd: warehouse_row.w_ytd,
     })
 }
 
+#[tracing::instrument(skip_all, fields(w_id = warehouse_id, d_id = district_id))]
 async fn get_and_update_district(
     tx: &mut Transaction<'_, Postgres>,
     warehouse_id: i16,
     district_id: i16,
     payment_amount: &BigDecimal,
-) -> Result<DistrictData, StatusCode> {
-    // Get current district data
+) -> Result<DistrictData, ApiError> {
+    // Same as the warehouse: the increment happens in the database
     let row = sqlx::query!(
         r#"
-        SELECT d_name, d_street_1, d_street_2, d_city, d_state, d_zip, d_ytd
-        FROM district1 WHERE d_w_id = $1 AND d_id = $2
+        UPDATE district1 SET d_ytd = COALESCE(d_ytd, 0) + $1
+        WHERE d_w_id = $2 AND d_id = $3
+        RETURNING d_name, d_street_1, d_street_2, d_city, d_state, d_zip, d_ytd AS "d_ytd!"
         "#,
+        payment_amount,
         warehouse_id,
         district_id
     )
     .fetch_optional(&mut **tx)
     .await
-    .map_err(|e| {
-        eprintln!("Database error fetching district: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    .map_err(|e| ApiError::database("updating district", e))?;
 
     let district_row = match row {
         Some(row) => row,
-        None => return Err(StatusCode::NOT_FOUND),
+        None => {
+            return Err(ApiError::NotFound(Entity::District {
+                warehouse_id,
+                district_id,
+            }))
+        }
     };
 
-    let current_ytd = district_row.d_ytd.unwrap_or_else(|| BigDecimal::from(0));
-    let new_ytd = &current_ytd + payment_amount;
-
-    // Update district YTD
-    sqlx::query!(
-        "UPDATE district1 SET d_ytd = $1 WHERE d_w_id = $2 AND d_id = $3",
-        new_ytd,
-        warehouse_id,
-        district_id
-    )
-    .execute(&mut **tx)
-    .await
-    .map_err(|e| {
-        eprintln!("Database error updating district: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
-
     Ok(DistrictData {
         d_name: district_row.d_name.unwrap_or_default(),
         d_street_1: district_row.d_street_1.unwrap_or_default(),
@@ -317,104 +505,101 @@ async fn get_and_update_district(
         d_city: district_row.d_city.unwrap_or_default(),
         d_state: district_row.d_state.unwrap_or_default(),
         d_zip: district_row.d_zip.unwrap_or_default(),
-        d_ytd: new_ytd,
+        d_ytd: district_row.d_ytd,
     })
 }
 
+// warehouse_id/district_id identify the paying district (recorded in BC c_data),
+// customer_warehouse_id/customer_district_id the customer's home district
+#[tracing::instrument(
+    skip_all,
+    fields(c_w_id = customer_warehouse_id, c_d_id = customer_district_id, c_id = tracing::field::Empty)
+)]
 async fn get_and_update_customer(
     tx: &mut Transaction<'_, Postgres>,
     warehouse_id: i16,
     district_id: i16,
-    customer_id: i32,
+    customer_warehouse_id: i16,
+    customer_district_id: i16,
+    customer_selector: &CustomerSelector,
     payment_amount: &BigDecimal,
-) -> Result<CustomerData, StatusCode> {
-    // Get current customer data
+) -> Result<CustomerData, ApiError> {
+    // Resolve the customer ID, picking the middle customer when selecting by last name
+    let customer_id = match customer_selector {
+        CustomerSelector::Id(customer_id) => *customer_id,
+        CustomerSelector::LastName(last_name) => {
+            find_customer_by_last_name(
+                &mut **tx,
+                customer_warehouse_id,
+                customer_district_id,
+                last_name,
+            )
+            .await
+            .map_err(|e| ApiError::database("selecting customer by last name", e))?
+            .ok_or_else(|| {
+                ApiError::NotFound(Entity::CustomerLastName {
+                    warehouse_id: customer_warehouse_id,
+                    district_id: customer_district_id,
+                    customer_last_name: last_name.clone(),
+                })
+            })?
+            .c_id
+        }
+    };
+
+    // For bad credit customers the payment is prepended to C_DATA (TPC-C 2.5.2.2),
+    // which is cut to 500 characters
+    let payment_info = format!(
+        "{}|{}|{}|{}|{}|{}|{}|",
+        customer_id,
+        customer_district_id,
+        customer_warehouse_id,
+        district_id,
+        warehouse_id,
+        payment_amount,
+        payment_amount
+    );
+
+    // Balance, YTD payment, count and C_DATA are all derived from the row's
+    // current values in one statement, so concurrent payments cannot lose updates
     let row = sqlx::query!(
         r#"
-        SELECT c_first, c_middle, c_last, c_street_1, c_street_2, c_city, c_state, c_zip,
-               c_phone, c_since, c_credit, c_credit_lim, c_discount, c_balance, 
-               c_ytd_payment, c_payment_cnt, c_data
-        FROM customer1 WHERE c_w_id = $1 AND c_d_id = $2 AND c_id = $3
+        UPDATE customer1
+        SET c_balance = COALESCE(c_balance, 0) - $1,
+            c_ytd_payment = COALESCE(c_ytd_payment, 0) + $1,
+            c_payment_cnt = COALESCE(c_payment_cnt, 0) + 1,
+            c_data = CASE WHEN c_credit = 'BC' THEN left($2 || COALESCE(c_data, ''), 500)
+                          ELSE c_data
+                     END
+        WHERE c_w_id = $3 AND c_d_id = $4 AND c_id = $5
+        RETURNING c_first, c_middle, c_last, c_street_1, c_street_2, c_city, c_state, c_zip,
+                  c_phone, c_since, c_credit, c_credit_lim, c_discount,
+                  c_balance AS "c_balance!", c_ytd_payment AS "c_ytd_payment!",
+                  c_payment_cnt AS "c_payment_cnt!", c_data
         "#,
-        warehouse_id,
-        district_id,
+        payment_amount,
+        payment_info,
+        customer_warehouse_id,
+        customer_district_id,
         customer_id
     )
     .fetch_optional(&mut **tx)
     .await
-    .map_err(|e| {
-        eprintln!("Database error fetching customer: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    .map_err(|e| ApiError::database("updating customer", e))?;
 
     let customer_row = match row {
         Some(row) => row,
-        None => return Err(StatusCode::NOT_FOUND),
-    };
-
-    // Calculate new values
-    let current_balance = customer_row
-        .c_balance
-        .unwrap_or_else(|| BigDecimal::from(0));
-    let new_balance = &current_balance - payment_amount; // Payment decreases balance
-
-    let current_ytd_payment = customer_row
-        .c_ytd_payment
-        .unwrap_or_else(|| BigDecimal::from(0));
-    let new_ytd_payment = &current_ytd_payment + payment_amount;
-
-    let current_payment_cnt = customer_row.c_payment_cnt.unwrap_or(0);
-    let new_payment_cnt = current_payment_cnt + 1;
-
-    // Handle credit data update for "BC" (Bad Credit) customers
-    let credit = customer_row.c_credit.as_deref().unwrap_or("");
-    let new_c_data = if credit == "BC" {
-        // For bad credit customers, prepend payment info to c_data (TPC-C requirement)
-        let payment_info = format!(
-            "{}|{}|{}|{}|{}|{}|{}|",
-            customer_id,
-            district_id,
-            warehouse_id,
-            district_id,
-            warehouse_id,
-            payment_amount,
-            payment_amount
-        );
-        let existing_data = customer_row.c_data.unwrap_or_default();
-        let combined = format!("{}{}", payment_info, existing_data);
-        // Truncate to maximum c_data length (500 chars typically)
-        if combined.len() > 500 {
-            combined[..500].to_string()
-        } else {
-            combined
+        None => {
+            return Err(ApiError::NotFound(Entity::Customer {
+                warehouse_id: customer_warehouse_id,
+                district_id: customer_district_id,
+                customer_id,
+            }))
         }
-    } else {
-        customer_row.c_data.unwrap_or_default()
     };
 
-    // Update customer
-    sqlx::query!(
-        r#"
-        UPDATE customer1 
-        SET c_balance = $1, c_ytd_payment = $2, c_payment_cnt = $3, c_data = $4
-        WHERE c_w_id = $5 AND c_d_id = $6 AND c_id = $7
-        "#,
-        new_balance,
-        new_ytd_payment,
-        new_payment_cnt,
-        new_c_data,
-        warehouse_id,
-        district_id,
-        customer_id
-    )
-    .execute(&mut **tx)
-    .await
-    .map_err(|e| {
-        eprintln!("Database error updating customer: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
-
     Ok(CustomerData {
+        c_id: customer_id,
         c_first: customer_row.c_first.unwrap_or_default(),
         c_middle: customer_row.c_middle.unwrap_or_default(),
         c_last: customer_row.c_last.unwrap_or_default(),
@@ -430,16 +615,18 @@ async fn get_and_update_customer(
         c_discount: customer_row
             .c_discount
             .unwrap_or_else(|| BigDecimal::from(0)),
-        c_balance: new_balance,
-        c_ytd_payment: new_ytd_payment,
-        c_payment_cnt: new_payment_cnt,
-        c_data: new_c_data,
+        c_balance: customer_row.c_balance,
+        c_ytd_payment: customer_row.c_ytd_payment,
+        c_payment_cnt: customer_row.c_payment_cnt,
+        c_data: customer_row.c_data.unwrap_or_default(),
     })
 }
 
 struct PaymentHistoryParams {
     warehouse_id: i16,
     district_id: i16,
+    customer_warehouse_id: i16,
+    customer_district_id: i16,
     customer_id: i32,
     payment_amount: BigDecimal,
     payment_date: NaiveDateTime,
@@ -447,10 +634,14 @@ struct PaymentHistoryParams {
     district_name: String,
 }
 
+#[tracing::instrument(
+    skip_all,
+    fields(w_id = params.warehouse_id, d_id = params.district_id, c_id = params.customer_id)
+)]
 async fn insert_payment_history(
     tx: &mut Transaction<'_, Postgres>,
     params: PaymentHistoryParams,
-) -> Result<(), StatusCode> {
+) -> Result<(), ApiError> {
     // Create history data string (TPC-C format)
     let h_data = format!("{} {}", params.warehouse_name, params.district_name);
 
@@ -460,8 +651,8 @@ async fn insert_payment_history(
         VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
         "#,
         params.customer_id,
-        params.district_id,
-        params.warehouse_id,
+        params.customer_district_id,
+        params.customer_warehouse_id,
         params.district_id,
         params.warehouse_id,
         params.payment_date,
@@ -470,10 +661,7 @@ async fn insert_payment_history(
     )
     .execute(&mut **tx)
     .await
-    .map_err(|e| {
-        eprintln!("Database error inserting payment history: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    .map_err(|e| ApiError::database("inserting payment history", e))?;
 
     Ok(())
 }
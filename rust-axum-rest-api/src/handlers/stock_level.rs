@@ -1,11 +1,15 @@
-use axum::{
-    extract::{Query, State},
-    http::StatusCode,
-    Json,
-};
+use axum::{extract::State, Json};
 use serde::{Deserialize, Serialize};
 use sqlx::{Pool, Postgres};
-#[derive(Deserialize)]
+
+use crate::error::{ApiError, Entity};
+use crate::extract::ApiQuery;
+use crate::table_set::TableSet;
+use crate::transaction::{
+    begin, run_transaction, Attempted, ExecutionMode, IsolationLevel, TransactionSettings,
+};
+
+#[derive(Deserialize, Serialize)]
 pub struct StockLevelQuery {
     pub warehouse_id: i16,
     pub district_id: i16,
@@ -21,22 +25,60 @@ pub struct StockLevelResponse {
 }
 
 pub async fn stock_level(
-    State(pool): State<Pool<Postgres>>,
-    Query(params): Query<StockLevelQuery>,
-) -> Result<Json<StockLevelResponse>, StatusCode> {
+    TableSet { transactions, .. }: TableSet,
+    State(settings): State<TransactionSettings>,
+    ApiQuery(params): ApiQuery<StockLevelQuery>,
+) -> Result<Attempted<Json<StockLevelResponse>>, ApiError> {
+    let attempted = transactions.stock_level(&settings, &params).await?;
+    Ok(attempted.map(Json))
+}
+
+// Stock-Level with retries, without HTTP; the handler and the in-process driver call this
+pub async fn execute_stock_level(
+    pool: &Pool<Postgres>,
+    settings: &TransactionSettings,
+    params: &StockLevelQuery,
+) -> Result<Attempted<StockLevelResponse>, ApiError> {
+    let isolation = settings.isolation_levels.stock_level;
+    run_transaction(&settings.retry_policy, "stock-level", || async {
+        match settings.execution_mode {
+            ExecutionMode::Inline => process_stock_level(pool, isolation, params).await,
+            ExecutionMode::StoredProcedures => call_stock_level(pool, isolation, params).await,
+        }
+    })
+    .await
+}
+
+#[tracing::instrument(
+    name = "stock_level",
+    skip_all,
+    fields(w_id = params.warehouse_id, d_id = params.district_id, threshold = params.threshold)
+)]
+async fn process_stock_level(
+    pool: &Pool<Postgres>,
+    isolation: Option<IsolationLevel>,
+    params: &StockLevelQuery,
+) -> Result<StockLevelResponse, ApiError> {
+    let mut tx = begin(pool, isolation).await?;
+
     // First, get the district's next order ID
     let d_next_o_id_result = sqlx::query_scalar!(
         "SELECT d_next_o_id FROM district1 WHERE d_id = $1 AND d_w_id = $2",
         params.district_id,
         params.warehouse_id
     )
-    .fetch_optional(&pool)
+    .fetch_optional(&mut *tx)
     .await
-    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    .map_err(|e| ApiError::database("fetching district", e))?;
 
     let d_next_o_id = match d_next_o_id_result {
         Some(Some(id)) => id,
-        _ => return Err(StatusCode::NOT_FOUND),
+        _ => {
+            return Err(ApiError::NotFound(Entity::District {
+                warehouse_id: params.warehouse_id,
+                district_id: params.district_id,
+            }))
+        }
     };
 
     // This implements the TPC-C stock level transaction using the specification approach
@@ -62,17 +104,59 @@ pub async fn stock_level(
         d_next_o_id - 20,
         params.threshold
     )
-    .fetch_one(&pool)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| ApiError::database("counting low stock items", e))?;
+
+    tx.commit()
+        .await
+        .map_err(|e| ApiError::database("committing transaction", e))?;
+
+    Ok(StockLevelResponse {
+        warehouse_id: params.warehouse_id,
+        district_id: params.district_id,
+        threshold: params.threshold,
+        low_stock_count,
+    })
+}
+
+// Stock-Level as one call to tpcc_stock_level
+#[tracing::instrument(
+    name = "tpcc_stock_level",
+    skip_all,
+    fields(w_id = params.warehouse_id, d_id = params.district_id, threshold = params.threshold)
+)]
+async fn call_stock_level(
+    pool: &Pool<Postgres>,
+    isolation: Option<IsolationLevel>,
+    params: &StockLevelQuery,
+) -> Result<StockLevelResponse, ApiError> {
+    let mut tx = begin(pool, isolation).await?;
+    let low_stock_count = sqlx::query_scalar!(
+        r#"SELECT low_stock_count AS "low_stock_count!" FROM tpcc_stock_level($1, $2, $3)"#,
+        params.warehouse_id,
+        params.district_id,
+        params.threshold
+    )
+    .fetch_one(&mut *tx)
     .await
     .map_err(|e| {
-        eprintln!("Database error: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
+        ApiError::procedure("calling tpcc_stock_level", e, |row| {
+            (row.table == "district").then_some(Entity::District {
+                warehouse_id: params.warehouse_id,
+                district_id: params.district_id,
+            })
+        })
     })?;
 
-    Ok(Json(StockLevelResponse {
+    tx.commit()
+        .await
+        .map_err(|e| ApiError::database("committing transaction", e))?;
+
+    Ok(StockLevelResponse {
         warehouse_id: params.warehouse_id,
         district_id: params.district_id,
         threshold: params.threshold,
         low_stock_count,
-    }))
+    })
 }
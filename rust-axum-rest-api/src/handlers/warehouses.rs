@@ -1,18 +1,19 @@
-use axum::{extract::State, http::StatusCode, Json};
-use sqlx::{Pool, Postgres};
+use axum::Json;
 
+use crate::error::ApiError;
 use crate::models::Warehouse;
+use crate::table_set::TableSet;
 
 pub async fn get_warehouses(
-    State(pool): State<Pool<Postgres>>,
-) -> Result<Json<Vec<Warehouse>>, StatusCode> {
+    TableSet { pool, .. }: TableSet,
+) -> Result<Json<Vec<Warehouse>>, ApiError> {
     let warehouses = sqlx::query_as!(
         Warehouse,
         "SELECT w_id, w_name, w_street_1, w_street_2, w_city, w_state, w_zip, w_tax, w_ytd FROM warehouse1 ORDER BY w_id ASC LIMIT 100"
     )
     .fetch_all(&pool)
     .await
-    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    .map_err(|e| ApiError::database("fetching warehouses", e))?;
 
     Ok(Json(warehouses))
 }
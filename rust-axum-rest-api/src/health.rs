@@ -0,0 +1,205 @@
+// Readiness of the database behind the API: reachable, every table set with all
+// nine tables and the indexes of 002_create_indexes.sql, and populated
+use serde::Serialize;
+use sqlx::{Pool, Postgres};
+use std::collections::BTreeSet;
+use std::time::{Duration, Instant};
+
+use crate::handlers::delivery::DISTRICTS_PER_WAREHOUSE;
+use crate::load::ITEMS;
+use crate::table_set::{TableSets, TPCC_TABLES};
+
+// A probe that takes longer than this reports the database as unavailable
+pub const READINESS_TIMEOUT: Duration = Duration::from_secs(5);
+
+// The secondary indexes of 002_create_indexes.sql: name prefix (sysbench-tpcc
+// appends the table set number), table and columns. Sets created with
+// CREATE TABLE ... LIKE get generated names, so indexes are matched by columns.
+const INDEXES: [(&str, &str, &str); 6] = [
+    ("idx_customer", "customer", "c_w_id,c_d_id,c_last,c_first"),
+    ("idx_orders", "orders", "o_w_id,o_d_id,o_c_id,o_id"),
+    ("fkey_stock_2", "stock", "s_i_id"),
+    ("fkey_order_line_2", "order_line", "ol_supply_w_id,ol_i_id"),
+    ("fkey_history_1", "history", "h_c_w_id,h_c_d_id,h_c_id"),
+    ("fkey_history_2", "history", "h_w_id,h_d_id"),
+];
+
+#[derive(Serialize)]
+pub struct DatabaseStatus {
+    pub connected: bool,
+    pub latency_ms: f64,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct TableSetStatus {
+    pub table_set: u32,
+    pub missing_tables: Vec<String>,
+    pub missing_indexes: Vec<String>,
+    pub warehouses: i64,
+    pub districts: i64,
+    pub items: i64,
+    // At least one warehouse, all of its districts and the full item catalog
+    pub loaded: bool,
+    pub error: Option<String>,
+}
+
+impl TableSetStatus {
+    fn ready(&self) -> bool {
+        self.error.is_none()
+            && self.missing_tables.is_empty()
+            && self.missing_indexes.is_empty()
+            && self.loaded
+    }
+}
+
+#[derive(Serialize)]
+pub struct ReadinessReport {
+    pub ready: bool,
+    pub database: DatabaseStatus,
+    pub table_sets: Vec<TableSetStatus>,
+}
+
+// Check the database and every table set the server serves
+pub async fn check(pool: &Pool<Postgres>, table_sets: &TableSets) -> ReadinessReport {
+    let started = Instant::now();
+    let ping = tokio::time::timeout(READINESS_TIMEOUT, sqlx::query("SELECT 1").execute(pool)).await;
+    let database = DatabaseStatus {
+        connected: matches!(ping, Ok(Ok(_))),
+        latency_ms: started.elapsed().as_secs_f64() * 1000.0,
+        error: match ping {
+            Ok(Ok(_)) => None,
+            Ok(Err(e)) => Some(e.to_string()),
+            Err(_) => Some(format!(
+                "no response within {}s",
+                READINESS_TIMEOUT.as_secs()
+            )),
+        },
+    };
+    if !database.connected {
+        return ReadinessReport {
+            ready: false,
+            database,
+            table_sets: Vec::new(),
+        };
+    }
+
+    let mut statuses = Vec::new();
+    for number in table_sets.numbers() {
+        let status =
+            match tokio::time::timeout(READINESS_TIMEOUT, check_table_set(pool, number)).await {
+                Ok(Ok(status)) => status,
+                Ok(Err(e)) => failed_table_set(number, e.to_string()),
+                Err(_) => failed_table_set(
+                    number,
+                    format!("no response within {}s", READINESS_TIMEOUT.as_secs()),
+                ),
+            };
+        statuses.push(status);
+    }
+
+    ReadinessReport {
+        ready: statuses.iter().all(TableSetStatus::ready),
+        database,
+        table_sets: statuses,
+    }
+}
+
+fn failed_table_set(table_set: u32, error: String) -> TableSetStatus {
+    TableSetStatus {
+        table_set,
+        missing_tables: Vec::new(),
+        missing_indexes: Vec::new(),
+        warehouses: 0,
+        districts: 0,
+        items: 0,
+        loaded: false,
+        error: Some(error),
+    }
+}
+
+// Tables and counts come from the base tables (warehouseK, ...), not the views
+// of the table set's pool, since only the tables carry indexes
+async fn check_table_set(
+    pool: &Pool<Postgres>,
+    number: u32,
+) -> Result<TableSetStatus, sqlx::Error> {
+    let table_names: Vec<String> = TPCC_TABLES
+        .iter()
+        .map(|table| format!("{}{}", table, number))
+        .collect();
+
+    let existing: BTreeSet<String> = sqlx::query_scalar!(
+        r#"SELECT tablename::text AS "tablename!" FROM pg_tables
+           WHERE schemaname = current_schema() AND tablename = ANY($1)"#,
+        &table_names
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .collect();
+    let missing_tables: Vec<String> = table_names
+        .iter()
+        .filter(|name| !existing.contains(*name))
+        .cloned()
+        .collect();
+
+    // Column lists of the existing indexes, as "table:col1,col2"
+    let indexes: BTreeSet<String> = sqlx::query!(
+        r#"SELECT tablename::text AS "tablename!", indexdef AS "indexdef!" FROM pg_indexes
+           WHERE schemaname = current_schema() AND tablename = ANY($1)"#,
+        &table_names
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .filter_map(|row| {
+        Some(format!(
+            "{}:{}",
+            row.tablename,
+            index_columns(&row.indexdef)?
+        ))
+    })
+    .collect();
+    let missing_indexes = INDEXES
+        .iter()
+        .filter(|(_, table, columns)| {
+            !indexes.contains(&format!("{}{}:{}", table, number, columns))
+        })
+        .map(|(prefix, table, columns)| {
+            format!("{}{} ON {}{} ({})", prefix, number, table, number, columns)
+        })
+        .collect();
+
+    let (warehouses, districts, items) = if missing_tables.is_empty() {
+        sqlx::query_as(&format!(
+            "SELECT (SELECT count(*) FROM warehouse{n}), (SELECT count(*) FROM district{n}), \
+             (SELECT count(*) FROM item{n})",
+            n = number
+        ))
+        .fetch_one(pool)
+        .await?
+    } else {
+        (0, 0, 0)
+    };
+
+    Ok(TableSetStatus {
+        table_set: number,
+        missing_tables,
+        missing_indexes,
+        warehouses,
+        districts,
+        items,
+        loaded: warehouses > 0
+            && districts == warehouses * DISTRICTS_PER_WAREHOUSE as i64
+            && items == ITEMS as i64,
+        error: None,
+    })
+}
+
+// "CREATE INDEX ... USING btree (c_w_id, c_d_id)" -> "c_w_id,c_d_id"
+fn index_columns(indexdef: &str) -> Option<String> {
+    let (_, columns) = indexdef.split_once(" USING ")?;
+    let columns = columns.split_once('(')?.1.rsplit_once(')')?.0;
+    Some(columns.chars().filter(|c| !c.is_whitespace()).collect())
+}
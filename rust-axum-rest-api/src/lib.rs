@@ -1,29 +1,120 @@
 use axum::{
+    http::HeaderValue,
+    middleware,
     routing::{get, post},
     Router,
 };
 use sqlx::{Pool, Postgres};
 use tower_http::{
-    cors::{Any, CorsLayer},
+    cors::{AllowOrigin, Any, CorsLayer},
     services::{ServeDir, ServeFile},
+    trace::{DefaultMakeSpan, TraceLayer},
 };
+use tracing::Level;
 
+pub mod config;
+pub mod consistency;
+pub mod delivery_queue;
+pub mod drive;
+pub mod error;
+pub mod extract;
 pub mod handlers;
+pub mod health;
+pub mod load;
+pub mod metrics;
+pub mod migrate;
 pub mod models;
+#[cfg(feature = "pipelined-driver")]
+pub mod pipelined;
+pub mod random;
+pub mod state;
+pub mod table_set;
+pub mod telemetry;
+pub mod transaction;
+pub mod transaction_pool;
 
+use config::{DatabaseConfig, ServerConfig};
+use delivery_queue::DeliveryQueue;
 use handlers::*;
+use state::AppState;
+use table_set::TableSets;
+use transaction::TransactionSettings;
 
 // Factory function to create the app router
 pub async fn create_app(pool: Pool<Postgres>) -> Router {
+    create_app_with_config(pool, &ServerConfig::default()).await
+}
+
+pub async fn create_app_with_config(pool: Pool<Postgres>, config: &ServerConfig) -> Router {
+    create_router(create_state(pool).await, config)
+}
+
+// Shared state of the handlers; the server keeps a clone to shut down cleanly.
+// Panics if TX_DRIVER names a driver that cannot be set up.
+pub async fn create_state(pool: Pool<Postgres>) -> AppState {
+    let database = DatabaseConfig {
+        url: std::env::var("DATABASE_URL").ok(),
+        ..DatabaseConfig::default()
+    };
+    create_state_with_config(pool, &database)
+        .await
+        .unwrap_or_else(|e| panic!("{}", e))
+}
+
+// `database` configures the pools of the pipelined driver (TX_DRIVER=pipelined)
+pub async fn create_state_with_config(
+    pool: Pool<Postgres>,
+    database: &DatabaseConfig,
+) -> Result<AppState, String> {
+    // Background workers for deferred (queued) Delivery
+    let delivery_workers = std::env::var("DELIVERY_WORKERS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(2);
+    let transaction_settings = TransactionSettings::from_env();
+    tracing::info!(
+        "Transaction isolation levels: {:?}",
+        transaction_settings.isolation_levels
+    );
+    let table_sets = TableSets::discover(&pool)
+        .await
+        .with_driver(transaction_settings.driver, database)
+        .map_err(|e| format!("TX_DRIVER={}: {}", transaction_settings.driver, e))?;
+    // The pipelined driver shares out the configured pool's connections but the one
+    // kept for the health checks
+    let pool = table_sets.health_pool(&pool);
+    tracing::info!(
+        "Driver: {}, execution mode: {}, New-Order path: {}",
+        table_sets.driver(),
+        transaction_settings.execution_mode,
+        transaction_settings.new_order_path
+    );
+    Ok(AppState {
+        table_sets,
+        delivery_queue: DeliveryQueue::start(
+            delivery_workers,
+            transaction_settings.retry_policy,
+            transaction_settings.isolation_levels.delivery,
+            transaction_settings.execution_mode,
+        ),
+        pool,
+        transaction_settings,
+        new_order_stats: NewOrderStats::default(),
+    })
+}
+
+pub fn create_router(state: AppState, config: &ServerConfig) -> Router {
     // Configure CORS for development
     let cors = CorsLayer::new()
-        .allow_origin(Any)
+        .allow_origin(allowed_origins(&config.cors_origins))
         .allow_methods(Any)
         .allow_headers(Any);
 
     // API routes without CORS initially
     let api_routes = Router::new()
         .route("/", get(root))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
         .route("/warehouses", get(get_warehouses))
         .route("/districts", get(get_districts))
         .route("/customers", get(search_customers))
@@ -33,43 +124,77 @@ pub async fn create_app(pool: Pool<Postgres>) -> Router {
         .route("/order-status", get(order_status))
         .route("/orders", get(list_orders))
         .route("/new-order", post(new_order))
+        .route("/new-order/stats", get(new_order_stats))
         .route("/payment", post(payment))
         .route("/delivery", post(delivery))
-        .with_state(pool);
+        .route("/delivery/{ticket}", get(deferred_delivery_status))
+        .route("/admin/consistency", get(check_consistency))
+        .route_layer(middleware::from_fn(metrics::track_requests))
+        // Root span of each request; transaction and step spans nest inside it
+        .layer(TraceLayer::new_for_http().make_span_with(DefaultMakeSpan::new().level(Level::INFO)))
+        .with_state(state.clone());
+
+    // Prometheus scrapes /metrics at the root in both serving modes
+    let metrics_routes = Router::new()
+        .route("/metrics", get(get_metrics))
+        .with_state(state.clone());
+
+    // Probes also answer at the root when the API is nested under /api
+    let probe_routes = Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .with_state(state);
 
-    // Determine serving mode based on environment variable or debug/release build
-    let serve_frontend = std::env::var("SERVE_FRONTEND")
-        .map(|v| v == "true" || v == "1")
-        .unwrap_or_else(|_| {
-            // Default: serve frontend only in release builds
-            !cfg!(debug_assertions)
-        });
+    // Determine serving mode based on configuration or debug/release build
+    // Default: serve frontend only in release builds
+    let serve_frontend = config.serve_frontend.unwrap_or(!cfg!(debug_assertions));
 
-    let frontend_dist = std::path::Path::new("../ui-vite-react/dist");
+    let frontend_dist = config.frontend_dist.as_path();
+    let base_url = format!("http://{}:{}", config.host, config.port);
 
     if serve_frontend && frontend_dist.exists() {
         // Frontend serving mode: serve built frontend and API
         // No CORS needed since frontend and API are served from same origin
         tracing::info!("🚀 Starting in COMBINED mode (frontend + API)");
-        tracing::info!("   Frontend: http://localhost:8080/");
-        tracing::info!("   API: http://localhost:8080/api/");
+        tracing::info!("   Frontend: {}/", base_url);
+        tracing::info!("   API: {}/api/", base_url);
         Router::new()
             .nest("/api", api_routes)
+            .merge(metrics_routes)
+            .merge(probe_routes)
             .nest_service("/assets", ServeDir::new(frontend_dist.join("assets")))
             .route_service("/", ServeFile::new(frontend_dist.join("index.html")))
             .fallback_service(ServeFile::new(frontend_dist.join("index.html")))
     } else {
         // API-only mode: API with CORS for cross-origin requests from Vite dev server
         tracing::info!("🔧 Starting in API-ONLY mode");
-        tracing::info!("   API: http://localhost:8080/");
+        tracing::info!("   API: {}/", base_url);
         tracing::info!("   Frontend: Start with 'cd ../ui-vite-react && npm run dev'");
         if !frontend_dist.exists() {
             tracing::info!(
                 "   Note: No built frontend found. Run 'npm run build' to enable combined mode."
             );
         }
-        api_routes.layer(cors)
+        api_routes.merge(metrics_routes).layer(cors)
+    }
+}
+
+// "*" allows any origin; invalid origins are skipped with a warning
+fn allowed_origins(origins: &[String]) -> AllowOrigin {
+    if origins.iter().any(|origin| origin == "*") {
+        return AllowOrigin::any();
     }
+    AllowOrigin::list(
+        origins
+            .iter()
+            .filter_map(|origin| match HeaderValue::from_str(origin) {
+                Ok(value) => Some(value),
+                Err(_) => {
+                    tracing::warn!("Ignoring invalid CORS origin {:?}", origin);
+                    None
+                }
+            }),
+    )
 }
 
 // Root handler
@@ -0,0 +1,462 @@
+// Initial TPC-C population (clause 4.3.3.1), written with COPY
+use chrono::{NaiveDateTime, Utc};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use sqlx::pool::PoolConnection;
+use sqlx::postgres::{PgCopyIn, PgPoolCopyExt};
+use sqlx::{Pool, Postgres};
+use std::fmt::Write;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+use crate::handlers::delivery::DISTRICTS_PER_WAREHOUSE;
+use crate::migrate::MIGRATOR;
+use crate::random::{self, NuRandConstants};
+use crate::table_set::{self, TPCC_TABLES};
+
+pub const ITEMS: i32 = 100_000;
+pub const CUSTOMERS_PER_DISTRICT: i32 = 3000;
+pub const ORDERS_PER_DISTRICT: i32 = 3000;
+// Orders 2101..=3000 of each district are undelivered
+pub const FIRST_NEW_ORDER: i32 = 2101;
+
+// Rows are sent to the server in chunks of about this size
+const COPY_CHUNK_BYTES: usize = 1 << 20;
+
+pub struct LoadOptions {
+    pub warehouses: i16,
+    pub table_sets: Vec<u32>,
+    // Warehouses loaded concurrently, each over its own connection
+    pub jobs: usize,
+    // Share of customers with bad credit (C_CREDIT = "BC"); 10 in the specification
+    pub bad_credit_percent: u32,
+    // Share of items and stock with "ORIGINAL" in their data; 10 in the specification
+    pub original_percent: u32,
+}
+
+pub struct LoadSummary {
+    pub nurand: NuRandConstants,
+    pub rows: u64,
+}
+
+// Create table set 1 with the crate's migrations, and any other set with the same
+// layout and its views
+pub async fn create_schema(pool: &Pool<Postgres>, table_sets: &[u32]) -> Result<(), sqlx::Error> {
+    MIGRATOR.run(pool).await?;
+
+    for &table_set in table_sets.iter().filter(|&&set| set != 1) {
+        for table in TPCC_TABLES {
+            sqlx::query(&format!(
+                "CREATE TABLE IF NOT EXISTS {table}{table_set} (LIKE {table}1 INCLUDING ALL)"
+            ))
+            .execute(pool)
+            .await?;
+        }
+    }
+    table_set::create_views(pool).await?;
+    Ok(())
+}
+
+// Empty every table of a table set
+pub async fn truncate(pool: &Pool<Postgres>, table_set: u32) -> Result<(), sqlx::Error> {
+    let tables = TPCC_TABLES
+        .iter()
+        .map(|table| format!("{}{}", table, table_set))
+        .collect::<Vec<_>>()
+        .join(", ");
+    sqlx::query(&format!("TRUNCATE {}", tables))
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+// Load ITEM once per table set and every warehouse, `jobs` warehouses at a time
+pub async fn load(
+    pool: &Pool<Postgres>,
+    options: &LoadOptions,
+) -> Result<LoadSummary, sqlx::Error> {
+    // One C_LAST constant for the whole population; the driver derives its own from it
+    let nurand = NuRandConstants::random(&mut rand::thread_rng());
+    let load_date = Utc::now().naive_utc();
+    let permits = Arc::new(Semaphore::new(options.jobs.max(1)));
+    let mut tasks = JoinSet::new();
+
+    for &table_set in &options.table_sets {
+        let item_pool = pool.clone();
+        let item_permits = Arc::clone(&permits);
+        let original_percent = options.original_percent;
+        tasks.spawn(async move {
+            let _permit = item_permits
+                .acquire_owned()
+                .await
+                .expect("semaphore closed");
+            load_items(&item_pool, table_set, original_percent).await
+        });
+
+        for warehouse_id in 1..=options.warehouses {
+            let pool = pool.clone();
+            let permits = Arc::clone(&permits);
+            let warehouse = WarehouseLoad {
+                table_set,
+                warehouse_id,
+                load_date,
+                nurand,
+                bad_credit_percent: options.bad_credit_percent,
+                original_percent: options.original_percent,
+            };
+            tasks.spawn(async move {
+                let _permit = permits.acquire_owned().await.expect("semaphore closed");
+                warehouse.load(&pool).await
+            });
+        }
+    }
+
+    let mut rows = 0;
+    while let Some(result) = tasks.join_next().await {
+        rows += result.expect("load task panicked")?;
+    }
+
+    for &table_set in &options.table_sets {
+        for table in TPCC_TABLES {
+            sqlx::query(&format!("ANALYZE {}{}", table, table_set))
+                .execute(pool)
+                .await?;
+        }
+    }
+
+    Ok(LoadSummary { nurand, rows })
+}
+
+// A COPY FROM STDIN in text format, buffered into chunks
+struct CopyWriter {
+    copy: PgCopyIn<PoolConnection<Postgres>>,
+    buffer: String,
+    rows: u64,
+}
+
+impl CopyWriter {
+    async fn start(
+        pool: &Pool<Postgres>,
+        table: &str,
+        table_set: u32,
+        columns: &str,
+    ) -> Result<Self, sqlx::Error> {
+        let copy = pool
+            .copy_in_raw(&format!(
+                "COPY {}{} ({}) FROM STDIN",
+                table, table_set, columns
+            ))
+            .await?;
+        Ok(CopyWriter {
+            copy,
+            buffer: String::with_capacity(COPY_CHUNK_BYTES + 4096),
+            rows: 0,
+        })
+    }
+
+    // Terminate the row written into `buffer`, sending the buffer once it is full
+    async fn end_row(&mut self) -> Result<(), sqlx::Error> {
+        self.buffer.push('\n');
+        self.rows += 1;
+        if self.buffer.len() >= COPY_CHUNK_BYTES {
+            self.copy.send(self.buffer.as_bytes()).await?;
+            self.buffer.clear();
+        }
+        Ok(())
+    }
+
+    async fn finish(mut self) -> Result<u64, sqlx::Error> {
+        if !self.buffer.is_empty() {
+            self.copy.send(self.buffer.as_bytes()).await?;
+        }
+        self.copy.finish().await?;
+        Ok(self.rows)
+    }
+}
+
+// Writes one COPY text row from tab-separated format arguments
+macro_rules! copy_row {
+    ($writer:expr, $($arg:tt)*) => {{
+        write!($writer.buffer, $($arg)*).expect("writing to a String cannot fail");
+        $writer.end_row().await?;
+    }};
+}
+
+const NULL: &str = "\\N";
+
+fn timestamp(date: NaiveDateTime) -> String {
+    date.format("%Y-%m-%d %H:%M:%S%.6f").to_string()
+}
+
+async fn load_items(
+    pool: &Pool<Postgres>,
+    table_set: u32,
+    original_percent: u32,
+) -> Result<u64, sqlx::Error> {
+    let mut rng = StdRng::from_entropy();
+    let mut items = CopyWriter::start(
+        pool,
+        "item",
+        table_set,
+        "i_id, i_im_id, i_name, i_price, i_data",
+    )
+    .await?;
+    for i_id in 1..=ITEMS {
+        let im_id = rng.gen_range(1..=10_000);
+        let name = random::alpha_string(&mut rng, 14, 24);
+        let price = random::amount(&mut rng, 100, 10_000);
+        let data = random::data_string(&mut rng, original_percent);
+        copy_row!(items, "{i_id}\t{im_id}\t{name}\t{price}\t{data}");
+    }
+    let rows = items.finish().await?;
+    tracing::info!("Loaded {} items into item{}", rows, table_set);
+    Ok(rows)
+}
+
+struct WarehouseLoad {
+    table_set: u32,
+    warehouse_id: i16,
+    load_date: NaiveDateTime,
+    nurand: NuRandConstants,
+    bad_credit_percent: u32,
+    original_percent: u32,
+}
+
+impl WarehouseLoad {
+    // Load the warehouse and all rows that belong to it
+    async fn load(&self, pool: &Pool<Postgres>) -> Result<u64, sqlx::Error> {
+        let mut rng = StdRng::from_entropy();
+        let mut rows = 0;
+        rows += self.load_warehouse_and_districts(pool, &mut rng).await?;
+        rows += self.load_stock(pool, &mut rng).await?;
+        rows += self.load_customers_and_history(pool, &mut rng).await?;
+        rows += self.load_orders(pool, &mut rng).await?;
+        tracing::info!(
+            "Loaded warehouse {} of table set {} ({} rows)",
+            self.warehouse_id,
+            self.table_set,
+            rows
+        );
+        Ok(rows)
+    }
+
+    async fn load_warehouse_and_districts(
+        &self,
+        pool: &Pool<Postgres>,
+        rng: &mut StdRng,
+    ) -> Result<u64, sqlx::Error> {
+        let w_id = self.warehouse_id;
+        let mut warehouse = CopyWriter::start(
+            pool,
+            "warehouse",
+            self.table_set,
+            "w_id, w_name, w_street_1, w_street_2, w_city, w_state, w_zip, w_tax, w_ytd",
+        )
+        .await?;
+        copy_row!(
+            warehouse,
+            "{w_id}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t300000.00",
+            random::alpha_string(rng, 6, 10),
+            random::alpha_string(rng, 10, 20),
+            random::alpha_string(rng, 10, 20),
+            random::alpha_string(rng, 10, 20),
+            random::state(rng),
+            random::zip(rng),
+            random::amount(rng, 0, 20),
+        );
+        let mut rows = warehouse.finish().await?;
+
+        let mut districts = CopyWriter::start(
+            pool,
+            "district",
+            self.table_set,
+            "d_id, d_w_id, d_name, d_street_1, d_street_2, d_city, d_state, d_zip, d_tax, d_ytd, d_next_o_id",
+        )
+        .await?;
+        for d_id in 1..=DISTRICTS_PER_WAREHOUSE {
+            copy_row!(
+                districts,
+                "{d_id}\t{w_id}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t30000.00\t{}",
+                random::alpha_string(rng, 6, 10),
+                random::alpha_string(rng, 10, 20),
+                random::alpha_string(rng, 10, 20),
+                random::alpha_string(rng, 10, 20),
+                random::state(rng),
+                random::zip(rng),
+                random::amount(rng, 0, 20),
+                ORDERS_PER_DISTRICT + 1,
+            );
+        }
+        rows += districts.finish().await?;
+        Ok(rows)
+    }
+
+    async fn load_stock(
+        &self,
+        pool: &Pool<Postgres>,
+        rng: &mut StdRng,
+    ) -> Result<u64, sqlx::Error> {
+        let w_id = self.warehouse_id;
+        let mut stock = CopyWriter::start(
+            pool,
+            "stock",
+            self.table_set,
+            "s_i_id, s_w_id, s_quantity, s_dist_01, s_dist_02, s_dist_03, s_dist_04, s_dist_05, \
+             s_dist_06, s_dist_07, s_dist_08, s_dist_09, s_dist_10, s_ytd, s_order_cnt, s_remote_cnt, s_data",
+        )
+        .await?;
+        for s_i_id in 1..=ITEMS {
+            let quantity = rng.gen_range(10..=100);
+            let mut dists = String::with_capacity(250);
+            for _ in 0..DISTRICTS_PER_WAREHOUSE {
+                dists.push_str(&random::alpha_string(rng, 24, 24));
+                dists.push('\t');
+            }
+            let data = random::data_string(rng, self.original_percent);
+            copy_row!(
+                stock,
+                "{s_i_id}\t{w_id}\t{quantity}\t{dists}0\t0\t0\t{data}"
+            );
+        }
+        stock.finish().await
+    }
+
+    async fn load_customers_and_history(
+        &self,
+        pool: &Pool<Postgres>,
+        rng: &mut StdRng,
+    ) -> Result<u64, sqlx::Error> {
+        let w_id = self.warehouse_id;
+        let since = timestamp(self.load_date);
+        let mut customers = CopyWriter::start(
+            pool,
+            "customer",
+            self.table_set,
+            "c_id, c_d_id, c_w_id, c_first, c_middle, c_last, c_street_1, c_street_2, c_city, \
+             c_state, c_zip, c_phone, c_since, c_credit, c_credit_lim, c_discount, c_balance, \
+             c_ytd_payment, c_payment_cnt, c_delivery_cnt, c_data",
+        )
+        .await?;
+        for d_id in 1..=DISTRICTS_PER_WAREHOUSE {
+            for c_id in 1..=CUSTOMERS_PER_DISTRICT {
+                // The first 1000 customers cover every last name once
+                let last_name = if c_id <= 1000 {
+                    random::last_name(c_id as u32 - 1)
+                } else {
+                    random::last_name(self.nurand.customer_last_name_number(rng))
+                };
+                let credit = if rng.gen_range(1..=100) <= self.bad_credit_percent {
+                    "BC"
+                } else {
+                    "GC"
+                };
+                copy_row!(
+                    customers,
+                    "{c_id}\t{d_id}\t{w_id}\t{}\tOE\t{last_name}\t{}\t{}\t{}\t{}\t{}\t{}\t{since}\t{credit}\t50000\t{}\t-10.00\t10.00\t1\t0\t{}",
+                    random::alpha_string(rng, 8, 16),
+                    random::alpha_string(rng, 10, 20),
+                    random::alpha_string(rng, 10, 20),
+                    random::alpha_string(rng, 10, 20),
+                    random::state(rng),
+                    random::zip(rng),
+                    random::numeric_string(rng, 16),
+                    random::amount(rng, 0, 50),
+                    random::alpha_string(rng, 300, 500),
+                );
+            }
+        }
+        let mut rows = customers.finish().await?;
+
+        let mut history = CopyWriter::start(
+            pool,
+            "history",
+            self.table_set,
+            "h_c_id, h_c_d_id, h_c_w_id, h_d_id, h_w_id, h_date, h_amount, h_data",
+        )
+        .await?;
+        for d_id in 1..=DISTRICTS_PER_WAREHOUSE {
+            for c_id in 1..=CUSTOMERS_PER_DISTRICT {
+                copy_row!(
+                    history,
+                    "{c_id}\t{d_id}\t{w_id}\t{d_id}\t{w_id}\t{since}\t10.00\t{}",
+                    random::alpha_string(rng, 12, 24),
+                );
+            }
+        }
+        rows += history.finish().await?;
+        Ok(rows)
+    }
+
+    async fn load_orders(
+        &self,
+        pool: &Pool<Postgres>,
+        rng: &mut StdRng,
+    ) -> Result<u64, sqlx::Error> {
+        let w_id = self.warehouse_id;
+        let entry_date = timestamp(self.load_date);
+        let mut orders = CopyWriter::start(
+            pool,
+            "orders",
+            self.table_set,
+            "o_id, o_d_id, o_w_id, o_c_id, o_entry_d, o_carrier_id, o_ol_cnt, o_all_local",
+        )
+        .await?;
+        let mut order_lines = CopyWriter::start(
+            pool,
+            "order_line",
+            self.table_set,
+            "ol_o_id, ol_d_id, ol_w_id, ol_number, ol_i_id, ol_supply_w_id, ol_delivery_d, \
+             ol_quantity, ol_amount, ol_dist_info",
+        )
+        .await?;
+        let mut new_orders = CopyWriter::start(
+            pool,
+            "new_orders",
+            self.table_set,
+            "no_o_id, no_d_id, no_w_id",
+        )
+        .await?;
+
+        for d_id in 1..=DISTRICTS_PER_WAREHOUSE {
+            // Each customer places exactly one order, in random order
+            let mut customer_ids: Vec<i32> = (1..=CUSTOMERS_PER_DISTRICT).collect();
+            customer_ids.shuffle(rng);
+
+            for (o_id, c_id) in (1..=ORDERS_PER_DISTRICT).zip(customer_ids) {
+                let delivered = o_id < FIRST_NEW_ORDER;
+                let ol_cnt = rng.gen_range(5..=15);
+                let carrier_id = if delivered {
+                    rng.gen_range(1..=10).to_string()
+                } else {
+                    NULL.to_string()
+                };
+                copy_row!(
+                    orders,
+                    "{o_id}\t{d_id}\t{w_id}\t{c_id}\t{entry_date}\t{carrier_id}\t{ol_cnt}\t1"
+                );
+
+                for ol_number in 1..=ol_cnt {
+                    let (delivery_date, amount) = if delivered {
+                        (entry_date.as_str(), "0.00".to_string())
+                    } else {
+                        (NULL, random::amount(rng, 1, 999_999))
+                    };
+                    copy_row!(
+                        order_lines,
+                        "{o_id}\t{d_id}\t{w_id}\t{ol_number}\t{}\t{w_id}\t{delivery_date}\t5\t{amount}\t{}",
+                        rng.gen_range(1..=ITEMS),
+                        random::alpha_string(rng, 24, 24),
+                    );
+                }
+
+                if !delivered {
+                    copy_row!(new_orders, "{o_id}\t{d_id}\t{w_id}");
+                }
+            }
+        }
+
+        Ok(orders.finish().await? + order_lines.finish().await? + new_orders.finish().await?)
+    }
+}
@@ -1,26 +1,303 @@
+use clap::{Parser, Subcommand};
 use dotenvy::dotenv;
-use rust_axum_rest_api::create_app;
-use sqlx::postgres::PgPoolOptions;
-use tracing::{info, Level};
+use rust_axum_rest_api::config::{Config, ConfigArgs, DatabaseConfig, ServerConfig};
+use rust_axum_rest_api::consistency::{self, ConsistencyReport};
+use rust_axum_rest_api::migrate::{self, MigrationError, MigrationStatus};
+use rust_axum_rest_api::table_set::{self, TableSets};
+use rust_axum_rest_api::{create_router, create_state_with_config, telemetry};
+use sqlx::{Pool, Postgres};
+use std::future::IntoFuture;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::time::Instant;
+use tracing::{error, info, warn};
 
-#[tokio::main]
-async fn main() -> Result<(), sqlx::Error> {
-    // initialize tracing for logging
-    tracing_subscriber::fmt().with_max_level(Level::INFO).init();
+#[derive(Parser)]
+#[command(about = "TPC-C REST API")]
+struct Cli {
+    #[command(flatten)]
+    config: ConfigArgs,
+    // Serve the API when no subcommand is given
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Check the TPC-C consistency conditions; exits with 1 if any required condition fails
+    Consistency {
+        /// Warehouse to check; every warehouse when omitted
+        #[arg(long)]
+        warehouse_id: Option<i16>,
+        /// Table set holding the warehouse
+        #[arg(long, default_value_t = 1)]
+        table_set: u32,
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Apply the pending migrations and print the applied versions; exits with 1 on
+    /// schema drift
+    Migrate {
+        /// Only report; exits with 1 if migrations are pending
+        #[arg(long)]
+        check: bool,
+    },
+}
 
+#[tokio::main]
+async fn main() -> Result<ExitCode, sqlx::Error> {
     dotenv().ok();
-    let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    let pool = PgPoolOptions::new().connect(&url).await?;
-    info!("Connected to the database!");
+    let cli = Cli::parse();
+    let config = match Config::load(&cli.config) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            return Ok(ExitCode::from(2));
+        }
+    };
+
+    // initialize tracing for logging; LOG_FORMAT=json and OTLP export are optional
+    let _telemetry = telemetry::init("rust-axum-rest-api", config.server.log_level.as_deref());
+
+    let database = &config.database;
+    let pool = database.connect().await?;
+    info!(
+        "Connected to the database! Pool: {}..{} connections, acquire timeout {}s, idle timeout {}s, statement timeout {}",
+        database.min_connections,
+        database.max_connections,
+        database.acquire_timeout_secs,
+        database.idle_timeout_secs,
+        database
+            .statement_timeout_ms
+            .map_or("server default".to_string(), |ms| format!("{}ms", ms))
+    );
 
-    let app = create_app(pool).await;
+    match cli.command {
+        None => {
+            if database.run_migrations {
+                if let Err(e) = apply_migrations(&pool).await {
+                    error!("Not starting: {}", e);
+                    return Ok(ExitCode::FAILURE);
+                }
+            }
+            serve(pool, &config.server, database).await
+        }
+        Some(Command::Consistency {
+            warehouse_id,
+            table_set,
+            json,
+        }) => check_consistency(pool, warehouse_id, table_set, json).await,
+        Some(Command::Migrate { check }) => migrate(pool, check).await,
+    }
+}
+
+// The migrations, then the views of the table sets other than 1
+async fn apply_migrations(pool: &Pool<Postgres>) -> Result<MigrationStatus, MigrationError> {
+    let (before, after) = migrate::run(pool).await?;
+    table_set::create_views(pool).await?;
+    let newly_applied: Vec<i64> = after
+        .applied
+        .iter()
+        .filter(|version| before.pending.contains(version))
+        .copied()
+        .collect();
+    if newly_applied.is_empty() {
+        info!("Schema up to date; applied migrations: {:?}", after.applied);
+    } else {
+        info!(
+            "Applied migrations {:?}; applied migrations: {:?}",
+            newly_applied, after.applied
+        );
+    }
+    Ok(after)
+}
+
+async fn migrate(pool: Pool<Postgres>, check: bool) -> Result<ExitCode, sqlx::Error> {
+    let result = if check {
+        migrate::status(&pool).await
+    } else {
+        apply_migrations(&pool).await
+    };
+    let status = match result {
+        Ok(status) => status,
+        Err(MigrationError::Drift(drift)) => {
+            for drift in drift {
+                eprintln!("Schema drift: {}", drift);
+            }
+            return Ok(ExitCode::FAILURE);
+        }
+        Err(e) => {
+            eprintln!("Migration failed: {}", e);
+            return Ok(ExitCode::from(2));
+        }
+    };
+
+    println!("Applied: {:?}", status.applied);
+    println!("Pending: {:?}", status.pending);
+    for drift in &status.drift {
+        println!("Drift: {}", drift);
+    }
+    Ok(if status.drift.is_empty() && status.pending.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
 
-    // run our app with hyper, listening globally on port 8080
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:8080")
-        .await
-        .unwrap();
-    info!("Server is running on http://127.0.0.1:8080");
-    axum::serve(listener, app).await.unwrap();
+async fn serve(
+    pool: Pool<Postgres>,
+    config: &ServerConfig,
+    database: &DatabaseConfig,
+) -> Result<ExitCode, sqlx::Error> {
+    // run our app with hyper on the configured address
+    let listener = match tokio::net::TcpListener::bind((config.host.as_str(), config.port)).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Cannot listen on {}:{}: {}", config.host, config.port, e);
+            return Ok(ExitCode::FAILURE);
+        }
+    };
+    match listener.local_addr() {
+        Ok(address) => info!("Server is running on http://{}", address),
+        Err(e) => {
+            error!("Cannot read the listening address: {}", e);
+            return Ok(ExitCode::FAILURE);
+        }
+    }
+
+    let state = match create_state_with_config(pool, database).await {
+        Ok(state) => state,
+        Err(e) => {
+            error!("Not starting: {}", e);
+            return Ok(ExitCode::FAILURE);
+        }
+    };
+    let app = create_router(state.clone(), config);
+
+    // The first SIGINT/SIGTERM stops accepting connections and lets in-flight
+    // requests finish until the drain deadline; a second one aborts them at once
+    let stop_accepting = Arc::new(Notify::new());
+    let mut server = Box::pin(
+        axum::serve(listener, app)
+            .with_graceful_shutdown({
+                let stop_accepting = Arc::clone(&stop_accepting);
+                async move { stop_accepting.notified().await }
+            })
+            .into_future(),
+    );
+    tokio::select! {
+        result = &mut server => {
+            // Only returns early on an accept error
+            error!("Server stopped: {:?}", result);
+            state.shutdown(Instant::now()).await;
+            return Ok(ExitCode::FAILURE);
+        }
+        _ = shutdown_signal() => {}
+    }
+
+    let timeout = Duration::from_secs(config.shutdown_timeout_secs);
+    let mut deadline = Instant::now() + timeout;
+    info!(
+        "Shutting down: finishing in-flight requests for up to {}s",
+        timeout.as_secs()
+    );
+    stop_accepting.notify_one();
+    tokio::select! {
+        _ = &mut server => info!("In-flight requests finished"),
+        _ = tokio::time::sleep_until(deadline) => {
+            warn!("Shutdown deadline passed; aborting in-flight requests")
+        }
+        _ = shutdown_signal() => {
+            warn!("Second signal; aborting in-flight requests");
+            deadline = Instant::now();
+        }
+    }
+    // Dropping the server drops unfinished handlers; their transactions roll back
+    drop(server);
+
+    state.shutdown(deadline).await;
+    info!("Shutdown complete");
+    Ok(ExitCode::SUCCESS)
+}
+
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to listen for Ctrl-C");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+async fn check_consistency(
+    pool: Pool<Postgres>,
+    warehouse_id: Option<i16>,
+    table_set: u32,
+    json: bool,
+) -> Result<ExitCode, sqlx::Error> {
+    let table_sets = TableSets::discover(&pool).await;
+    let Some(pool) = table_sets.pool(table_set) else {
+        eprintln!("Table set {} not found", table_set);
+        return Ok(ExitCode::from(2));
+    };
+
+    let report = match consistency::check(pool, warehouse_id).await {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("Consistency check failed: {}", e);
+            return Ok(ExitCode::from(2));
+        }
+    };
+
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&report).expect("report serializes")
+        );
+    } else {
+        print_report(&report);
+    }
+    Ok(if report.consistent {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
 
-    Ok(())
+// One line per condition in the style of tpcc_check.lua, followed by the offending rows
+fn print_report(report: &ConsistencyReport) {
+    let scope = match report.warehouse_id {
+        Some(warehouse_id) => format!("warehouse: {}", warehouse_id),
+        None => "all warehouses".to_string(),
+    };
+    for condition in &report.conditions {
+        let status = match (condition.passed, condition.required) {
+            (true, _) => "PASSED",
+            (false, true) => "FAILED!!!",
+            (false, false) => "FAILED (informational)",
+        };
+        println!(
+            "Check {}, {} {} ({})",
+            condition.condition, scope, status, condition.description
+        );
+        for row in &condition.violations {
+            println!("    {}", row);
+        }
+    }
 }
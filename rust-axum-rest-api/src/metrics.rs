@@ -0,0 +1,229 @@
+use axum::{
+    extract::{MatchedPath, Request},
+    http::StatusCode,
+    middleware::Next,
+    response::Response,
+};
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+use std::sync::LazyLock;
+use std::time::{Duration, Instant};
+
+use crate::error::ErrorCode;
+use crate::table_set::TableSets;
+
+// Finer than Prometheus' defaults at the low end, where most TPC-C transactions finish
+const LATENCY_BUCKETS: [f64; 14] = [
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+// Process-wide metrics, served in the Prometheus text format on GET /metrics.
+// Global like the transaction helpers that record into it, so in-process
+// drivers and background delivery workers are measured too.
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_errors: IntCounterVec,
+    http_duration: HistogramVec,
+    transaction_duration: HistogramVec,
+    transaction_retries: IntCounterVec,
+    pool_connections: IntGaugeVec,
+    pool_max_connections: IntGaugeVec,
+    pool_begin_duration: Histogram,
+}
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new();
+        let latency = |name: &str, help: &str| {
+            HistogramOpts::new(name, help).buckets(LATENCY_BUCKETS.to_vec())
+        };
+
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests by route and status"),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let http_errors = IntCounterVec::new(
+            Opts::new(
+                "http_errors_total",
+                "Error responses by route and error code",
+            ),
+            &["route", "code"],
+        )
+        .unwrap();
+        let http_duration = HistogramVec::new(
+            latency(
+                "http_request_duration_seconds",
+                "HTTP request latency by route",
+            ),
+            &["method", "route"],
+        )
+        .unwrap();
+        let transaction_duration = HistogramVec::new(
+            latency(
+                "tpcc_transaction_duration_seconds",
+                "TPC-C transaction latency including retries, by outcome (ok or error code)",
+            ),
+            &["transaction", "outcome"],
+        )
+        .unwrap();
+        let transaction_retries = IntCounterVec::new(
+            Opts::new(
+                "tpcc_transaction_retries_total",
+                "Transaction attempts retried after a serialization failure or deadlock",
+            ),
+            &["transaction", "code"],
+        )
+        .unwrap();
+        let pool_connections = IntGaugeVec::new(
+            Opts::new(
+                "db_pool_connections",
+                "Open pool connections by table set and state (idle or in_use)",
+            ),
+            &["table_set", "state"],
+        )
+        .unwrap();
+        let pool_max_connections = IntGaugeVec::new(
+            Opts::new(
+                "db_pool_max_connections",
+                "Maximum pool connections by table set",
+            ),
+            &["table_set"],
+        )
+        .unwrap();
+        let pool_begin_duration = Histogram::with_opts(latency(
+            "db_pool_begin_duration_seconds",
+            "Time to acquire a pool connection and start a transaction",
+        ))
+        .unwrap();
+
+        registry.register(Box::new(http_requests.clone())).unwrap();
+        registry.register(Box::new(http_errors.clone())).unwrap();
+        registry.register(Box::new(http_duration.clone())).unwrap();
+        registry
+            .register(Box::new(transaction_duration.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(transaction_retries.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(pool_connections.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(pool_max_connections.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(pool_begin_duration.clone()))
+            .unwrap();
+
+        Metrics {
+            registry,
+            http_requests,
+            http_errors,
+            http_duration,
+            transaction_duration,
+            transaction_retries,
+            pool_connections,
+            pool_max_connections,
+            pool_begin_duration,
+        }
+    }
+
+    pub fn observe_transaction(&self, transaction: &str, outcome: &str, elapsed: Duration) {
+        self.transaction_duration
+            .with_label_values(&[transaction, outcome])
+            .observe(elapsed.as_secs_f64());
+    }
+
+    pub fn observe_retry(&self, transaction: &str, code: &str) {
+        self.transaction_retries
+            .with_label_values(&[transaction, code])
+            .inc();
+    }
+
+    pub fn observe_begin(&self, elapsed: Duration) {
+        self.pool_begin_duration.observe(elapsed.as_secs_f64());
+    }
+
+    fn observe_request(
+        &self,
+        method: &str,
+        route: &str,
+        status: StatusCode,
+        error: Option<&ErrorCode>,
+        elapsed: Duration,
+    ) {
+        self.http_requests
+            .with_label_values(&[method, route, status.as_str()])
+            .inc();
+        self.http_duration
+            .with_label_values(&[method, route])
+            .observe(elapsed.as_secs_f64());
+        if let Some(ErrorCode(code)) = error {
+            self.http_errors.with_label_values(&[route, code]).inc();
+        }
+    }
+
+    // Pool gauges are sampled when scraped
+    fn observe_pools(&self, table_sets: &TableSets) {
+        for number in table_sets.numbers() {
+            let Some(pool) = table_sets.pool(number) else {
+                continue;
+            };
+            let table_set = number.to_string();
+            let size = pool.size() as i64;
+            let idle = pool.num_idle() as i64;
+            self.pool_connections
+                .with_label_values(&[table_set.as_str(), "idle"])
+                .set(idle);
+            self.pool_connections
+                .with_label_values(&[table_set.as_str(), "in_use"])
+                .set(size - idle);
+            self.pool_max_connections
+                .with_label_values(&[table_set.as_str()])
+                .set(pool.options().get_max_connections() as i64);
+        }
+    }
+
+    // All metrics in the Prometheus text exposition format
+    pub fn encode(&self, table_sets: &TableSets) -> String {
+        self.observe_pools(table_sets);
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("encoding metrics failed");
+        String::from_utf8(buffer).expect("metrics are UTF-8")
+    }
+}
+
+// Middleware counting and timing every routed request; error responses carry
+// their error code as a response extension
+pub async fn track_requests(request: Request, next: Next) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let method = request.method().clone();
+    let start = Instant::now();
+
+    let response = next.run(request).await;
+
+    metrics().observe_request(
+        method.as_str(),
+        &route,
+        response.status(),
+        response.extensions().get::<ErrorCode>(),
+        start.elapsed(),
+    );
+    response
+}
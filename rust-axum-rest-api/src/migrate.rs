@@ -0,0 +1,155 @@
+// The migrations in ./migrations, embedded in the binary, and how the database
+// compares to them
+use serde::Serialize;
+use sqlx::migrate::{Migrate, MigrateError, Migrator};
+use sqlx::{Pool, Postgres};
+use std::collections::BTreeMap;
+use std::fmt;
+
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+// A difference between the migrations recorded in the database and the embedded
+// ones that applying migrations cannot fix
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Drift {
+    // Applied, but the migration file changed since
+    Modified { version: i64, description: String },
+    // Applied, but not among the embedded migrations (a newer or foreign build)
+    Unknown { version: i64 },
+    // Started and failed; the schema is in an unknown state
+    Failed { version: i64 },
+}
+
+impl fmt::Display for Drift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Drift::Modified {
+                version,
+                description,
+            } => write!(
+                f,
+                "migration {} ({}) was changed after it was applied",
+                version, description
+            ),
+            Drift::Unknown { version } => {
+                write!(
+                    f,
+                    "migration {} is applied but unknown to this build",
+                    version
+                )
+            }
+            Drift::Failed { version } => write!(f, "migration {} failed part-way", version),
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct MigrationStatus {
+    pub applied: Vec<i64>,
+    pub pending: Vec<i64>,
+    pub drift: Vec<Drift>,
+}
+
+#[derive(Debug)]
+pub enum MigrationError {
+    Drift(Vec<Drift>),
+    Migrate(MigrateError),
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationError::Drift(drift) => {
+                write!(f, "schema drift: ")?;
+                for (i, drift) in drift.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, "; ")?;
+                    }
+                    write!(f, "{}", drift)?;
+                }
+                Ok(())
+            }
+            MigrationError::Migrate(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for MigrationError {}
+
+impl From<MigrateError> for MigrationError {
+    fn from(e: MigrateError) -> Self {
+        MigrationError::Migrate(e)
+    }
+}
+
+impl From<sqlx::Error> for MigrationError {
+    fn from(e: sqlx::Error) -> Self {
+        MigrationError::Migrate(MigrateError::Execute(e))
+    }
+}
+
+// Compare the database with the embedded migrations without changing anything.
+// A database without the _sqlx_migrations table (e.g. prepared by sysbench) has
+// every migration pending.
+pub async fn status(pool: &Pool<Postgres>) -> Result<MigrationStatus, MigrationError> {
+    let mut conn = pool.acquire().await?;
+    let has_table: bool =
+        sqlx::query_scalar!(r#"SELECT to_regclass('_sqlx_migrations') IS NOT NULL AS "exists!""#)
+            .fetch_one(&mut *conn)
+            .await?;
+    let (applied, dirty) = if has_table {
+        (
+            conn.list_applied_migrations().await?,
+            conn.dirty_version().await?,
+        )
+    } else {
+        (Vec::new(), None)
+    };
+
+    let embedded: BTreeMap<i64, _> = MIGRATOR
+        .iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+        .map(|migration| (migration.version, migration))
+        .collect();
+
+    let mut status = MigrationStatus::default();
+    if let Some(version) = dirty {
+        status.drift.push(Drift::Failed { version });
+    }
+    for migration in &applied {
+        match embedded.get(&migration.version) {
+            Some(embedded) if embedded.checksum == migration.checksum => {
+                status.applied.push(migration.version)
+            }
+            Some(embedded) => status.drift.push(Drift::Modified {
+                version: migration.version,
+                description: embedded.description.to_string(),
+            }),
+            None => status.drift.push(Drift::Unknown {
+                version: migration.version,
+            }),
+        }
+    }
+    status.pending = embedded
+        .keys()
+        .filter(|version| !applied.iter().any(|m| m.version == **version))
+        .copied()
+        .collect();
+    Ok(status)
+}
+
+// Apply the pending migrations, refusing to touch a database that has drifted.
+// Returns the status before and after.
+pub async fn run(
+    pool: &Pool<Postgres>,
+) -> Result<(MigrationStatus, MigrationStatus), MigrationError> {
+    let before = status(pool).await?;
+    if !before.drift.is_empty() {
+        return Err(MigrationError::Drift(before.drift));
+    }
+    // Holds an advisory lock, so servers starting together apply each migration once
+    MIGRATOR.run(pool).await?;
+    let after = status(pool).await?;
+    Ok((before, after))
+}
@@ -0,0 +1,176 @@
+// The five TPC-C transactions on tokio-postgres, for comparing drivers on the same
+// hardware (TX_DRIVER=pipelined). They run the inline statements of the handlers
+// with the same results, errors and retries, but as prepared statements that are
+// sent without waiting for the previous reply: every statement whose parameters
+// are known goes out in one pipeline, so a transaction costs a few round trips
+// instead of one per statement. PostgreSQL still runs the statements of a pipeline
+// one after the other, in the order they were sent.
+pub mod delivery;
+pub mod new_order;
+pub mod order_status;
+pub mod payment;
+pub mod stock_level;
+
+use bigdecimal::num_bigint::{BigInt, Sign};
+use bigdecimal::BigDecimal;
+use deadpool_postgres::tokio_postgres::config::SslMode;
+use deadpool_postgres::tokio_postgres::types::{FromSql, ToSql, Type};
+use deadpool_postgres::tokio_postgres::{Config, NoTls, Statement};
+use deadpool_postgres::{
+    Client, Manager, ManagerConfig, Pool, RecyclingMethod, Runtime, Transaction,
+};
+use futures_util::future::try_join_all;
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+use crate::config::DatabaseConfig;
+use crate::error::ApiError;
+use crate::metrics::metrics;
+use crate::transaction::IsolationLevel;
+
+// A pool of up to `max_size` connections to `database`, with its acquire timeout.
+// `options` are the connection options of the table set's sqlx pool, which carry its
+// search_path and statement_timeout. Connections are made without TLS, so a URL
+// requiring it is refused here rather than on the first transaction.
+pub fn pool(
+    database: &DatabaseConfig,
+    options: Option<&str>,
+    max_size: usize,
+) -> Result<Pool, String> {
+    let url = database
+        .url
+        .as_deref()
+        .ok_or_else(|| "DATABASE_URL must be set".to_string())?;
+    let mut config: Config = url
+        .parse()
+        .map_err(|e| format!("invalid DATABASE_URL: {}", e))?;
+    if config.get_ssl_mode() == SslMode::Require {
+        return Err("DATABASE_URL requires TLS (sslmode=require), which the pipelined driver does not support".to_string());
+    }
+    if let Some(options) = options {
+        config.options(options);
+    }
+
+    let manager = Manager::from_config(
+        config,
+        NoTls,
+        ManagerConfig {
+            recycling_method: RecyclingMethod::Fast,
+        },
+    );
+    Pool::builder(manager)
+        .max_size(max_size)
+        .wait_timeout(Some(Duration::from_secs(database.acquire_timeout_secs)))
+        .runtime(Runtime::Tokio1)
+        .build()
+        .map_err(|e| e.to_string())
+}
+
+// The parameters of one statement. Each statement of a pipeline borrows its own
+// array until the pipeline completes, so arrays for a variable number of
+// statements are collected before the statements are sent.
+pub(crate) type Params<'a, const N: usize> = [&'a (dyn ToSql + Sync); N];
+
+// Wait for a connection, recording the wait like sqlx's begin does
+pub(crate) async fn connect(pool: &Pool) -> Result<Client, ApiError> {
+    let start = Instant::now();
+    let client = pool
+        .get()
+        .await
+        .map_err(|e| ApiError::pipelined("acquiring connection", e))?;
+    metrics().observe_begin(start.elapsed());
+    Ok(client)
+}
+
+// BEGIN with the isolation level in the same statement
+pub(crate) async fn begin(
+    client: &mut Client,
+    isolation: Option<IsolationLevel>,
+) -> Result<Transaction<'_>, ApiError> {
+    let mut builder = client.build_transaction();
+    if let Some(level) = isolation {
+        use deadpool_postgres::tokio_postgres::IsolationLevel as Level;
+        builder = builder.isolation_level(match level {
+            IsolationLevel::ReadCommitted => Level::ReadCommitted,
+            IsolationLevel::RepeatableRead => Level::RepeatableRead,
+            IsolationLevel::Serializable => Level::Serializable,
+        });
+    }
+    builder
+        .start()
+        .await
+        .map_err(|e| ApiError::pipelined("starting transaction", e))
+}
+
+// Prepare `statements` on the connection, all in one round trip; statements the
+// connection prepared before come from its cache without one
+pub(crate) async fn prepare<const N: usize>(
+    tx: &Transaction<'_>,
+    statements: [&str; N],
+) -> Result<[Statement; N], ApiError> {
+    let prepared = try_join_all(statements.map(|sql| tx.prepare_cached(sql)))
+        .await
+        .map_err(|e| ApiError::pipelined("preparing statements", e))?;
+    Ok(prepared
+        .try_into()
+        .unwrap_or_else(|_| unreachable!("one statement per query")))
+}
+
+pub(crate) async fn commit(tx: Transaction<'_>) -> Result<(), ApiError> {
+    tx.commit()
+        .await
+        .map_err(|e| ApiError::pipelined("committing transaction", e))
+}
+
+pub(crate) async fn rollback(tx: Transaction<'_>) -> Result<(), ApiError> {
+    tx.rollback()
+        .await
+        .map_err(|e| ApiError::pipelined("rolling back transaction", e))
+}
+
+// NUMERIC decoded the way sqlx decodes it, with the scale taken from the base-10000
+// digits rather than the column's display scale, so both drivers serialize the same
+// amounts the same way. Amounts are sent as text and cast in SQL ($1::text::numeric).
+pub(crate) struct Numeric(pub BigDecimal);
+
+impl<'a> FromSql<'a> for Numeric {
+    fn from_sql(_: &Type, raw: &'a [u8]) -> Result<Self, Box<dyn Error + Sync + Send>> {
+        let word = |offset: usize| {
+            raw.get(offset..offset + 2)
+                .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+                .ok_or("truncated NUMERIC")
+        };
+
+        // Header: digit count, weight of the first digit, sign and display scale
+        let digit_count = word(0)? as usize;
+        let weight = word(2)? as i16;
+        let sign = match word(4)? {
+            0x0000 => Sign::Plus,
+            0x4000 => Sign::Minus,
+            _ => return Err("NaN and infinite NUMERIC values are not supported".into()),
+        };
+        if digit_count == 0 {
+            return Ok(Numeric(BigDecimal::from(0)));
+        }
+
+        // Each base-10000 digit as two base-100 digits
+        let mut cents = Vec::with_capacity(digit_count * 2);
+        for n in 0..digit_count {
+            let digit = word(8 + 2 * n)?;
+            cents.push((digit / 100) as u8);
+            cents.push((digit % 100) as u8);
+        }
+        let value = BigInt::from_radix_be(sign, &cents, 100).ok_or("invalid NUMERIC digit")?;
+        let scale = (digit_count as i64 - weight as i64 - 1) * 4;
+        Ok(Numeric(BigDecimal::new(value, scale)))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == Type::NUMERIC
+    }
+}
+
+// The value of a nullable NUMERIC column
+pub(crate) fn numeric(value: Option<Numeric>) -> Option<BigDecimal> {
+    value.map(|Numeric(value)| value)
+}
@@ -0,0 +1,212 @@
+use bigdecimal::{BigDecimal, FromPrimitive};
+use chrono::Utc;
+use deadpool_postgres::Pool;
+use futures_util::future::{try_join5, try_join_all};
+
+use crate::error::ApiError;
+use crate::handlers::delivery::{
+    validate_delivery, DeliveredOrder, DeliveryRequest, DeliveryResponse,
+};
+use crate::transaction::{run_transaction, Attempted, IsolationLevel, TransactionSettings};
+
+use super::{begin, commit, connect, numeric, prepare, Params};
+
+const OLDEST_NEW_ORDER: &str = "
+    SELECT no_o_id
+    FROM new_orders1
+    WHERE no_w_id = $1 AND no_d_id = $2
+    ORDER BY no_o_id ASC
+    LIMIT 1
+    FOR UPDATE";
+
+const ORDER: &str = "SELECT o_c_id FROM orders1 WHERE o_w_id = $1 AND o_d_id = $2 AND o_id = $3";
+
+const UPDATE_ORDER: &str =
+    "UPDATE orders1 SET o_carrier_id = $4 WHERE o_w_id = $1 AND o_d_id = $2 AND o_id = $3";
+
+const UPDATE_ORDER_LINES: &str = "
+    UPDATE order_line1
+    SET ol_delivery_d = $4
+    WHERE ol_w_id = $1 AND ol_d_id = $2 AND ol_o_id = $3
+    RETURNING ol_amount";
+
+// The customer and the amount come from the order and its lines, so the update need
+// not wait for them to be read
+const UPDATE_CUSTOMER: &str = "
+    UPDATE customer1
+    SET c_balance = c_balance + (SELECT COALESCE(sum(ol_amount), 0)
+                                 FROM order_line1
+                                 WHERE ol_w_id = $1 AND ol_d_id = $2 AND ol_o_id = $3),
+        c_delivery_cnt = c_delivery_cnt + 1
+    WHERE c_w_id = $1 AND c_d_id = $2
+      AND c_id = (SELECT COALESCE(o_c_id, 0) FROM orders1
+                  WHERE o_w_id = $1 AND o_d_id = $2 AND o_id = $3)";
+
+const DELETE_NEW_ORDER: &str =
+    "DELETE FROM new_orders1 WHERE no_w_id = $1 AND no_d_id = $2 AND no_o_id = $3";
+
+// Delivery run inline with validation and retries on the pipelined driver
+pub async fn execute_delivery(
+    pool: &Pool,
+    settings: &TransactionSettings,
+    request: &DeliveryRequest,
+) -> Result<Attempted<DeliveryResponse>, ApiError> {
+    let (carrier_id, district_ids) = validate_delivery(request)?;
+
+    let attempted = run_transaction(&settings.retry_policy, "delivery", || {
+        process_delivery(
+            pool,
+            settings.isolation_levels.delivery,
+            request.warehouse_id,
+            &district_ids,
+            carrier_id,
+        )
+    })
+    .await?;
+
+    Ok(attempted.map(|response| DeliveryResponse {
+        district_id: request.district_id,
+        ..response
+    }))
+}
+
+// Two pipelines: the oldest new order of every district, then the delivery of each
+#[tracing::instrument(
+    name = "delivery",
+    skip_all,
+    fields(w_id = warehouse_id, carrier_id, driver = "pipelined")
+)]
+async fn process_delivery(
+    pool: &Pool,
+    isolation: Option<IsolationLevel>,
+    warehouse_id: i16,
+    district_ids: &[i16],
+    carrier_id: i16,
+) -> Result<DeliveryResponse, ApiError> {
+    let delivery_date = Utc::now().naive_utc();
+
+    let mut client = connect(pool).await?;
+    let tx = begin(&mut client, isolation).await?;
+    let [oldest_new_order, order, update_order, update_order_lines, update_customer, delete_new_order] =
+        prepare(
+            &tx,
+            [
+                OLDEST_NEW_ORDER,
+                ORDER,
+                UPDATE_ORDER,
+                UPDATE_ORDER_LINES,
+                UPDATE_CUSTOMER,
+                DELETE_NEW_ORDER,
+            ],
+        )
+        .await?;
+
+    // Pipeline 1: find and lock the oldest undelivered order of each district
+    let district_params: Vec<Params<2>> = district_ids
+        .iter()
+        .map(|district_id| [&warehouse_id as _, district_id as _])
+        .collect();
+    let new_order_rows = try_join_all(
+        district_params
+            .iter()
+            .map(|params| tx.query_opt(&oldest_new_order, params)),
+    )
+    .await
+    .map_err(|e| ApiError::pipelined("finding new orders", e))?;
+
+    let mut skipped_districts = Vec::new();
+    let mut orders = Vec::new();
+    for (&district_id, row) in district_ids.iter().zip(new_order_rows) {
+        match row {
+            Some(row) => orders.push((district_id, row.get::<_, i32>(0))),
+            None => skipped_districts.push(district_id),
+        }
+    }
+
+    // Pipeline 2: deliver every order found
+    let order_params: Vec<Params<3>> = orders
+        .iter()
+        .map(|(district_id, order_id)| [&warehouse_id as _, district_id as _, order_id as _])
+        .collect();
+    let update_params: Vec<Params<4>> = order_params
+        .iter()
+        .map(|&[warehouse_id, district_id, order_id]| {
+            [warehouse_id, district_id, order_id, &carrier_id as _]
+        })
+        .collect();
+    let order_line_params: Vec<Params<4>> = order_params
+        .iter()
+        .map(|&[warehouse_id, district_id, order_id]| {
+            [warehouse_id, district_id, order_id, &delivery_date as _]
+        })
+        .collect();
+    let (order_rows, _, order_line_rows, _, _) = try_join5(
+        try_join_all(
+            order_params
+                .iter()
+                .map(|params| tx.query_opt(&order, params)),
+        ),
+        try_join_all(
+            update_params
+                .iter()
+                .map(|params| tx.execute(&update_order, params)),
+        ),
+        try_join_all(
+            order_line_params
+                .iter()
+                .map(|params| tx.query(&update_order_lines, params)),
+        ),
+        try_join_all(
+            order_params
+                .iter()
+                .map(|params| tx.execute(&update_customer, params)),
+        ),
+        try_join_all(
+            order_params
+                .iter()
+                .map(|params| tx.execute(&delete_new_order, params)),
+        ),
+    )
+    .await
+    .map_err(|e| ApiError::pipelined("delivering orders", e))?;
+
+    let mut delivered_orders = Vec::with_capacity(orders.len());
+    for (((district_id, order_id), order_row), order_line_rows) in
+        orders.into_iter().zip(order_rows).zip(order_line_rows)
+    {
+        let Some(order_row) = order_row else {
+            return Err(ApiError::Internal(format!(
+                "new order {} in warehouse {} district {} has no order row",
+                order_id, warehouse_id, district_id
+            )));
+        };
+
+        // Counted and summed like the sqlx driver's order lines
+        let mut total_amount = BigDecimal::from_f64(0.0).unwrap();
+        for row in &order_line_rows {
+            if let Some(amount) = numeric(row.get(0)) {
+                total_amount += amount;
+            }
+        }
+        delivered_orders.push(DeliveredOrder {
+            district_id,
+            order_id,
+            customer_id: order_row.get::<_, Option<i32>>(0).unwrap_or(0),
+            carrier_id,
+            order_line_count: order_line_rows.len(),
+            total_amount,
+        });
+    }
+
+    commit(tx).await?;
+
+    Ok(DeliveryResponse {
+        warehouse_id,
+        district_id: None,
+        carrier_id,
+        delivery_date,
+        total_orders_delivered: delivered_orders.len(),
+        delivered_orders,
+        skipped_districts,
+    })
+}
@@ -0,0 +1,347 @@
+use bigdecimal::BigDecimal;
+use chrono::Utc;
+use deadpool_postgres::Pool;
+use futures_util::future::{try_join4, try_join5, try_join_all};
+use std::collections::BTreeMap;
+
+use crate::error::{ApiError, Entity};
+use crate::handlers::new_order::{
+    brand_generic, order_total, validate_order_lines, CustomerSummary, NewOrderOutcome,
+    NewOrderRequest, NewOrderResponse, NewOrderRollback, OrderLineSummary, ITEM_NOT_VALID,
+};
+use crate::transaction::{run_transaction, Attempted, IsolationLevel, TransactionSettings};
+
+use super::{begin, commit, connect, numeric, prepare, rollback, Params};
+
+const WAREHOUSE: &str = "SELECT w_tax FROM warehouse1 WHERE w_id = $1";
+
+const DISTRICT: &str = "
+    UPDATE district1 SET d_next_o_id = COALESCE(d_next_o_id, 1) + 1
+    WHERE d_w_id = $1 AND d_id = $2
+    RETURNING d_tax, d_next_o_id - 1";
+
+const CUSTOMER: &str =
+    "SELECT c_last, c_credit, c_discount FROM customer1 WHERE c_w_id = $1 AND c_d_id = $2 AND c_id = $3";
+
+const ITEM: &str = "SELECT i_name, i_price FROM item1 WHERE i_id = $1";
+
+const INSERT_ORDER: &str = "
+    INSERT INTO orders1 (o_id, o_d_id, o_w_id, o_c_id, o_entry_d, o_carrier_id, o_ol_cnt, o_all_local)
+    VALUES ($1, $2, $3, $4, $5, NULL, $6, $7)";
+
+const INSERT_NEW_ORDER: &str =
+    "INSERT INTO new_orders1 (no_o_id, no_d_id, no_w_id) VALUES ($1, $2, $3)";
+
+const LOCK_STOCK: &str =
+    "SELECT s_i_id FROM stock1 WHERE s_w_id = $1 AND s_i_id = ANY($2) ORDER BY s_i_id FOR UPDATE";
+
+const UPDATE_STOCK: &str = "
+    UPDATE stock1
+    SET s_quantity = CASE WHEN COALESCE(s_quantity, 0) >= $3::smallint
+                          THEN COALESCE(s_quantity, 0) - $3
+                          ELSE COALESCE(s_quantity, 0) - $3 + 91 -- TPC-C specification
+                     END,
+        s_ytd = COALESCE(s_ytd, 0) + $3,
+        s_order_cnt = COALESCE(s_order_cnt, 0) + 1,
+        s_remote_cnt = COALESCE(s_remote_cnt, 0) + CASE WHEN $4 THEN 1 ELSE 0 END
+    WHERE s_i_id = $1 AND s_w_id = $2
+    RETURNING s_quantity, s_data";
+
+// S_DIST_xx is read from the stock row by the insert itself, so the order lines go in
+// the same pipeline as the stock updates; a missing stock row inserts nothing and
+// fails the order through its update
+const INSERT_ORDER_LINE: &str = "
+    INSERT INTO order_line1 (ol_o_id, ol_d_id, ol_w_id, ol_number, ol_i_id, ol_supply_w_id,
+                             ol_quantity, ol_amount, ol_dist_info)
+    SELECT $1, $2, $3, $4, $5, $6, $7, $8::text::numeric,
+           COALESCE(CASE $2::smallint
+               WHEN 1 THEN s_dist_01 WHEN 2 THEN s_dist_02 WHEN 3 THEN s_dist_03
+               WHEN 4 THEN s_dist_04 WHEN 5 THEN s_dist_05 WHEN 6 THEN s_dist_06
+               WHEN 7 THEN s_dist_07 WHEN 8 THEN s_dist_08 WHEN 9 THEN s_dist_09
+               WHEN 10 THEN s_dist_10
+           END, '')
+    FROM stock1
+    WHERE s_i_id = $5 AND s_w_id = $6";
+
+// New-Order with retries on the pipelined driver
+pub async fn execute_new_order(
+    pool: &Pool,
+    settings: &TransactionSettings,
+    request: &NewOrderRequest,
+) -> Result<Attempted<NewOrderOutcome>, ApiError> {
+    validate_order_lines(request)?;
+
+    let isolation = settings.isolation_levels.new_order;
+    run_transaction(&settings.retry_policy, "new-order", || {
+        process_new_order(pool, isolation, request)
+    })
+    .await
+}
+
+// Two pipelines: the warehouse, district, customer and items first, then the order,
+// the stock rows and the order lines
+#[tracing::instrument(
+    name = "new_order",
+    skip_all,
+    fields(
+        w_id = request.warehouse_id,
+        d_id = request.district_id,
+        c_id = request.customer_id,
+        o_id = tracing::field::Empty,
+        ol_cnt = request.order_lines.len(),
+        driver = "pipelined"
+    )
+)]
+async fn process_new_order(
+    pool: &Pool,
+    isolation: Option<IsolationLevel>,
+    request: &NewOrderRequest,
+) -> Result<NewOrderOutcome, ApiError> {
+    let mut client = connect(pool).await?;
+    let tx = begin(&mut client, isolation).await?;
+    let [warehouse, district, customer, item, insert_order, insert_new_order, lock_stock, update_stock, insert_order_line] =
+        prepare(
+            &tx,
+            [
+                WAREHOUSE,
+                DISTRICT,
+                CUSTOMER,
+                ITEM,
+                INSERT_ORDER,
+                INSERT_NEW_ORDER,
+                LOCK_STOCK,
+                UPDATE_STOCK,
+                INSERT_ORDER_LINE,
+            ],
+        )
+        .await?;
+
+    let entry_date = Utc::now().naive_utc();
+    let warehouse_id = request.warehouse_id;
+    let district_id = request.district_id;
+    let customer_id = request.customer_id;
+
+    // Pipeline 1: taxes, the next order ID, the customer and every item
+    let item_params: Vec<Params<1>> = request
+        .order_lines
+        .iter()
+        .map(|line| [&line.item_id as _])
+        .collect();
+    let (warehouse_row, district_row, customer_row, item_rows) = try_join4(
+        tx.query_opt(&warehouse, &[&warehouse_id]),
+        tx.query_opt(&district, &[&warehouse_id, &district_id]),
+        tx.query_opt(&customer, &[&warehouse_id, &district_id, &customer_id]),
+        try_join_all(item_params.iter().map(|params| tx.query_opt(&item, params))),
+    )
+    .await
+    .map_err(|e| ApiError::pipelined("reading warehouse, district, customer and items", e))?;
+
+    let w_tax = match warehouse_row {
+        Some(row) => numeric(row.get(0)).unwrap_or_else(|| BigDecimal::from(0)),
+        None => return Err(ApiError::NotFound(Entity::Warehouse { warehouse_id })),
+    };
+    let (d_tax, order_id) = match district_row {
+        Some(row) => (
+            numeric(row.get(0)).unwrap_or_else(|| BigDecimal::from(0)),
+            row.get::<_, i32>(1),
+        ),
+        None => {
+            return Err(ApiError::NotFound(Entity::District {
+                warehouse_id,
+                district_id,
+            }))
+        }
+    };
+    tracing::Span::current().record("o_id", order_id);
+    let customer = match customer_row {
+        Some(row) => CustomerSummary {
+            customer_id,
+            last_name: row.get::<_, Option<String>>(0).unwrap_or_default(),
+            credit: row.get::<_, Option<String>>(1).unwrap_or_default(),
+            discount: numeric(row.get(2)).unwrap_or_else(|| BigDecimal::from(0)),
+        },
+        None => {
+            return Err(ApiError::NotFound(Entity::Customer {
+                warehouse_id,
+                district_id,
+                customer_id,
+            }))
+        }
+    };
+
+    // The per-line path updates the stock of the lines before an unused item, and
+    // fails on a missing stock row there, before it rolls back
+    let unused_line = item_rows.iter().position(Option::is_none);
+    let entered_lines = &request.order_lines[..unused_line.unwrap_or(request.order_lines.len())];
+
+    let all_local = request
+        .order_lines
+        .iter()
+        .all(|line| line.supply_warehouse_id == warehouse_id);
+    let line_count = request.order_lines.len() as i16;
+    let all_local_flag = i16::from(all_local);
+
+    // Stock rows are locked in (supply warehouse, item ID) order, as the other paths do
+    let mut items_by_warehouse: BTreeMap<i16, Vec<i32>> = BTreeMap::new();
+    for line in &request.order_lines {
+        items_by_warehouse
+            .entry(line.supply_warehouse_id)
+            .or_default()
+            .push(line.item_id);
+    }
+
+    let line_amounts: Vec<BigDecimal> = entered_lines
+        .iter()
+        .zip(&item_rows)
+        .map(|(line, item_row)| {
+            let price = item_row.as_ref().and_then(|row| numeric(row.get(1)));
+            price.unwrap_or_else(|| BigDecimal::from(0)) * BigDecimal::from(line.quantity)
+        })
+        .collect();
+    let remote: Vec<bool> = entered_lines
+        .iter()
+        .map(|line| line.supply_warehouse_id != warehouse_id)
+        .collect();
+    let line_numbers: Vec<i16> = (1..=entered_lines.len() as i16).collect();
+    let amounts: Vec<String> = line_amounts.iter().map(ToString::to_string).collect();
+
+    let lock_params: Vec<Params<2>> = items_by_warehouse
+        .iter()
+        .map(|(supply_warehouse_id, item_ids)| [supply_warehouse_id as _, item_ids as _])
+        .collect();
+    let stock_params: Vec<Params<4>> = entered_lines
+        .iter()
+        .zip(&remote)
+        .map(|(line, is_remote)| {
+            [
+                &line.item_id as _,
+                &line.supply_warehouse_id as _,
+                &line.quantity as _,
+                is_remote as _,
+            ]
+        })
+        .collect();
+    // No order lines when the order rolls back
+    let order_line_params: Vec<Params<8>> = match unused_line {
+        Some(_) => Vec::new(),
+        None => entered_lines
+            .iter()
+            .zip(&line_numbers)
+            .zip(&amounts)
+            .map(|((line, line_number), amount)| {
+                [
+                    &order_id as _,
+                    &district_id as _,
+                    &warehouse_id as _,
+                    line_number as _,
+                    &line.item_id as _,
+                    &line.supply_warehouse_id as _,
+                    &line.quantity as _,
+                    amount as _,
+                ]
+            })
+            .collect(),
+    };
+
+    // Pipeline 2: the order, the stock locks, then each line's stock update and
+    // order line
+    let (_, _, _, stock_rows, _) = try_join5(
+        tx.execute(
+            &insert_order,
+            &[
+                &order_id,
+                &district_id,
+                &warehouse_id,
+                &customer_id,
+                &entry_date,
+                &line_count,
+                &all_local_flag,
+            ],
+        ),
+        tx.execute(&insert_new_order, &[&order_id, &district_id, &warehouse_id]),
+        try_join_all(
+            lock_params
+                .iter()
+                .map(|params| tx.query(&lock_stock, params)),
+        ),
+        try_join_all(
+            stock_params
+                .iter()
+                .map(|params| tx.query_opt(&update_stock, params)),
+        ),
+        try_join_all(
+            order_line_params
+                .iter()
+                .map(|params| tx.execute(&insert_order_line, params)),
+        ),
+    )
+    .await
+    .map_err(|e| ApiError::pipelined("entering order", e))?;
+
+    let mut order_lines = Vec::with_capacity(entered_lines.len());
+    for (((line, stock_row), item_row), line_amount) in entered_lines
+        .iter()
+        .zip(stock_rows)
+        .zip(&item_rows)
+        .zip(line_amounts)
+    {
+        let Some(stock_row) = stock_row else {
+            return Err(ApiError::NotFound(Entity::Stock {
+                warehouse_id: line.supply_warehouse_id,
+                item_id: line.item_id,
+            }));
+        };
+        let item_row = item_row.as_ref().expect("entered lines have items");
+        let item_name: String = item_row.get::<_, Option<String>>(0).unwrap_or_default();
+        let s_data: String = stock_row.get::<_, Option<String>>(1).unwrap_or_default();
+        order_lines.push(OrderLineSummary {
+            item_id: line.item_id,
+            supply_warehouse_id: line.supply_warehouse_id,
+            quantity: line.quantity,
+            brand_generic: brand_generic(&item_name, &s_data),
+            item_name,
+            item_price: numeric(item_row.get(1)).unwrap_or_else(|| BigDecimal::from(0)),
+            stock_quantity: stock_row.get(0),
+            line_amount,
+        });
+    }
+
+    // An unused item ID rolls back the whole order
+    if let Some(line) = unused_line {
+        let item_id = request.order_lines[line].item_id;
+        rollback(tx).await?;
+        tracing::debug!(
+            "New-Order {} for warehouse {} district {} rolled back: unused item {}",
+            order_id,
+            warehouse_id,
+            district_id,
+            item_id
+        );
+        return Ok(NewOrderOutcome::RolledBack(NewOrderRollback {
+            warehouse_id,
+            district_id,
+            order_id,
+            customer,
+            item_id,
+            message: ITEM_NOT_VALID,
+        }));
+    }
+
+    commit(tx).await?;
+
+    let total_amount = order_total(
+        order_lines.iter().map(|line| &line.line_amount).sum(),
+        &w_tax,
+        &d_tax,
+        &customer.discount,
+    );
+    Ok(NewOrderOutcome::Committed(NewOrderResponse {
+        order_id,
+        customer,
+        warehouse_tax: w_tax,
+        district_tax: d_tax,
+        order_entry_date: entry_date,
+        total_amount,
+        order_lines,
+    }))
+}
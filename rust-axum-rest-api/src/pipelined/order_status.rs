@@ -0,0 +1,186 @@
+use chrono::NaiveDateTime;
+use deadpool_postgres::Pool;
+use futures_util::future::try_join3;
+
+use crate::error::{ApiError, Entity};
+use crate::handlers::customers::CustomerSelector;
+use crate::handlers::order_status::{
+    CustomerInfo, CustomerSelectionInfo, CustomerSelectionMethod, LatestOrderInfo, OrderLineInfo,
+    OrderStatusQuery, OrderStatusResponse,
+};
+use crate::transaction::{run_transaction, Attempted, IsolationLevel, TransactionSettings};
+
+use super::{begin, commit, connect, numeric, prepare, Params};
+
+const CUSTOMERS_BY_LAST_NAME: &str = "
+    SELECT c_id
+    FROM customer1
+    WHERE c_w_id = $1 AND c_d_id = $2 AND c_last = $3
+    ORDER BY c_first";
+
+const CUSTOMER: &str = "
+    SELECT c_first, c_middle, c_last, c_balance
+    FROM customer1
+    WHERE c_w_id = $1 AND c_d_id = $2 AND c_id = $3";
+
+const LATEST_ORDER: &str = "
+    SELECT o_id, o_entry_d, o_carrier_id
+    FROM orders1
+    WHERE o_w_id = $1 AND o_d_id = $2 AND o_c_id = $3
+    ORDER BY o_id DESC
+    LIMIT 1";
+
+// The lines of the latest order, found again by the statement itself so it can go in
+// the same pipeline as the order
+const LATEST_ORDER_LINES: &str = "
+    SELECT ol_i_id, ol_supply_w_id, ol_quantity, ol_amount, ol_delivery_d
+    FROM order_line1
+    WHERE ol_w_id = $1 AND ol_d_id = $2
+      AND ol_o_id = (SELECT o_id FROM orders1
+                     WHERE o_w_id = $1 AND o_d_id = $2 AND o_c_id = $3
+                     ORDER BY o_id DESC
+                     LIMIT 1)
+    ORDER BY ol_number ASC";
+
+// Order-Status with validation and retries on the pipelined driver
+pub async fn execute_order_status(
+    pool: &Pool,
+    settings: &TransactionSettings,
+    params: &OrderStatusQuery,
+) -> Result<Attempted<OrderStatusResponse>, ApiError> {
+    let customer_selector =
+        CustomerSelector::from_request(params.customer_id, params.customer_last_name.clone())
+            .ok_or_else(|| {
+                ApiError::bad_request(
+                    "exactly one of customer_id or customer_last_name must be provided",
+                )
+            })?;
+
+    let isolation = settings.isolation_levels.order_status;
+    run_transaction(&settings.retry_policy, "order-status", || {
+        process_order_status(
+            pool,
+            isolation,
+            params.warehouse_id,
+            params.district_id,
+            &customer_selector,
+        )
+    })
+    .await
+}
+
+// One pipeline for the customer, the latest order and its lines; by last name the
+// customer is looked up in one before
+#[tracing::instrument(
+    name = "order_status",
+    skip_all,
+    fields(w_id = warehouse_id, d_id = district_id, c_id = tracing::field::Empty, o_id = tracing::field::Empty, driver = "pipelined")
+)]
+async fn process_order_status(
+    pool: &Pool,
+    isolation: Option<IsolationLevel>,
+    warehouse_id: i16,
+    district_id: i16,
+    customer_selector: &CustomerSelector,
+) -> Result<OrderStatusResponse, ApiError> {
+    let mut client = connect(pool).await?;
+    let tx = begin(&mut client, isolation).await?;
+    let [customers_by_last_name, customer, latest_order, latest_order_lines] = prepare(
+        &tx,
+        [
+            CUSTOMERS_BY_LAST_NAME,
+            CUSTOMER,
+            LATEST_ORDER,
+            LATEST_ORDER_LINES,
+        ],
+    )
+    .await?;
+
+    // Resolve the customer, picking the middle customer when selecting by last name
+    let customer_selection = match customer_selector {
+        CustomerSelector::Id(customer_id) => CustomerSelectionInfo {
+            selected_by: CustomerSelectionMethod::CustomerId,
+            c_id: *customer_id,
+            name_count: 1,
+        },
+        CustomerSelector::LastName(last_name) => {
+            let customer_ids = tx
+                .query(
+                    &customers_by_last_name,
+                    &[&warehouse_id, &district_id, last_name],
+                )
+                .await
+                .map_err(|e| ApiError::pipelined("selecting customer by last name", e))?;
+            if customer_ids.is_empty() {
+                return Err(ApiError::NotFound(Entity::CustomerLastName {
+                    warehouse_id,
+                    district_id,
+                    customer_last_name: last_name.clone(),
+                }));
+            }
+            CustomerSelectionInfo {
+                selected_by: CustomerSelectionMethod::LastName,
+                c_id: customer_ids[(customer_ids.len() - 1) / 2].get(0),
+                name_count: customer_ids.len() as i64,
+            }
+        }
+    };
+    let customer_id = customer_selection.c_id;
+    tracing::Span::current().record("c_id", customer_id);
+
+    // The customer, its latest order and that order's lines
+    let params: Params<3> = [&warehouse_id, &district_id, &customer_id];
+    let (customer_row, order_row, order_line_rows) = try_join3(
+        tx.query_opt(&customer, &params),
+        tx.query_opt(&latest_order, &params),
+        tx.query(&latest_order_lines, &params),
+    )
+    .await
+    .map_err(|e| ApiError::pipelined("fetching customer and latest order", e))?;
+
+    let Some(customer_row) = customer_row else {
+        return Err(ApiError::NotFound(Entity::Customer {
+            warehouse_id,
+            district_id,
+            customer_id,
+        }));
+    };
+    let Some(order_row) = order_row else {
+        // No orders found for customer
+        return Err(ApiError::NotFound(Entity::CustomerOrders {
+            warehouse_id,
+            district_id,
+            customer_id,
+        }));
+    };
+    let latest_order_info = LatestOrderInfo {
+        o_id: order_row.get(0),
+        o_entry_d: order_row.get::<_, Option<NaiveDateTime>>(1),
+        o_carrier_id: order_row.get::<_, Option<i16>>(2),
+    };
+    tracing::Span::current().record("o_id", latest_order_info.o_id);
+
+    commit(tx).await?;
+
+    Ok(OrderStatusResponse {
+        customer_selection,
+        customer: CustomerInfo {
+            c_id: customer_id,
+            c_first: customer_row.get(0),
+            c_middle: customer_row.get(1),
+            c_last: customer_row.get(2),
+            c_balance: numeric(customer_row.get(3)),
+        },
+        latest_order: latest_order_info,
+        order_lines: order_line_rows
+            .iter()
+            .map(|row| OrderLineInfo {
+                ol_i_id: row.get(0),
+                ol_supply_w_id: row.get(1),
+                ol_quantity: row.get(2),
+                ol_amount: numeric(row.get(3)),
+                ol_delivery_d: row.get(4),
+            })
+            .collect(),
+    })
+}
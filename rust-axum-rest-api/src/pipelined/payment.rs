@@ -0,0 +1,302 @@
+use bigdecimal::BigDecimal;
+use chrono::{NaiveDateTime, Utc};
+use deadpool_postgres::tokio_postgres::{Row, Statement};
+use deadpool_postgres::{Pool, Transaction};
+use futures_util::future::{try_join, try_join3};
+
+use crate::error::{ApiError, Entity};
+use crate::handlers::customers::CustomerSelector;
+use crate::handlers::payment::{
+    DistrictInfo, PaymentCustomerInfo, PaymentInput, PaymentRequest, PaymentResponse, WarehouseInfo,
+};
+use crate::transaction::{run_transaction, Attempted, IsolationLevel, TransactionSettings};
+
+use super::{begin, commit, connect, numeric, prepare};
+
+const UPDATE_WAREHOUSE: &str = "
+    UPDATE warehouse1 SET w_ytd = COALESCE(w_ytd, 0) + $1::text::numeric
+    WHERE w_id = $2
+    RETURNING w_name, w_street_1, w_street_2, w_city, w_state, w_zip";
+
+const UPDATE_DISTRICT: &str = "
+    UPDATE district1 SET d_ytd = COALESCE(d_ytd, 0) + $1::text::numeric
+    WHERE d_w_id = $2 AND d_id = $3
+    RETURNING d_name, d_street_1, d_street_2, d_city, d_state, d_zip";
+
+const CUSTOMERS_BY_LAST_NAME: &str = "
+    SELECT c_id
+    FROM customer1
+    WHERE c_w_id = $1 AND c_d_id = $2 AND c_last = $3
+    ORDER BY c_first";
+
+const UPDATE_CUSTOMER: &str = "
+    UPDATE customer1
+    SET c_balance = COALESCE(c_balance, 0) - $1::text::numeric,
+        c_ytd_payment = COALESCE(c_ytd_payment, 0) + $1::text::numeric,
+        c_payment_cnt = COALESCE(c_payment_cnt, 0) + 1,
+        c_data = CASE WHEN c_credit = 'BC' THEN left($2 || COALESCE(c_data, ''), 500)
+                      ELSE c_data
+                 END
+    WHERE c_w_id = $3 AND c_d_id = $4 AND c_id = $5
+    RETURNING c_first, c_middle, c_last, c_street_1, c_street_2, c_city, c_state, c_zip,
+              c_phone, c_since, c_credit, c_credit_lim, c_discount, c_balance";
+
+// H_DATA is built from the names in the tables, so the insert need not wait for the
+// warehouse and district updates to return them
+const INSERT_HISTORY: &str = "
+    INSERT INTO history1 (h_c_id, h_c_d_id, h_c_w_id, h_d_id, h_w_id, h_date, h_amount, h_data)
+    SELECT $1, $2, $3, $4, $5, $6, $7::text::numeric,
+           COALESCE(w_name, '') || ' ' || COALESCE(d_name, '')
+    FROM warehouse1, district1
+    WHERE w_id = $5 AND d_w_id = $5 AND d_id = $4";
+
+// Payment with validation and retries on the pipelined driver
+pub async fn execute_payment(
+    pool: &Pool,
+    settings: &TransactionSettings,
+    request: &PaymentRequest,
+) -> Result<Attempted<PaymentResponse>, ApiError> {
+    let input = PaymentInput::from_request(request)?;
+
+    let isolation = settings.isolation_levels.payment;
+    run_transaction(&settings.retry_policy, "payment", || {
+        process_payment(pool, isolation, &input)
+    })
+    .await
+}
+
+// One pipeline when the customer is selected by ID. By last name the customer's
+// update and the history row wait for the name lookup in a second one.
+#[tracing::instrument(
+    name = "payment",
+    skip_all,
+    fields(
+        w_id = input.warehouse_id,
+        d_id = input.district_id,
+        c_w_id = input.customer_warehouse_id,
+        c_d_id = input.customer_district_id,
+        c_id = tracing::field::Empty,
+        driver = "pipelined"
+    )
+)]
+async fn process_payment(
+    pool: &Pool,
+    isolation: Option<IsolationLevel>,
+    input: &PaymentInput,
+) -> Result<PaymentResponse, ApiError> {
+    let payment_date = Utc::now().naive_utc();
+    let amount = input.amount.to_string();
+
+    let mut client = connect(pool).await?;
+    let tx = begin(&mut client, isolation).await?;
+    let [update_warehouse, update_district, customers_by_last_name, update_customer, insert_history] =
+        prepare(
+            &tx,
+            [
+                UPDATE_WAREHOUSE,
+                UPDATE_DISTRICT,
+                CUSTOMERS_BY_LAST_NAME,
+                UPDATE_CUSTOMER,
+                INSERT_HISTORY,
+            ],
+        )
+        .await?;
+    let statements = CustomerStatements {
+        update_customer: &update_customer,
+        insert_history: &insert_history,
+    };
+
+    // Pipeline 1: the warehouse, the district, and the customer with its history row,
+    // or the customers sharing the last name
+    let customer = async {
+        match &input.customer {
+            CustomerSelector::Id(customer_id) => update_customer_and_history(
+                &tx,
+                &statements,
+                input,
+                *customer_id,
+                &amount,
+                payment_date,
+            )
+            .await
+            .map(|row| CustomerLookup::Updated(*customer_id, row)),
+            CustomerSelector::LastName(last_name) => tx
+                .query(
+                    &customers_by_last_name,
+                    &[
+                        &input.customer_warehouse_id,
+                        &input.customer_district_id,
+                        last_name,
+                    ],
+                )
+                .await
+                .map(|rows| CustomerLookup::ByLastName(last_name, rows)),
+        }
+    };
+    let (warehouse_row, district_row, customer) = try_join3(
+        tx.query_opt(&update_warehouse, &[&amount, &input.warehouse_id]),
+        tx.query_opt(
+            &update_district,
+            &[&amount, &input.warehouse_id, &input.district_id],
+        ),
+        customer,
+    )
+    .await
+    .map_err(|e| ApiError::pipelined("updating warehouse, district and customer", e))?;
+
+    let Some(warehouse) = warehouse_row else {
+        return Err(ApiError::NotFound(Entity::Warehouse {
+            warehouse_id: input.warehouse_id,
+        }));
+    };
+    let Some(district) = district_row else {
+        return Err(ApiError::NotFound(Entity::District {
+            warehouse_id: input.warehouse_id,
+            district_id: input.district_id,
+        }));
+    };
+
+    let (customer_id, customer_row) = match customer {
+        CustomerLookup::Updated(customer_id, row) => (customer_id, row),
+        // Pipeline 2: the middle customer of those sharing the last name, and the
+        // history row
+        CustomerLookup::ByLastName(last_name, customer_ids) => {
+            if customer_ids.is_empty() {
+                return Err(ApiError::NotFound(Entity::CustomerLastName {
+                    warehouse_id: input.customer_warehouse_id,
+                    district_id: input.customer_district_id,
+                    customer_last_name: last_name.to_string(),
+                }));
+            }
+            let customer_id: i32 = customer_ids[(customer_ids.len() - 1) / 2].get(0);
+            let row = update_customer_and_history(
+                &tx,
+                &statements,
+                input,
+                customer_id,
+                &amount,
+                payment_date,
+            )
+            .await
+            .map_err(|e| ApiError::pipelined("updating customer", e))?;
+            (customer_id, row)
+        }
+    };
+    tracing::Span::current().record("c_id", customer_id);
+    let Some(customer) = customer_row else {
+        return Err(ApiError::NotFound(Entity::Customer {
+            warehouse_id: input.customer_warehouse_id,
+            district_id: input.customer_district_id,
+            customer_id,
+        }));
+    };
+
+    commit(tx).await?;
+
+    let text = |row: &Row, column: usize| row.get::<_, Option<String>>(column).unwrap_or_default();
+    Ok(PaymentResponse {
+        warehouse: WarehouseInfo {
+            w_id: input.warehouse_id,
+            w_name: text(&warehouse, 0),
+            w_street_1: text(&warehouse, 1),
+            w_street_2: text(&warehouse, 2),
+            w_city: text(&warehouse, 3),
+            w_state: text(&warehouse, 4),
+            w_zip: text(&warehouse, 5),
+        },
+        district: DistrictInfo {
+            d_id: input.district_id,
+            d_name: text(&district, 0),
+            d_street_1: text(&district, 1),
+            d_street_2: text(&district, 2),
+            d_city: text(&district, 3),
+            d_state: text(&district, 4),
+            d_zip: text(&district, 5),
+        },
+        customer: PaymentCustomerInfo {
+            c_id: customer_id,
+            c_d_id: input.customer_district_id,
+            c_w_id: input.customer_warehouse_id,
+            c_first: text(&customer, 0),
+            c_middle: text(&customer, 1),
+            c_last: text(&customer, 2),
+            c_street_1: text(&customer, 3),
+            c_street_2: text(&customer, 4),
+            c_city: text(&customer, 5),
+            c_state: text(&customer, 6),
+            c_zip: text(&customer, 7),
+            c_phone: text(&customer, 8),
+            c_since: customer
+                .get::<_, Option<NaiveDateTime>>(9)
+                .unwrap_or_default(),
+            c_credit: text(&customer, 10),
+            c_credit_lim: customer.get::<_, Option<i64>>(11).unwrap_or(0),
+            c_discount: numeric(customer.get(12)).unwrap_or_else(|| BigDecimal::from(0)),
+            c_balance: numeric(customer.get(13)).unwrap_or_else(|| BigDecimal::from(0)),
+        },
+        payment_date,
+        payment_amount: input.amount.clone(),
+    })
+}
+
+// The customer as updated, or the IDs of those with the requested last name
+enum CustomerLookup<'a> {
+    Updated(i32, Option<Row>),
+    ByLastName(&'a str, Vec<Row>),
+}
+
+struct CustomerStatements<'a> {
+    update_customer: &'a Statement,
+    insert_history: &'a Statement,
+}
+
+// The customer's update (None when there is no such customer) and the history row,
+// sent together
+async fn update_customer_and_history(
+    tx: &Transaction<'_>,
+    statements: &CustomerStatements<'_>,
+    input: &PaymentInput,
+    customer_id: i32,
+    amount: &str,
+    payment_date: NaiveDateTime,
+) -> Result<Option<Row>, deadpool_postgres::tokio_postgres::Error> {
+    // For bad credit customers the payment is prepended to C_DATA (TPC-C 2.5.2.2),
+    // which is cut to 500 characters
+    let payment_info = format!(
+        "{}|{}|{}|{}|{}|{}|{}|",
+        customer_id,
+        input.customer_district_id,
+        input.customer_warehouse_id,
+        input.district_id,
+        input.warehouse_id,
+        input.amount,
+        input.amount
+    );
+
+    let (customer_row, _) = try_join(
+        tx.query_opt(
+            statements.update_customer,
+            &[
+                &amount,
+                &payment_info,
+                &input.customer_warehouse_id,
+                &input.customer_district_id,
+                &customer_id,
+            ],
+        ),
+        tx.execute(
+            statements.insert_history,
+            &[
+                &customer_id,
+                &input.customer_district_id,
+                &input.customer_warehouse_id,
+                &input.district_id,
+                &input.warehouse_id,
+                &payment_date,
+                &amount,
+            ],
+        ),
+    )
+    .await?;
+    Ok(customer_row)
+}
@@ -0,0 +1,82 @@
+use deadpool_postgres::Pool;
+use futures_util::future::try_join;
+
+use crate::error::{ApiError, Entity};
+use crate::handlers::stock_level::{StockLevelQuery, StockLevelResponse};
+use crate::transaction::{run_transaction, Attempted, IsolationLevel, TransactionSettings};
+
+use super::{begin, commit, connect, prepare, Params};
+
+const NEXT_ORDER_ID: &str = "SELECT d_next_o_id FROM district1 WHERE d_id = $2 AND d_w_id = $1";
+
+// The district's next order ID is read by the count itself, so both go in one pipeline
+const LOW_STOCK_COUNT: &str = "
+    SELECT COUNT(DISTINCT (s_i_id))
+    FROM district1 d, order_line1 ol, stock1 s
+    WHERE d.d_w_id = $1
+      AND d.d_id = $2
+      AND ol.ol_w_id = $1
+      AND ol.ol_d_id = $2
+      AND ol.ol_o_id < d.d_next_o_id
+      AND ol.ol_o_id >= d.d_next_o_id - 20
+      AND s.s_w_id = $1
+      AND s.s_i_id = ol.ol_i_id
+      AND s.s_quantity < $3";
+
+// Stock-Level with retries on the pipelined driver
+pub async fn execute_stock_level(
+    pool: &Pool,
+    settings: &TransactionSettings,
+    params: &StockLevelQuery,
+) -> Result<Attempted<StockLevelResponse>, ApiError> {
+    let isolation = settings.isolation_levels.stock_level;
+    run_transaction(&settings.retry_policy, "stock-level", || {
+        process_stock_level(pool, isolation, params)
+    })
+    .await
+}
+
+#[tracing::instrument(
+    name = "stock_level",
+    skip_all,
+    fields(w_id = params.warehouse_id, d_id = params.district_id, threshold = params.threshold, driver = "pipelined")
+)]
+async fn process_stock_level(
+    pool: &Pool,
+    isolation: Option<IsolationLevel>,
+    params: &StockLevelQuery,
+) -> Result<StockLevelResponse, ApiError> {
+    let mut client = connect(pool).await?;
+    let tx = begin(&mut client, isolation).await?;
+    let [next_order_id, low_stock_count] = prepare(&tx, [NEXT_ORDER_ID, LOW_STOCK_COUNT]).await?;
+
+    let district_params: Params<2> = [&params.warehouse_id, &params.district_id];
+    let (district_row, count_row) = try_join(
+        tx.query_opt(&next_order_id, &district_params),
+        tx.query_one(
+            &low_stock_count,
+            &[&params.warehouse_id, &params.district_id, &params.threshold],
+        ),
+    )
+    .await
+    .map_err(|e| ApiError::pipelined("counting low stock items", e))?;
+
+    if district_row
+        .and_then(|row| row.get::<_, Option<i32>>(0))
+        .is_none()
+    {
+        return Err(ApiError::NotFound(Entity::District {
+            warehouse_id: params.warehouse_id,
+            district_id: params.district_id,
+        }));
+    }
+
+    commit(tx).await?;
+
+    Ok(StockLevelResponse {
+        warehouse_id: params.warehouse_id,
+        district_id: params.district_id,
+        threshold: params.threshold,
+        low_stock_count: count_row.get(0),
+    })
+}
@@ -0,0 +1,111 @@
+// Random data generation following TPC-C clause 2.1.6 and 4.3.2
+use rand::Rng;
+
+const NAME_SYLLABLES: [&str; 10] = [
+    "BAR", "OUGHT", "ABLE", "PRI", "PRES", "ESE", "ANTI", "CALLY", "ATION", "EING",
+];
+
+const ALPHANUMERIC: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+
+// Customer last name built from three syllables of `num` (0..=999)
+pub fn last_name(num: u32) -> String {
+    let mut name = String::with_capacity(15);
+    name.push_str(NAME_SYLLABLES[(num / 100 % 10) as usize]);
+    name.push_str(NAME_SYLLABLES[(num / 10 % 10) as usize]);
+    name.push_str(NAME_SYLLABLES[(num % 10) as usize]);
+    name
+}
+
+// The run-time constants C of NURand(A, x, y) for A = 255, 1023 and 8191
+#[derive(Clone, Copy, Debug)]
+pub struct NuRandConstants {
+    pub c_last: u32,
+    pub c_id: u32,
+    pub ol_i_id: u32,
+}
+
+impl NuRandConstants {
+    pub fn random(rng: &mut impl Rng) -> Self {
+        NuRandConstants {
+            c_last: rng.gen_range(0..=255),
+            c_id: rng.gen_range(0..=1023),
+            ol_i_id: rng.gen_range(0..=8191),
+        }
+    }
+
+    // Constants for a measurement run against data loaded with `load`:
+    // C_LAST must differ from the load value by 65..=119, but not 96 or 112 (2.1.6.1)
+    pub fn for_run(load: &NuRandConstants, rng: &mut impl Rng) -> Self {
+        let mut run = NuRandConstants::random(rng);
+        loop {
+            let delta = run.c_last.abs_diff(load.c_last);
+            if (65..=119).contains(&delta) && delta != 96 && delta != 112 {
+                return run;
+            }
+            run.c_last = rng.gen_range(0..=255);
+        }
+    }
+
+    pub fn customer_last_name_number(&self, rng: &mut impl Rng) -> u32 {
+        nurand(rng, 255, 0, 999, self.c_last)
+    }
+
+    pub fn customer_id(&self, rng: &mut impl Rng) -> u32 {
+        nurand(rng, 1023, 1, 3000, self.c_id)
+    }
+
+    pub fn item_id(&self, rng: &mut impl Rng) -> u32 {
+        nurand(rng, 8191, 1, 100_000, self.ol_i_id)
+    }
+}
+
+// Non-uniform random number: (((random(0, A) | random(x, y)) + C) % (y - x + 1)) + x
+pub fn nurand(rng: &mut impl Rng, a: u32, x: u32, y: u32, c: u32) -> u32 {
+    (((rng.gen_range(0..=a) | rng.gen_range(x..=y)) + c) % (y - x + 1)) + x
+}
+
+// Random alphanumeric string with a length in min..=max
+pub fn alpha_string(rng: &mut impl Rng, min: usize, max: usize) -> String {
+    let len = rng.gen_range(min..=max);
+    (0..len)
+        .map(|_| ALPHANUMERIC[rng.gen_range(0..ALPHANUMERIC.len())] as char)
+        .collect()
+}
+
+// Random string of `len` digits
+pub fn numeric_string(rng: &mut impl Rng, len: usize) -> String {
+    (0..len)
+        .map(|_| char::from(b'0' + rng.gen_range(0..10)))
+        .collect()
+}
+
+// Zip code: four random digits followed by "11111"
+pub fn zip(rng: &mut impl Rng) -> String {
+    let mut zip = numeric_string(rng, 4);
+    zip.push_str("11111");
+    zip
+}
+
+// Two random upper-case letters
+pub fn state(rng: &mut impl Rng) -> String {
+    (0..2)
+        .map(|_| char::from(b'A' + rng.gen_range(0..26)))
+        .collect()
+}
+
+// I_DATA / S_DATA: an a-string of 26..=50 characters, with "ORIGINAL" at a random
+// position in `original_percent` percent of the rows
+pub fn data_string(rng: &mut impl Rng, original_percent: u32) -> String {
+    let mut data = alpha_string(rng, 26, 50);
+    if rng.gen_range(1..=100) <= original_percent {
+        let position = rng.gen_range(0..=data.len() - 8);
+        data.replace_range(position..position + 8, "ORIGINAL");
+    }
+    data
+}
+
+// Random amount with two decimals in min_cents..=max_cents, formatted for SQL/COPY
+pub fn amount(rng: &mut impl Rng, min_cents: u32, max_cents: u32) -> String {
+    let cents = rng.gen_range(min_cents..=max_cents);
+    format!("{}.{:02}", cents / 100, cents % 100)
+}
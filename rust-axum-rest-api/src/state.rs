@@ -0,0 +1,90 @@
+use axum::extract::FromRef;
+use sqlx::{Pool, Postgres};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::delivery_queue::DeliveryQueue;
+use crate::handlers::new_order::NewOrderStats;
+use crate::table_set::TableSets;
+use crate::transaction::TransactionSettings;
+
+// Shared application state; handlers extract only the parts they need
+#[derive(Clone)]
+pub struct AppState {
+    pub pool: Pool<Postgres>,
+    pub table_sets: TableSets,
+    pub delivery_queue: DeliveryQueue,
+    pub new_order_stats: NewOrderStats,
+    pub transaction_settings: TransactionSettings,
+}
+
+impl AppState {
+    // Finish the deferred deliveries by `deadline`, log the final New-Order counts
+    // and close every pool. Call once the server has stopped taking requests.
+    pub async fn shutdown(&self, deadline: Instant) {
+        // Idle workers and pools need a moment to wind down even past the deadline
+        let deadline = deadline.max(Instant::now() + Duration::from_secs(1));
+        let unfinished = self.delivery_queue.shutdown(deadline).await;
+        if unfinished > 0 {
+            tracing::warn!("{} deferred deliveries were not finished", unfinished);
+        } else {
+            tracing::info!("Deferred delivery queue drained");
+        }
+
+        let stats = self.new_order_stats.snapshot();
+        tracing::info!(
+            "New-Order totals: {} committed, {} rolled back, {} failed",
+            stats.committed,
+            stats.rolled_back,
+            stats.failed
+        );
+
+        let mut pools: Vec<_> = self
+            .table_sets
+            .numbers()
+            .into_iter()
+            .filter_map(|number| self.table_sets.pool(number).cloned())
+            .collect();
+        pools.push(self.pool.clone());
+        for pool in pools {
+            if tokio::time::timeout_at(deadline, pool.close())
+                .await
+                .is_err()
+            {
+                tracing::warn!("Timed out closing a connection pool");
+            }
+        }
+        self.table_sets.close_pipelined();
+        tracing::info!("Connection pools closed");
+    }
+}
+
+impl FromRef<AppState> for Pool<Postgres> {
+    fn from_ref(state: &AppState) -> Self {
+        state.pool.clone()
+    }
+}
+
+impl FromRef<AppState> for TableSets {
+    fn from_ref(state: &AppState) -> Self {
+        state.table_sets.clone()
+    }
+}
+
+impl FromRef<AppState> for DeliveryQueue {
+    fn from_ref(state: &AppState) -> Self {
+        state.delivery_queue.clone()
+    }
+}
+
+impl FromRef<AppState> for TransactionSettings {
+    fn from_ref(state: &AppState) -> Self {
+        state.transaction_settings
+    }
+}
+
+impl FromRef<AppState> for NewOrderStats {
+    fn from_ref(state: &AppState) -> Self {
+        state.new_order_stats.clone()
+    }
+}
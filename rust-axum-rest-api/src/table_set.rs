@@ -0,0 +1,383 @@
+use axum::{
+    extract::{FromRef, FromRequestParts, Query},
+    http::request::Parts,
+};
+use serde::Deserialize;
+use sqlx::{Pool, Postgres};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+use crate::config::DatabaseConfig;
+use crate::error::{ApiError, Entity};
+use crate::transaction::Driver;
+use crate::transaction_pool::TransactionPool;
+
+// Header and query parameter selecting the table set of a request
+pub const TABLE_SET_HEADER: &str = "x-table-set";
+pub const TABLE_SET_PARAM: &str = "table_set";
+
+// The nine TPC-C tables. sysbench-tpcc's --tables=N creates warehouse1..warehouseN,
+// district1..districtN and so on; table set K is every table with suffix K.
+pub const TPCC_TABLES: [&str; 9] = [
+    "warehouse",
+    "district",
+    "customer",
+    "history",
+    "orders",
+    "new_orders",
+    "order_line",
+    "stock",
+    "item",
+];
+
+// Pools for the table sets that exist in the database.
+//
+// All SQL names the set-1 tables (warehouse1, stock1, ...) so it stays checked at
+// compile time. Every other set K needs a schema tpcc_tablesK holding a view per
+// table (tpcc_tablesK.warehouse1 -> warehouseK, ...), created by create_views, and
+// gets its own pool whose connections put that schema first on the search_path. The views are simple,
+// so PostgreSQL inlines them and they accept INSERT/UPDATE/DELETE and FOR UPDATE.
+//
+// The sets share the configured pool's max_connections: a single set uses that pool
+// itself, several get a lazily connected pool each with an equal share of it, so the
+// server never holds more connections than configured for the transactions.
+#[derive(Clone)]
+pub struct TableSets {
+    pools: Arc<BTreeMap<u32, Pool<Postgres>>>,
+    // Pools of the pipelined driver when it runs the transactions, one per table set
+    #[cfg(feature = "pipelined-driver")]
+    pipelined: Arc<BTreeMap<u32, deadpool_postgres::Pool>>,
+}
+
+impl TableSets {
+    // Only table set 1, served by `pool`
+    pub fn single(pool: Pool<Postgres>) -> Self {
+        TableSets {
+            pools: Arc::new(BTreeMap::from([(1, pool)])),
+            #[cfg(feature = "pipelined-driver")]
+            pipelined: Arc::default(),
+        }
+    }
+
+    // Find the complete table sets in the current schema and prepare a pool for each.
+    // Only reads the catalog: sets other than 1 without their views are left out.
+    pub async fn discover(pool: &Pool<Postgres>) -> Self {
+        let numbers = match usable_table_sets(pool).await {
+            Ok(numbers) if !numbers.is_empty() => numbers,
+            Ok(_) => {
+                tracing::warn!("No complete TPC-C table set found; using table set 1");
+                return TableSets::single(pool.clone());
+            }
+            Err(e) => {
+                tracing::warn!("Failed to look up table sets, using table set 1: {}", e);
+                return TableSets::single(pool.clone());
+            }
+        };
+
+        let count = numbers.len() as u32;
+        let mut pools = BTreeMap::new();
+        for number in numbers {
+            // A lone set 1 needs neither views nor a pool of its own
+            if count == 1 && number == 1 {
+                pools.insert(1, pool.clone());
+                continue;
+            }
+            match table_set_pool(pool, number, count).await {
+                Ok(set_pool) => {
+                    pools.insert(number, set_pool);
+                }
+                Err(e) => tracing::warn!("Table set {} is unavailable: {}", number, e),
+            }
+        }
+        if pools.is_empty() {
+            pools.insert(1, pool.clone());
+        }
+
+        tracing::info!(
+            "Table sets: {:?}, up to {} connections each",
+            pools.keys().copied().collect::<Vec<_>>(),
+            pools
+                .values()
+                .next()
+                .map_or(0, |pool| pool.options().get_max_connections())
+        );
+        TableSets {
+            pools: Arc::new(pools),
+            #[cfg(feature = "pipelined-driver")]
+            pipelined: Arc::default(),
+        }
+    }
+
+    // Run the TPC-C transactions on `driver`. The pipelined driver takes the whole
+    // connection budget of `database` but one connection, left to the configured pool
+    // for the health checks (see health_pool), and splits an equal share of the rest
+    // per set: its own pool, connecting with the options of the set's sqlx pool and
+    // the URL and timeout of `database`, gets three quarters, and a new sqlx pool keeps
+    // the rest (at least one) for deferred deliveries and the other endpoints. Fails
+    // if the pipelined pools cannot be built.
+    #[cfg_attr(not(feature = "pipelined-driver"), allow(unused_variables))]
+    pub fn with_driver(self, driver: Driver, database: &DatabaseConfig) -> Result<Self, String> {
+        match driver {
+            Driver::Sqlx => Ok(self),
+            #[cfg(feature = "pipelined-driver")]
+            Driver::Pipelined => {
+                let connections =
+                    (database.max_connections.saturating_sub(1) / self.pools.len() as u32).max(2);
+                let sqlx_connections = (connections / 4).max(1);
+                let mut pools = BTreeMap::new();
+                let mut pipelined = BTreeMap::new();
+                for (&number, pool) in self.pools.iter() {
+                    let options = pool.options();
+                    let connect_options = pool.connect_options();
+                    let pipelined_pool = crate::pipelined::pool(
+                        database,
+                        connect_options.get_options(),
+                        (connections - sqlx_connections) as usize,
+                    )
+                    .map_err(|e| format!("table set {}: {}", number, e))?;
+                    pipelined.insert(number, pipelined_pool);
+                    let sqlx_pool = options
+                        .clone()
+                        .max_connections(sqlx_connections)
+                        .min_connections(options.get_min_connections().min(sqlx_connections))
+                        .connect_lazy_with((*connect_options).clone());
+                    pools.insert(number, sqlx_pool);
+                }
+                Ok(TableSets {
+                    pools: Arc::new(pools),
+                    pipelined: Arc::new(pipelined),
+                })
+            }
+        }
+    }
+
+    // The pool for the health checks: `pool`, the configured one, on sqlx; with the
+    // pipelined driver a lazily connected pool like it holding the one connection
+    // with_driver left over, so `pool` itself can be dropped
+    pub fn health_pool(&self, pool: &Pool<Postgres>) -> Pool<Postgres> {
+        match self.driver() {
+            Driver::Sqlx => pool.clone(),
+            #[cfg(feature = "pipelined-driver")]
+            Driver::Pipelined => pool
+                .options()
+                .clone()
+                .max_connections(1)
+                .min_connections(0)
+                .connect_lazy_with((*pool.connect_options()).clone()),
+        }
+    }
+
+    pub fn numbers(&self) -> Vec<u32> {
+        self.pools.keys().copied().collect()
+    }
+
+    pub fn pool(&self, number: u32) -> Option<&Pool<Postgres>> {
+        self.pools.get(&number)
+    }
+
+    // Where the transactions of table set `number` run
+    pub fn transaction_pool(&self, number: u32) -> Option<TransactionPool> {
+        #[cfg(feature = "pipelined-driver")]
+        if let Some(pool) = self.pipelined.get(&number) {
+            return Some(TransactionPool::Pipelined(pool.clone()));
+        }
+        self.pool(number).cloned().map(TransactionPool::Sqlx)
+    }
+
+    // The driver the transactions run on
+    pub fn driver(&self) -> Driver {
+        #[cfg(feature = "pipelined-driver")]
+        if !self.pipelined.is_empty() {
+            return Driver::Pipelined;
+        }
+        Driver::Sqlx
+    }
+
+    // Close the pipelined driver's pools, if any; the sqlx pools are closed by their owner
+    pub fn close_pipelined(&self) {
+        #[cfg(feature = "pipelined-driver")]
+        for pool in self.pipelined.values() {
+            pool.close();
+        }
+    }
+}
+
+// Table set numbers that have all nine tables
+async fn find_table_sets(pool: &Pool<Postgres>) -> Result<Vec<u32>, sqlx::Error> {
+    let table_names = sqlx::query_scalar!(
+        r#"SELECT tablename::text AS "tablename!" FROM pg_tables WHERE schemaname = current_schema()"#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut tables_by_set: BTreeMap<u32, BTreeSet<&str>> = BTreeMap::new();
+    for name in &table_names {
+        for table in TPCC_TABLES {
+            let Some(suffix) = name.strip_prefix(table) else {
+                continue;
+            };
+            if !suffix.is_empty() && suffix.bytes().all(|b| b.is_ascii_digit()) {
+                if let Ok(number) = suffix.parse::<u32>() {
+                    tables_by_set.entry(number).or_default().insert(table);
+                }
+            }
+        }
+    }
+
+    Ok(tables_by_set
+        .into_iter()
+        .filter(|(_, tables)| tables.len() == TPCC_TABLES.len())
+        .map(|(number, _)| number)
+        .collect())
+}
+
+// The complete table sets, leaving out (with a warning) those whose views are missing
+async fn usable_table_sets(pool: &Pool<Postgres>) -> Result<Vec<u32>, sqlx::Error> {
+    let mut numbers = Vec::new();
+    for number in find_table_sets(pool).await? {
+        let views = view_names();
+        let view_count = sqlx::query_scalar!(
+            r#"SELECT count(*) AS "count!" FROM pg_views WHERE schemaname = $1 AND viewname = ANY($2)"#,
+            view_schema(number),
+            &views
+        )
+        .fetch_one(pool)
+        .await?;
+        if number == 1 || view_count == views.len() as i64 {
+            numbers.push(number);
+        } else {
+            tracing::warn!(
+                "Table set {} has no views in {}; create them with `migrate` or --run-migrations true",
+                number,
+                view_schema(number)
+            );
+        }
+    }
+    Ok(numbers)
+}
+
+// Create (or refresh) the views of every complete table set but the first, and
+// return the sets. Run by the loader and with the migrations, never by discover.
+pub async fn create_views(pool: &Pool<Postgres>) -> Result<Vec<u32>, sqlx::Error> {
+    let numbers = find_table_sets(pool).await?;
+
+    let mut tx = pool.begin().await?;
+    // Several processes may migrate at once; DDL on the same views must not interleave
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtext('tpcc_table_sets'))")
+        .execute(&mut *tx)
+        .await?;
+    let base_schema = sqlx::query_scalar!(r#"SELECT current_schema() AS "schema!""#)
+        .fetch_one(&mut *tx)
+        .await?;
+    for &number in numbers.iter().filter(|&&number| number != 1) {
+        let view_schema = view_schema(number);
+        sqlx::query(&format!("CREATE SCHEMA IF NOT EXISTS {}", view_schema))
+            .execute(&mut *tx)
+            .await?;
+        for table in TPCC_TABLES {
+            sqlx::query(&format!(
+                r#"CREATE OR REPLACE VIEW {view_schema}.{table}1 AS SELECT * FROM "{base_schema}".{table}{number}"#
+            ))
+            .execute(&mut *tx)
+            .await?;
+        }
+    }
+    tx.commit().await?;
+    Ok(numbers)
+}
+
+fn view_schema(number: u32) -> String {
+    format!("tpcc_tables{}", number)
+}
+
+// The names the views of a set have in its schema: those of the set-1 tables
+fn view_names() -> Vec<String> {
+    TPCC_TABLES
+        .iter()
+        .map(|table| format!("{}1", table))
+        .collect()
+}
+
+// A pool for table set `number` with its share of `pool`'s connections when there
+// are `count` sets, using the views of any set but the first
+async fn table_set_pool(
+    pool: &Pool<Postgres>,
+    number: u32,
+    count: u32,
+) -> Result<Pool<Postgres>, sqlx::Error> {
+    let options = pool.options();
+    let pool_options = options
+        .clone()
+        .max_connections((options.get_max_connections() / count).max(1))
+        .min_connections(options.get_min_connections() / count);
+    if number == 1 {
+        return Ok(pool_options.connect_lazy_with((*pool.connect_options()).clone()));
+    }
+
+    let base_schema = sqlx::query_scalar!(r#"SELECT current_schema() AS "schema!""#)
+        .fetch_one(pool)
+        .await?;
+    let connect_options = (*pool.connect_options()).clone().options([(
+        "search_path",
+        format!(r#"{},"{}""#, view_schema(number), base_schema),
+    )]);
+    // Same timeouts as the main pool
+    Ok(pool_options.connect_lazy_with(connect_options))
+}
+
+// The table set of a request, chosen by the X-Table-Set header or the
+// table_set query parameter; table set 1 when neither is given
+pub struct TableSet {
+    pub number: u32,
+    pub pool: Pool<Postgres>,
+    // Where the five TPC-C transactions run
+    pub transactions: TransactionPool,
+}
+
+#[derive(Deserialize)]
+struct TableSetParam {
+    table_set: Option<u32>,
+}
+
+impl<S> FromRequestParts<S> for TableSet
+where
+    TableSets: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let number = match parts.headers.get(TABLE_SET_HEADER) {
+            Some(value) => value
+                .to_str()
+                .ok()
+                .and_then(|value| value.trim().parse().ok())
+                .ok_or_else(|| {
+                    ApiError::bad_request(format!(
+                        "{} must be a table set number",
+                        TABLE_SET_HEADER
+                    ))
+                })?,
+            None => Query::<TableSetParam>::try_from_uri(&parts.uri)
+                .map_err(|_| {
+                    ApiError::bad_request(format!("{} must be a table set number", TABLE_SET_PARAM))
+                })?
+                .0
+                .table_set
+                .unwrap_or(1),
+        };
+
+        let table_sets = TableSets::from_ref(state);
+        let (Some(pool), Some(transactions)) = (
+            table_sets.pool(number).cloned(),
+            table_sets.transaction_pool(number),
+        ) else {
+            return Err(ApiError::NotFound(Entity::TableSet { table_set: number }));
+        };
+        Ok(TableSet {
+            number,
+            pool,
+            transactions,
+        })
+    }
+}
@@ -0,0 +1,128 @@
+use tracing_subscriber::{
+    layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer, Registry,
+};
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+// Global tracing setup shared by the server and the tpcc-* binaries:
+// - `log_level` (the configured filter) or else RUST_LOG filters events and
+//   spans, "info" when neither is set
+// - LOG_FORMAT=json writes one JSON object per event, with the fields of the
+//   enclosing spans (warehouse, district, order IDs, attempt, ...)
+// - OTEL_EXPORTER_OTLP_ENDPOINT (or OTEL_EXPORTER_OTLP_TRACES_ENDPOINT) exports
+//   spans to an OTLP/HTTP collector, e.g. http://localhost:4318, when built with
+//   the `otlp` feature
+//
+// Keep the returned guard alive until exit; dropping it flushes exported spans.
+pub fn init(service_name: &'static str, log_level: Option<&str>) -> Telemetry {
+    let filter = match log_level {
+        Some(log_level) => EnvFilter::new(log_level),
+        None => EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+    };
+    let json = std::env::var("LOG_FORMAT").is_ok_and(|format| format.eq_ignore_ascii_case("json"));
+
+    let mut layers: Vec<BoxedLayer> = vec![if json {
+        tracing_subscriber::fmt::layer().json().boxed()
+    } else {
+        tracing_subscriber::fmt::layer().boxed()
+    }];
+    let telemetry = Telemetry::new(service_name, &mut layers);
+
+    tracing_subscriber::registry()
+        .with(layers)
+        .with(filter)
+        .init();
+    telemetry.report();
+    telemetry
+}
+
+fn otlp_endpoint() -> Option<String> {
+    std::env::var("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT")
+        .or_else(|_| std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT"))
+        .ok()
+        .filter(|endpoint| !endpoint.is_empty())
+}
+
+pub struct Telemetry {
+    #[cfg(feature = "otlp")]
+    provider: Option<opentelemetry_sdk::trace::SdkTracerProvider>,
+    // Reported once the subscriber is installed
+    otlp: Result<Option<String>, String>,
+}
+
+impl Telemetry {
+    #[cfg(feature = "otlp")]
+    fn new(service_name: &'static str, layers: &mut Vec<BoxedLayer>) -> Self {
+        use opentelemetry::trace::TracerProvider;
+        use opentelemetry_otlp::WithExportConfig;
+
+        let Some(endpoint) = otlp_endpoint() else {
+            return Telemetry {
+                provider: None,
+                otlp: Ok(None),
+            };
+        };
+        // The exporter reads the endpoint (and headers, timeout, ...) from the
+        // standard OTEL_EXPORTER_OTLP_* variables itself
+        let exporter = match opentelemetry_otlp::SpanExporter::builder()
+            .with_http()
+            .with_protocol(opentelemetry_otlp::Protocol::HttpBinary)
+            .build()
+        {
+            Ok(exporter) => exporter,
+            Err(e) => {
+                return Telemetry {
+                    provider: None,
+                    otlp: Err(format!("creating the OTLP exporter failed: {}", e)),
+                }
+            }
+        };
+        let provider = opentelemetry_sdk::trace::SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(
+                opentelemetry_sdk::Resource::builder()
+                    .with_service_name(service_name)
+                    .build(),
+            )
+            .build();
+        layers.push(
+            tracing_opentelemetry::layer()
+                .with_tracer(provider.tracer(service_name))
+                .boxed(),
+        );
+
+        Telemetry {
+            provider: Some(provider),
+            otlp: Ok(Some(endpoint)),
+        }
+    }
+
+    #[cfg(not(feature = "otlp"))]
+    fn new(_service_name: &'static str, _layers: &mut Vec<BoxedLayer>) -> Self {
+        Telemetry {
+            otlp: match otlp_endpoint() {
+                Some(_) => Err("built without the otlp feature, not exporting spans".to_string()),
+                None => Ok(None),
+            },
+        }
+    }
+
+    fn report(&self) {
+        match &self.otlp {
+            Ok(Some(endpoint)) => tracing::info!("Exporting spans over OTLP to {}", endpoint),
+            Ok(None) => {}
+            Err(message) => tracing::warn!("OTEL_EXPORTER_OTLP_ENDPOINT is set but {}", message),
+        }
+    }
+}
+
+#[cfg(feature = "otlp")]
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        if let Some(provider) = self.provider.take() {
+            if let Err(e) = provider.shutdown() {
+                eprintln!("Flushing OTLP spans failed: {}", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,496 @@
+use axum::{
+    http::HeaderValue,
+    response::{IntoResponse, Response},
+};
+use rand::Rng;
+use sqlx::{Pool, Postgres, Transaction};
+use std::fmt;
+use std::future::Future;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use tracing::Instrument;
+
+use crate::error::ApiError;
+use crate::metrics::metrics;
+
+// Response header with the number of times the transaction was run
+pub const ATTEMPTS_HEADER: &str = "x-transaction-attempts";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IsolationLevel {
+    ReadCommitted,
+    RepeatableRead,
+    Serializable,
+}
+
+impl IsolationLevel {
+    fn as_sql(self) -> &'static str {
+        match self {
+            IsolationLevel::ReadCommitted => "READ COMMITTED",
+            IsolationLevel::RepeatableRead => "REPEATABLE READ",
+            IsolationLevel::Serializable => "SERIALIZABLE",
+        }
+    }
+}
+
+impl fmt::Display for IsolationLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_sql())
+    }
+}
+
+// Accepts the sysbench-tpcc names (RC, RR, SER) as well as the SQL names
+impl FromStr for IsolationLevel {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let normalized = value.trim().to_ascii_uppercase().replace(['_', '-'], " ");
+        match normalized.as_str() {
+            "RC" | "READ COMMITTED" => Ok(IsolationLevel::ReadCommitted),
+            "RR" | "REPEATABLE READ" => Ok(IsolationLevel::RepeatableRead),
+            "SER" | "SERIALIZABLE" => Ok(IsolationLevel::Serializable),
+            _ => Err(format!(
+                "unknown isolation level '{}' (expected RC, RR or SER)",
+                value
+            )),
+        }
+    }
+}
+
+// The five TPC-C transaction types
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransactionKind {
+    NewOrder,
+    Payment,
+    Delivery,
+    OrderStatus,
+    StockLevel,
+}
+
+impl TransactionKind {
+    pub const ALL: [TransactionKind; 5] = [
+        TransactionKind::NewOrder,
+        TransactionKind::Payment,
+        TransactionKind::Delivery,
+        TransactionKind::OrderStatus,
+        TransactionKind::StockLevel,
+    ];
+
+    // Name as written in the specification
+    pub fn name(self) -> &'static str {
+        match self {
+            TransactionKind::NewOrder => "New-Order",
+            TransactionKind::Payment => "Payment",
+            TransactionKind::Delivery => "Delivery",
+            TransactionKind::OrderStatus => "Order-Status",
+            TransactionKind::StockLevel => "Stock-Level",
+        }
+    }
+
+    fn env_suffix(self) -> &'static str {
+        match self {
+            TransactionKind::NewOrder => "NEW_ORDER",
+            TransactionKind::Payment => "PAYMENT",
+            TransactionKind::Delivery => "DELIVERY",
+            TransactionKind::OrderStatus => "ORDER_STATUS",
+            TransactionKind::StockLevel => "STOCK_LEVEL",
+        }
+    }
+}
+
+// Isolation level of each transaction type; None keeps the server default
+// (default_transaction_isolation, normally READ COMMITTED)
+#[derive(Clone, Copy, Debug, Default)]
+pub struct IsolationLevels {
+    pub new_order: Option<IsolationLevel>,
+    pub payment: Option<IsolationLevel>,
+    pub delivery: Option<IsolationLevel>,
+    pub order_status: Option<IsolationLevel>,
+    pub stock_level: Option<IsolationLevel>,
+}
+
+impl IsolationLevels {
+    // TX_ISOLATION sets every transaction type; TX_ISOLATION_NEW_ORDER,
+    // TX_ISOLATION_PAYMENT, TX_ISOLATION_DELIVERY, TX_ISOLATION_ORDER_STATUS
+    // and TX_ISOLATION_STOCK_LEVEL override it for one type
+    pub fn from_env() -> Self {
+        let parse = |name: String| {
+            let value = std::env::var(&name).ok()?;
+            match value.parse() {
+                Ok(level) => Some(level),
+                Err(message) => {
+                    tracing::warn!("Ignoring {}: {}", name, message);
+                    None
+                }
+            }
+        };
+
+        let default = parse("TX_ISOLATION".to_string());
+        let mut levels = IsolationLevels::default();
+        for kind in TransactionKind::ALL {
+            *levels.level_mut(kind) =
+                parse(format!("TX_ISOLATION_{}", kind.env_suffix())).or(default);
+        }
+        levels
+    }
+
+    pub fn level(&self, kind: TransactionKind) -> Option<IsolationLevel> {
+        match kind {
+            TransactionKind::NewOrder => self.new_order,
+            TransactionKind::Payment => self.payment,
+            TransactionKind::Delivery => self.delivery,
+            TransactionKind::OrderStatus => self.order_status,
+            TransactionKind::StockLevel => self.stock_level,
+        }
+    }
+
+    fn level_mut(&mut self, kind: TransactionKind) -> &mut Option<IsolationLevel> {
+        match kind {
+            TransactionKind::NewOrder => &mut self.new_order,
+            TransactionKind::Payment => &mut self.payment,
+            TransactionKind::Delivery => &mut self.delivery,
+            TransactionKind::OrderStatus => &mut self.order_status,
+            TransactionKind::StockLevel => &mut self.stock_level,
+        }
+    }
+}
+
+// Begin a transaction, setting its isolation level before any other statement
+pub async fn begin(
+    pool: &Pool<Postgres>,
+    isolation: Option<IsolationLevel>,
+) -> Result<Transaction<'static, Postgres>, ApiError> {
+    let start = Instant::now();
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| ApiError::database("starting transaction", e))?;
+    metrics().observe_begin(start.elapsed());
+
+    if let Some(level) = isolation {
+        sqlx::query(&format!(
+            "SET TRANSACTION ISOLATION LEVEL {}",
+            level.as_sql()
+        ))
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| ApiError::database("setting isolation level", e))?;
+    }
+
+    Ok(tx)
+}
+
+// How often and how fast a transaction is re-run after a serialization
+// failure or deadlock. Delays grow exponentially from `base_delay` up to
+// `max_delay`, with full jitter so conflicting transactions spread out.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(5),
+            max_delay: Duration::from_millis(200),
+        }
+    }
+}
+
+impl RetryPolicy {
+    // Defaults overridden by TX_MAX_ATTEMPTS, TX_RETRY_BASE_MS and TX_RETRY_MAX_MS
+    pub fn from_env() -> Self {
+        let default = RetryPolicy::default();
+        let env_u64 = |name: &str| std::env::var(name).ok().and_then(|v| v.parse::<u64>().ok());
+
+        RetryPolicy {
+            max_attempts: env_u64("TX_MAX_ATTEMPTS")
+                .map(|v| v.clamp(1, 100) as u32)
+                .unwrap_or(default.max_attempts),
+            base_delay: env_u64("TX_RETRY_BASE_MS")
+                .map(Duration::from_millis)
+                .unwrap_or(default.base_delay),
+            max_delay: env_u64("TX_RETRY_MAX_MS")
+                .map(Duration::from_millis)
+                .unwrap_or(default.max_delay),
+        }
+    }
+
+    // Random delay before attempt `attempt + 1`
+    fn backoff(&self, attempt: u32) -> Duration {
+        let ceiling = self
+            .base_delay
+            .saturating_mul(1 << (attempt - 1).min(16))
+            .min(self.max_delay);
+        rand::thread_rng().gen_range(Duration::ZERO..=ceiling)
+    }
+}
+
+// How New-Order sends its order lines to the database
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NewOrderPath {
+    // Item lookup, stock update and order line insert for each line in turn
+    #[default]
+    PerLine,
+    // One statement each for all items, all stock rows and all order lines
+    Batched,
+}
+
+impl fmt::Display for NewOrderPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            NewOrderPath::PerLine => "per-line",
+            NewOrderPath::Batched => "batched",
+        })
+    }
+}
+
+impl FromStr for NewOrderPath {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().replace('_', "-").as_str() {
+            "per-line" => Ok(NewOrderPath::PerLine),
+            "batched" => Ok(NewOrderPath::Batched),
+            _ => Err(format!(
+                "unknown New-Order path '{}' (expected per-line or batched)",
+                value
+            )),
+        }
+    }
+}
+
+impl NewOrderPath {
+    // TX_NEW_ORDER_PATH, per-line when unset
+    pub fn from_env() -> Self {
+        let Ok(value) = std::env::var("TX_NEW_ORDER_PATH") else {
+            return NewOrderPath::default();
+        };
+        value.parse().unwrap_or_else(|message| {
+            tracing::warn!("Ignoring TX_NEW_ORDER_PATH: {}", message);
+            NewOrderPath::default()
+        })
+    }
+}
+
+// Where the transactions' SQL runs
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExecutionMode {
+    // The handlers send each statement themselves
+    #[default]
+    Inline,
+    // The handlers call the tpcc_* PL/pgSQL functions of 003_create_procedures.sql,
+    // one round trip per transaction
+    StoredProcedures,
+}
+
+impl fmt::Display for ExecutionMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ExecutionMode::Inline => "inline",
+            ExecutionMode::StoredProcedures => "stored-procedures",
+        })
+    }
+}
+
+impl FromStr for ExecutionMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().replace('_', "-").as_str() {
+            "inline" => Ok(ExecutionMode::Inline),
+            "stored-procedures" | "stored-procedure" => Ok(ExecutionMode::StoredProcedures),
+            _ => Err(format!(
+                "unknown execution mode '{}' (expected inline or stored-procedures)",
+                value
+            )),
+        }
+    }
+}
+
+impl ExecutionMode {
+    // TX_EXECUTION_MODE, inline when unset
+    pub fn from_env() -> Self {
+        let Ok(value) = std::env::var("TX_EXECUTION_MODE") else {
+            return ExecutionMode::default();
+        };
+        value.parse().unwrap_or_else(|message| {
+            tracing::warn!("Ignoring TX_EXECUTION_MODE: {}", message);
+            ExecutionMode::default()
+        })
+    }
+}
+
+// Client library the five TPC-C transactions run on
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Driver {
+    // sqlx, one round trip per statement
+    #[default]
+    Sqlx,
+    // tokio-postgres, sending the independent statements of each step as one pipeline
+    // of prepared statements (the pipelined-driver feature)
+    #[cfg(feature = "pipelined-driver")]
+    Pipelined,
+}
+
+impl fmt::Display for Driver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Driver::Sqlx => "sqlx",
+            #[cfg(feature = "pipelined-driver")]
+            Driver::Pipelined => "pipelined",
+        })
+    }
+}
+
+impl FromStr for Driver {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "sqlx" => Ok(Driver::Sqlx),
+            #[cfg(feature = "pipelined-driver")]
+            "pipelined" => Ok(Driver::Pipelined),
+            #[cfg(not(feature = "pipelined-driver"))]
+            "pipelined" => Err("built without the pipelined-driver feature".to_string()),
+            _ => Err(format!(
+                "unknown driver '{}' (expected sqlx or pipelined)",
+                value
+            )),
+        }
+    }
+}
+
+impl Driver {
+    // TX_DRIVER, sqlx when unset
+    pub fn from_env() -> Self {
+        let Ok(value) = std::env::var("TX_DRIVER") else {
+            return Driver::default();
+        };
+        value.parse().unwrap_or_else(|message| {
+            tracing::warn!("Ignoring TX_DRIVER: {}", message);
+            Driver::default()
+        })
+    }
+}
+
+// Retry policy, isolation levels, driver, execution mode and New-Order path,
+// everything a TPC-C transaction needs besides its pool and input
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TransactionSettings {
+    pub retry_policy: RetryPolicy,
+    pub isolation_levels: IsolationLevels,
+    pub driver: Driver,
+    // Only used by the sqlx driver
+    pub execution_mode: ExecutionMode,
+    // Only used by the sqlx driver's inline execution mode
+    pub new_order_path: NewOrderPath,
+}
+
+impl TransactionSettings {
+    pub fn from_env() -> Self {
+        TransactionSettings {
+            retry_policy: RetryPolicy::from_env(),
+            isolation_levels: IsolationLevels::from_env(),
+            driver: Driver::from_env(),
+            execution_mode: ExecutionMode::from_env(),
+            new_order_path: NewOrderPath::from_env(),
+        }
+    }
+}
+
+// A transaction result with the number of attempts it took
+pub struct Attempted<T> {
+    pub attempts: u32,
+    pub value: T,
+}
+
+impl<T> Attempted<T> {
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Attempted<U> {
+        Attempted {
+            attempts: self.attempts,
+            value: f(self.value),
+        }
+    }
+}
+
+impl<T: IntoResponse> IntoResponse for Attempted<T> {
+    fn into_response(self) -> Response {
+        let mut response = self.value.into_response();
+        response
+            .headers_mut()
+            .insert(ATTEMPTS_HEADER, HeaderValue::from(self.attempts));
+        response
+    }
+}
+
+// Run `transaction` (which begins and commits its own database transaction)
+// until it succeeds, fails with a non-retryable error, or the policy's
+// attempts are used up. Errors after more than one attempt are wrapped in
+// ApiError::Retried so the response reports the attempt count.
+pub async fn run_transaction<T, F, Fut>(
+    policy: &RetryPolicy,
+    name: &'static str,
+    mut transaction: F,
+) -> Result<Attempted<T>, ApiError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, ApiError>>,
+{
+    let start = Instant::now();
+    let mut attempt = 1;
+    let result = loop {
+        // One span per attempt, enclosing the transaction's own step spans
+        let span = tracing::info_span!("transaction", transaction = name, attempt);
+        match transaction().instrument(span).await {
+            Ok(value) => {
+                if attempt > 1 {
+                    tracing::info!(
+                        transaction = name,
+                        attempts = attempt,
+                        "{} succeeded after {} attempts",
+                        name,
+                        attempt
+                    );
+                }
+                break Ok(Attempted {
+                    attempts: attempt,
+                    value,
+                });
+            }
+            Err(error) if error.is_retryable() && attempt < policy.max_attempts => {
+                let delay = policy.backoff(attempt);
+                tracing::warn!(
+                    transaction = name,
+                    attempt,
+                    code = error.code(),
+                    "{} attempt {} failed, retrying in {:?}: {}",
+                    name,
+                    attempt,
+                    delay,
+                    error
+                );
+                metrics().observe_retry(name, error.code());
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            Err(error) if attempt > 1 => {
+                break Err(ApiError::Retried {
+                    attempts: attempt,
+                    last: Box::new(error),
+                });
+            }
+            Err(error) => break Err(error),
+        }
+    };
+
+    let outcome = match &result {
+        Ok(_) => "ok",
+        Err(error) => error.code(),
+    };
+    metrics().observe_transaction(name, outcome, start.elapsed());
+    result
+}
@@ -0,0 +1,94 @@
+use sqlx::{Pool, Postgres};
+
+use crate::error::ApiError;
+use crate::handlers::delivery::{execute_delivery, DeliveryRequest, DeliveryResponse};
+use crate::handlers::new_order::{execute_new_order, NewOrderOutcome, NewOrderRequest};
+use crate::handlers::order_status::{execute_order_status, OrderStatusQuery, OrderStatusResponse};
+use crate::handlers::payment::{execute_payment, PaymentRequest, PaymentResponse};
+use crate::handlers::stock_level::{execute_stock_level, StockLevelQuery, StockLevelResponse};
+#[cfg(feature = "pipelined-driver")]
+use crate::pipelined;
+use crate::transaction::{Attempted, TransactionSettings};
+
+// The pool a table set's five TPC-C transactions run on, as chosen by TX_DRIVER at
+// startup. Everything else (browsing endpoints, deferred deliveries, health checks)
+// stays on the table set's sqlx pool.
+#[derive(Clone)]
+pub enum TransactionPool {
+    Sqlx(Pool<Postgres>),
+    #[cfg(feature = "pipelined-driver")]
+    Pipelined(deadpool_postgres::Pool),
+}
+
+impl TransactionPool {
+    pub async fn new_order(
+        &self,
+        settings: &TransactionSettings,
+        request: &NewOrderRequest,
+    ) -> Result<Attempted<NewOrderOutcome>, ApiError> {
+        match self {
+            TransactionPool::Sqlx(pool) => execute_new_order(pool, settings, request).await,
+            #[cfg(feature = "pipelined-driver")]
+            TransactionPool::Pipelined(pool) => {
+                pipelined::new_order::execute_new_order(pool, settings, request).await
+            }
+        }
+    }
+
+    pub async fn payment(
+        &self,
+        settings: &TransactionSettings,
+        request: &PaymentRequest,
+    ) -> Result<Attempted<PaymentResponse>, ApiError> {
+        match self {
+            TransactionPool::Sqlx(pool) => execute_payment(pool, settings, request).await,
+            #[cfg(feature = "pipelined-driver")]
+            TransactionPool::Pipelined(pool) => {
+                pipelined::payment::execute_payment(pool, settings, request).await
+            }
+        }
+    }
+
+    pub async fn order_status(
+        &self,
+        settings: &TransactionSettings,
+        params: &OrderStatusQuery,
+    ) -> Result<Attempted<OrderStatusResponse>, ApiError> {
+        match self {
+            TransactionPool::Sqlx(pool) => execute_order_status(pool, settings, params).await,
+            #[cfg(feature = "pipelined-driver")]
+            TransactionPool::Pipelined(pool) => {
+                pipelined::order_status::execute_order_status(pool, settings, params).await
+            }
+        }
+    }
+
+    // Delivery run inline; deferred deliveries go through the queue's sqlx pool
+    pub async fn delivery(
+        &self,
+        settings: &TransactionSettings,
+        request: &DeliveryRequest,
+    ) -> Result<Attempted<DeliveryResponse>, ApiError> {
+        match self {
+            TransactionPool::Sqlx(pool) => execute_delivery(pool, settings, request).await,
+            #[cfg(feature = "pipelined-driver")]
+            TransactionPool::Pipelined(pool) => {
+                pipelined::delivery::execute_delivery(pool, settings, request).await
+            }
+        }
+    }
+
+    pub async fn stock_level(
+        &self,
+        settings: &TransactionSettings,
+        params: &StockLevelQuery,
+    ) -> Result<Attempted<StockLevelResponse>, ApiError> {
+        match self {
+            TransactionPool::Sqlx(pool) => execute_stock_level(pool, settings, params).await,
+            #[cfg(feature = "pipelined-driver")]
+            TransactionPool::Pipelined(pool) => {
+                pipelined::stock_level::execute_stock_level(pool, settings, params).await
+            }
+        }
+    }
+}
@@ -0,0 +1,229 @@
+// Concurrent New-Order and Payment transactions: against a single district, followed
+// by the TPC-C consistency conditions (no duplicate order IDs, no lost YTD, balance
+// or stock updates), and across districts ordering the same items differently (no
+// deadlocks)
+mod common;
+
+use common::{connect_test_db, setup_warehouse, STOCK_QUANTITY};
+use rust_axum_rest_api::consistency;
+use rust_axum_rest_api::handlers::new_order::{
+    execute_new_order, NewOrderOutcome, NewOrderRequest, OrderLineRequest,
+};
+use rust_axum_rest_api::handlers::payment::{execute_payment, PaymentRequest};
+use rust_axum_rest_api::transaction::TransactionSettings;
+use std::collections::BTreeSet;
+use tokio::task::JoinSet;
+
+const CONCURRENCY_WAREHOUSE: i16 = 993;
+const LOCK_ORDER_WAREHOUSE: i16 = 992;
+const DISTRICT: i16 = 1;
+const CUSTOMERS: i32 = 3;
+const ITEMS: [i32; 10] = [2001, 2002, 2003, 2004, 2005, 2006, 2007, 2008, 2009, 2010];
+const TERMINALS: usize = 16;
+const TRANSACTIONS_PER_TERMINAL: usize = 10;
+const PAYMENT_AMOUNT: f64 = 10.0;
+const LINE_QUANTITY: i16 = 2;
+
+#[tokio::test]
+async fn test_concurrent_transactions_on_one_district_stay_consistent() {
+    let Some(pool) = connect_test_db().await else {
+        println!("⚠️  Database not available, skipping concurrency test");
+        return;
+    };
+    setup_warehouse(&pool, CONCURRENCY_WAREHOUSE, 1, &ITEMS).await;
+    let settings = TransactionSettings::default();
+
+    // Every terminal alternates New-Order and Payment on the same district
+    let mut terminals = JoinSet::new();
+    for terminal in 0..TERMINALS {
+        let pool = pool.clone();
+        terminals.spawn(async move {
+            let mut order_ids = Vec::new();
+            for n in 0..TRANSACTIONS_PER_TERMINAL {
+                let customer_id = ((terminal + n) as i32 % CUSTOMERS) + 1;
+                if n % 2 == 0 {
+                    let outcome = execute_new_order(
+                        &pool,
+                        &settings,
+                        &NewOrderRequest {
+                            warehouse_id: CONCURRENCY_WAREHOUSE,
+                            district_id: DISTRICT,
+                            customer_id,
+                            order_lines: ITEMS
+                                .iter()
+                                .map(|&item_id| OrderLineRequest {
+                                    item_id,
+                                    supply_warehouse_id: CONCURRENCY_WAREHOUSE,
+                                    quantity: LINE_QUANTITY,
+                                })
+                                .collect(),
+                        },
+                    )
+                    .await
+                    .unwrap_or_else(|e| panic!("New-Order failed: {}", e));
+                    match outcome.value {
+                        NewOrderOutcome::Committed(order) => order_ids.push(order.order_id),
+                        NewOrderOutcome::RolledBack(_) => panic!("New-Order rolled back"),
+                    }
+                } else {
+                    execute_payment(
+                        &pool,
+                        &settings,
+                        &PaymentRequest {
+                            warehouse_id: CONCURRENCY_WAREHOUSE,
+                            district_id: DISTRICT,
+                            customer_id: Some(customer_id),
+                            customer_last_name: None,
+                            customer_warehouse_id: None,
+                            customer_district_id: None,
+                            amount: PAYMENT_AMOUNT,
+                        },
+                    )
+                    .await
+                    .unwrap_or_else(|e| panic!("Payment failed: {}", e));
+                }
+            }
+            order_ids
+        });
+    }
+    let mut order_ids = Vec::new();
+    while let Some(result) = terminals.join_next().await {
+        order_ids.extend(result.expect("terminal panicked"));
+    }
+
+    let new_orders = TERMINALS * TRANSACTIONS_PER_TERMINAL / 2;
+    let payments = TERMINALS * TRANSACTIONS_PER_TERMINAL - new_orders;
+
+    // Every New-Order took its own order ID
+    let unique: BTreeSet<i32> = order_ids.iter().copied().collect();
+    assert_eq!(
+        unique.len(),
+        new_orders,
+        "duplicate order IDs: {:?}",
+        order_ids
+    );
+    assert_eq!(unique, (1..=new_orders as i32).collect());
+    let next_order_id: i32 =
+        sqlx::query_scalar("SELECT d_next_o_id FROM district1 WHERE d_w_id = $1 AND d_id = $2")
+            .bind(CONCURRENCY_WAREHOUSE)
+            .bind(DISTRICT)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    assert_eq!(next_order_id, new_orders as i32 + 1);
+
+    // No payment was lost from the warehouse, district or customers
+    let (w_ytd, d_ytd, c_ytd, c_payments): (f64, f64, f64, i64) = sqlx::query_as(
+        "SELECT (SELECT w_ytd FROM warehouse1 WHERE w_id = $1)::float8,
+                (SELECT d_ytd FROM district1 WHERE d_w_id = $1 AND d_id = 1)::float8,
+                (SELECT sum(c_ytd_payment) FROM customer1 WHERE c_w_id = $1)::float8,
+                (SELECT sum(c_payment_cnt) FROM customer1 WHERE c_w_id = $1)",
+    )
+    .bind(CONCURRENCY_WAREHOUSE)
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    let paid = payments as f64 * PAYMENT_AMOUNT;
+    assert_eq!((w_ytd, d_ytd, c_ytd), (paid, paid, paid));
+    assert_eq!(c_payments, payments as i64);
+
+    // Nor any order line from the stock
+    let stock: Vec<(i16, f64, i16)> = sqlx::query_as(
+        "SELECT s_quantity, s_ytd::float8, s_order_cnt FROM stock1 WHERE s_w_id = $1 ORDER BY s_i_id",
+    )
+    .bind(CONCURRENCY_WAREHOUSE)
+    .fetch_all(&pool)
+    .await
+    .unwrap();
+    // Quantities go down by each order line and are restocked by 91 when short
+    let quantity = (0..new_orders).fold(STOCK_QUANTITY, |quantity, _| {
+        if quantity >= LINE_QUANTITY {
+            quantity - LINE_QUANTITY
+        } else {
+            quantity - LINE_QUANTITY + 91
+        }
+    });
+    let ordered = new_orders as f64 * LINE_QUANTITY as f64;
+    let expected = (quantity, ordered, new_orders as i16);
+    assert_eq!(stock, vec![expected; ITEMS.len()]);
+
+    let report = consistency::check(&pool, Some(CONCURRENCY_WAREHOUSE))
+        .await
+        .unwrap();
+    for condition in report.conditions.iter().filter(|c| c.required) {
+        assert!(
+            condition.passed,
+            "condition {} ({}) failed: {:?}",
+            condition.condition, condition.description, condition.violations
+        );
+    }
+    assert!(report.consistent);
+}
+
+#[tokio::test]
+async fn test_new_orders_listing_items_in_different_orders_do_not_deadlock() {
+    let Some(pool) = connect_test_db().await else {
+        println!("⚠️  Database not available, skipping lock order test");
+        return;
+    };
+    let districts = 10;
+    setup_warehouse(&pool, LOCK_ORDER_WAREHOUSE, districts, &ITEMS).await;
+    let settings = TransactionSettings::default();
+
+    // One terminal per district, so only the stock rows are shared; each lists the
+    // items rotated, and odd terminals in reverse
+    let mut terminals = JoinSet::new();
+    for district_id in 1..=districts {
+        let pool = pool.clone();
+        terminals.spawn(async move {
+            let mut items = ITEMS.to_vec();
+            items.rotate_left(district_id as usize % ITEMS.len());
+            if district_id % 2 == 1 {
+                items.reverse();
+            }
+            for n in 0..TRANSACTIONS_PER_TERMINAL {
+                let outcome = execute_new_order(
+                    &pool,
+                    &settings,
+                    &NewOrderRequest {
+                        warehouse_id: LOCK_ORDER_WAREHOUSE,
+                        district_id,
+                        customer_id: (n as i32 % CUSTOMERS) + 1,
+                        order_lines: items
+                            .iter()
+                            .map(|&item_id| OrderLineRequest {
+                                item_id,
+                                supply_warehouse_id: LOCK_ORDER_WAREHOUSE,
+                                quantity: 1,
+                            })
+                            .collect(),
+                    },
+                )
+                .await
+                .unwrap_or_else(|e| panic!("New-Order failed: {}", e));
+
+                // Retries would mean a deadlock (or serialization failure) was hit
+                assert_eq!(outcome.attempts, 1, "district {}", district_id);
+                let NewOrderOutcome::Committed(order) = outcome.value else {
+                    panic!("New-Order rolled back");
+                };
+                let lines: Vec<i32> = order.order_lines.iter().map(|line| line.item_id).collect();
+                assert_eq!(lines, items, "lines must stay in request order");
+            }
+        });
+    }
+    while let Some(result) = terminals.join_next().await {
+        result.expect("terminal panicked");
+    }
+
+    let order_count: i64 =
+        sqlx::query_scalar("SELECT sum(s_order_cnt) FROM stock1 WHERE s_w_id = $1")
+            .bind(LOCK_ORDER_WAREHOUSE)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    assert_eq!(
+        order_count as usize,
+        districts as usize * TRANSACTIONS_PER_TERMINAL * ITEMS.len()
+    );
+}
@@ -0,0 +1,124 @@
+// Tests for the configuration layer: TOML file, overrides and what the pool and router make of it
+mod common;
+
+use axum::body::Body;
+use common::test_database_url;
+use hyper::Request;
+use rust_axum_rest_api::config::{Config, ConfigArgs, DatabaseArgs, ServerConfig};
+use rust_axum_rest_api::create_app_with_config;
+use rust_axum_rest_api::table_set::TableSets;
+use std::path::PathBuf;
+use tower::ServiceExt;
+
+#[test]
+fn test_file_values_are_overridden_by_flags() {
+    let mut config = Config::from_toml(
+        r#"
+        [server]
+        port = 9000
+        frontend_dist = "/srv/tpcc/dist"
+        cors_origins = ["http://localhost:5173"]
+
+        [database]
+        max_connections = 64
+        statement_timeout_ms = 2000
+        "#,
+    )
+    .unwrap();
+
+    // Unset keys keep their defaults
+    assert_eq!(config.server.host, "127.0.0.1");
+    assert_eq!(config.server.port, 9000);
+    assert_eq!(config.server.frontend_dist, PathBuf::from("/srv/tpcc/dist"));
+    assert_eq!(config.database.min_connections, 0);
+    assert_eq!(config.database.max_connections, 64);
+    assert_eq!(config.database.acquire_timeout_secs, 30);
+    assert_eq!(config.database.statement_timeout_ms, Some(2000));
+
+    ConfigArgs {
+        port: Some(9100),
+        cors_origins: vec!["https://tpcc.example".to_string()],
+        database: DatabaseArgs {
+            max_connections: Some(128),
+            idle_timeout_secs: Some(0),
+            ..DatabaseArgs::default()
+        },
+        ..ConfigArgs::default()
+    }
+    .apply(&mut config);
+
+    assert_eq!(config.server.port, 9100);
+    assert_eq!(config.server.cors_origins, ["https://tpcc.example"]);
+    assert_eq!(config.database.max_connections, 128);
+    assert_eq!(config.database.idle_timeout_secs, 0);
+    assert_eq!(config.database.statement_timeout_ms, Some(2000));
+    assert_eq!(config.database.pool_options().get_idle_timeout(), None);
+
+    // Typos are rejected rather than silently ignored
+    let error = Config::from_toml("[database]\nmax_conections = 5\n").unwrap_err();
+    assert!(error.to_string().contains("max_conections"));
+}
+
+#[tokio::test]
+async fn test_pools_use_configured_limits_and_statement_timeout() {
+    let mut config = Config::default();
+    config.database.url = Some(test_database_url());
+    config.database.max_connections = 3;
+    config.database.statement_timeout_ms = Some(1500);
+    let Ok(pool) = config.database.connect().await else {
+        println!("⚠️  Database not available, skipping pool configuration test");
+        return;
+    };
+
+    assert_eq!(pool.options().get_max_connections(), 3);
+    let statement_timeout: String = sqlx::query_scalar("SHOW statement_timeout")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(statement_timeout, "1500ms");
+
+    // Table set pools are configured like the main pool, sharing its connections
+    let table_sets = TableSets::discover(&pool).await;
+    let numbers = table_sets.numbers();
+    for &number in &numbers {
+        let set_pool = table_sets.pool(number).unwrap();
+        assert_eq!(
+            set_pool.options().get_max_connections(),
+            (3 / numbers.len() as u32).max(1)
+        );
+        let statement_timeout: String = sqlx::query_scalar("SHOW statement_timeout")
+            .fetch_one(set_pool)
+            .await
+            .unwrap();
+        assert_eq!(statement_timeout, "1500ms");
+    }
+}
+
+#[tokio::test]
+async fn test_cors_allows_only_configured_origins() {
+    let Ok(pool) = sqlx::PgPool::connect(&test_database_url()).await else {
+        println!("⚠️  Database not available, skipping CORS configuration test");
+        return;
+    };
+    let config = ServerConfig {
+        serve_frontend: Some(false),
+        cors_origins: vec!["http://localhost:5173".to_string()],
+        ..ServerConfig::default()
+    };
+    let app = create_app_with_config(pool, &config).await;
+
+    for (origin, allowed) in [
+        ("http://localhost:5173", true),
+        ("http://evil.example", false),
+    ] {
+        let request = Request::builder()
+            .uri("/")
+            .header("origin", origin)
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        let allow_origin = response.headers().get("access-control-allow-origin");
+        assert_eq!(allow_origin.is_some(), allowed, "origin {}", origin);
+    }
+}
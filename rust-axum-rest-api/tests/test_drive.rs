@@ -0,0 +1,90 @@
+// Tests for the TPC-C terminal emulator's transaction mix and inputs
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use rust_axum_rest_api::drive::{Deck, Terminal, TransactionInput, TransactionStats};
+use rust_axum_rest_api::random::NuRandConstants;
+use rust_axum_rest_api::transaction::TransactionKind;
+use std::time::Duration;
+
+#[test]
+fn test_deck_deals_the_specified_mix() {
+    let mut rng = StdRng::seed_from_u64(7);
+    let mut deck = Deck::new();
+    for _ in 0..3 {
+        let hand: Vec<TransactionKind> = (0..23).map(|_| deck.draw(&mut rng)).collect();
+        let count = |kind| hand.iter().filter(|&&card| card == kind).count();
+        assert_eq!(count(TransactionKind::NewOrder), 10);
+        assert_eq!(count(TransactionKind::Payment), 10);
+        assert_eq!(count(TransactionKind::OrderStatus), 1);
+        assert_eq!(count(TransactionKind::Delivery), 1);
+        assert_eq!(count(TransactionKind::StockLevel), 1);
+    }
+}
+
+#[test]
+fn test_terminal_inputs_follow_the_specification() {
+    let mut rng = StdRng::seed_from_u64(11);
+    let terminal = Terminal {
+        table_set: 1,
+        warehouse_id: 3,
+        district_id: 7,
+        warehouses: 5,
+        nurand: NuRandConstants::random(&mut rng),
+    };
+
+    let (mut rollbacks, mut remote_payments) = (0, 0);
+    for _ in 0..10_000 {
+        match terminal.input(TransactionKind::NewOrder, &mut rng) {
+            TransactionInput::NewOrder(request) => {
+                assert_eq!(request.warehouse_id, 3);
+                assert!((5..=15).contains(&request.order_lines.len()));
+                assert!(request.order_lines.iter().all(|line| {
+                    (1..=10).contains(&line.quantity) && (1..=5).contains(&line.supply_warehouse_id)
+                }));
+                if request
+                    .order_lines
+                    .iter()
+                    .any(|line| line.item_id > 100_000)
+                {
+                    rollbacks += 1;
+                }
+            }
+            _ => unreachable!(),
+        }
+        match terminal.input(TransactionKind::Payment, &mut rng) {
+            TransactionInput::Payment(request) => {
+                assert!(request.customer_id.is_some() != request.customer_last_name.is_some());
+                assert!((1.0..=5000.0).contains(&request.amount));
+                if request.customer_warehouse_id != Some(3) {
+                    remote_payments += 1;
+                }
+            }
+            _ => unreachable!(),
+        }
+        match terminal.input(TransactionKind::StockLevel, &mut rng) {
+            TransactionInput::StockLevel(query) => {
+                assert_eq!((query.warehouse_id, query.district_id), (3, 7));
+                assert!((10..=20).contains(&query.threshold));
+            }
+            _ => unreachable!(),
+        }
+    }
+    // About 1% of New-Orders roll back and 15% of Payments are remote
+    assert!((50..=150).contains(&rollbacks), "{} rollbacks", rollbacks);
+    assert!(
+        (1_300..=1_700).contains(&remote_payments),
+        "{} remote payments",
+        remote_payments
+    );
+}
+
+#[test]
+fn test_percentile_uses_nearest_rank() {
+    let stats = TransactionStats {
+        response_times: (1..=10).rev().map(Duration::from_millis).collect(),
+        ..Default::default()
+    };
+    assert_eq!(stats.percentile(90.0), Some(Duration::from_millis(9)));
+    assert_eq!(stats.percentile(100.0), Some(Duration::from_millis(10)));
+    assert_eq!(TransactionStats::default().percentile(90.0), None);
+}
@@ -0,0 +1,46 @@
+// Each execution path of the five transactions against inline sqlx: run on both in
+// mirrored warehouses, they give the same responses, errors and rows
+mod common;
+
+use common::{assert_matches_inline, connect_test_db, ExecutionPath};
+use rust_axum_rest_api::transaction::{ExecutionMode, TransactionSettings};
+use rust_axum_rest_api::transaction_pool::TransactionPool;
+
+#[tokio::test]
+async fn test_stored_procedures_match_inline_transactions() {
+    let Some(pool) = connect_test_db().await else {
+        println!("⚠️  Database not available, skipping stored procedure test");
+        return;
+    };
+    let path = ExecutionPath {
+        transactions: TransactionPool::Sqlx(pool.clone()),
+        settings: TransactionSettings {
+            execution_mode: ExecutionMode::StoredProcedures,
+            ..TransactionSettings::default()
+        },
+    };
+    assert_matches_inline(&pool, path, 989, 988).await;
+}
+
+#[cfg(feature = "pipelined-driver")]
+#[tokio::test]
+async fn test_pipelined_driver_matches_sqlx() {
+    use rust_axum_rest_api::config::DatabaseConfig;
+    use rust_axum_rest_api::pipelined;
+
+    let Some(pool) = connect_test_db().await else {
+        println!("⚠️  Database not available, skipping pipelined driver test");
+        return;
+    };
+    let database = DatabaseConfig {
+        url: Some(common::test_database_url()),
+        ..DatabaseConfig::default()
+    };
+    let pipelined = pipelined::pool(&database, None, database.max_connections as usize)
+        .expect("Failed to build the pipelined pool");
+    let path = ExecutionPath {
+        transactions: TransactionPool::Pipelined(pipelined),
+        settings: TransactionSettings::default(),
+    };
+    assert_matches_inline(&pool, path, 986, 985).await;
+}
@@ -0,0 +1,104 @@
+// Integration tests for the liveness and readiness probes
+mod common;
+
+use axum::body::Body;
+use common::connect_test_db;
+use http_body_util::BodyExt;
+use hyper::Request;
+use rust_axum_rest_api::create_app;
+use rust_axum_rest_api::table_set::{self, TPCC_TABLES};
+use sqlx::PgPool;
+use tower::ServiceExt;
+
+// Created without indexes or rows, then dropped again
+const UNLOADED_TABLE_SET: u32 = 93;
+
+async fn get(app: &axum::Router, uri: &str) -> (u16, Vec<u8>) {
+    let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status().as_u16();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    (status, body.to_vec())
+}
+
+async fn drop_unloaded_table_set(pool: &PgPool) {
+    for table in TPCC_TABLES {
+        sqlx::query(&format!(
+            "DROP TABLE IF EXISTS {table}{UNLOADED_TABLE_SET} CASCADE"
+        ))
+        .execute(pool)
+        .await
+        .expect("Failed to drop table set");
+    }
+    sqlx::query(&format!(
+        "DROP SCHEMA IF EXISTS tpcc_tables{UNLOADED_TABLE_SET} CASCADE"
+    ))
+    .execute(pool)
+    .await
+    .expect("Failed to drop table set views");
+}
+
+#[tokio::test]
+async fn test_readyz_reports_schema_and_data() {
+    let Some(pool) = connect_test_db().await else {
+        println!("⚠️  Database not available, skipping readiness test");
+        return;
+    };
+
+    // A table set with every table but none of the indexes or data
+    drop_unloaded_table_set(&pool).await;
+    for table in TPCC_TABLES {
+        sqlx::query(&format!(
+            "CREATE TABLE {table}{UNLOADED_TABLE_SET} (LIKE {table}1 INCLUDING DEFAULTS)"
+        ))
+        .execute(&pool)
+        .await
+        .expect("Failed to create table set");
+    }
+    table_set::create_views(&pool)
+        .await
+        .expect("Failed to create table set views");
+    let app = create_app(pool.clone()).await;
+
+    let (status, body) = get(&app, "/healthz").await;
+    assert_eq!(status, 200);
+    assert_eq!(body, b"ok");
+
+    let (status, body) = get(&app, "/readyz").await;
+    let report: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    drop_unloaded_table_set(&pool).await;
+
+    assert_eq!(status, 503, "{}", report);
+    assert_eq!(report["ready"], false);
+    assert_eq!(report["database"]["connected"], true);
+    let table_set = |number: u32| {
+        report["table_sets"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|status| status["table_set"] == number)
+            .unwrap_or_else(|| panic!("table set {} missing from {}", number, report))
+    };
+
+    // Set 1 is created by the migrations, indexes included
+    let set1 = table_set(1);
+    assert_eq!(set1["missing_tables"], serde_json::json!([]));
+    assert_eq!(set1["missing_indexes"], serde_json::json!([]));
+    let warehouses: i64 = sqlx::query_scalar("SELECT count(*) FROM warehouse1")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(set1["warehouses"], warehouses);
+
+    let unloaded = table_set(UNLOADED_TABLE_SET);
+    assert_eq!(unloaded["missing_tables"], serde_json::json!([]));
+    let missing_indexes = unloaded["missing_indexes"].as_array().unwrap();
+    assert_eq!(missing_indexes.len(), 6, "{}", unloaded);
+    assert!(missing_indexes.contains(&serde_json::json!(format!(
+        "idx_customer{0} ON customer{0} (c_w_id,c_d_id,c_last,c_first)",
+        UNLOADED_TABLE_SET
+    ))));
+    assert_eq!(unloaded["warehouses"], 0);
+    assert_eq!(unloaded["items"], 0);
+    assert_eq!(unloaded["loaded"], false);
+}
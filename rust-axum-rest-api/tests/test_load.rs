@@ -0,0 +1,176 @@
+// Integration test for the TPC-C loader and the consistency checks, run against its own table set
+mod common;
+
+use axum::body::Body;
+use common::connect_test_db;
+use http_body_util::BodyExt;
+use hyper::Request;
+use rust_axum_rest_api::create_app;
+use rust_axum_rest_api::load::{self, LoadOptions};
+use sqlx::PgPool;
+use tower::ServiceExt;
+
+const TABLE_SET: u32 = 3;
+
+async fn count(pool: &PgPool, sql: &str) -> i64 {
+    sqlx::query_scalar(sql)
+        .fetch_one(pool)
+        .await
+        .expect("Failed to count rows")
+}
+
+#[tokio::test]
+async fn test_load_populates_one_warehouse() {
+    let Some(pool) = connect_test_db().await else {
+        println!("⚠️  Database not available, skipping load test");
+        return;
+    };
+
+    load::create_schema(&pool, &[TABLE_SET]).await.unwrap();
+    load::truncate(&pool, TABLE_SET).await.unwrap();
+    let summary = load::load(
+        &pool,
+        &LoadOptions {
+            warehouses: 1,
+            table_sets: vec![TABLE_SET],
+            jobs: 2,
+            bad_credit_percent: 10,
+            original_percent: 10,
+        },
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(count(&pool, "SELECT COUNT(*) FROM warehouse3").await, 1);
+    assert_eq!(count(&pool, "SELECT COUNT(*) FROM district3").await, 10);
+    assert_eq!(
+        count(
+            &pool,
+            "SELECT COUNT(*) FROM district3 WHERE d_next_o_id = 3001"
+        )
+        .await,
+        10
+    );
+    assert_eq!(count(&pool, "SELECT COUNT(*) FROM item3").await, 100_000);
+    assert_eq!(count(&pool, "SELECT COUNT(*) FROM stock3").await, 100_000);
+    assert_eq!(count(&pool, "SELECT COUNT(*) FROM customer3").await, 30_000);
+    assert_eq!(count(&pool, "SELECT COUNT(*) FROM history3").await, 30_000);
+    assert_eq!(count(&pool, "SELECT COUNT(*) FROM orders3").await, 30_000);
+    assert_eq!(
+        count(&pool, "SELECT COUNT(*) FROM new_orders3").await,
+        9_000
+    );
+    assert_eq!(
+        count(&pool, "SELECT COUNT(*) FROM order_line3").await,
+        count(&pool, "SELECT SUM(o_ol_cnt)::bigint FROM orders3").await
+    );
+
+    // Every customer placed exactly one order
+    assert_eq!(
+        count(
+            &pool,
+            "SELECT COUNT(DISTINCT (o_d_id, o_c_id)) FROM orders3"
+        )
+        .await,
+        30_000
+    );
+    // Undelivered orders have no carrier and their lines no delivery date
+    assert_eq!(
+        count(
+            &pool,
+            "SELECT COUNT(*) FROM orders3 WHERE (o_carrier_id IS NULL) <> (o_id >= 2101)"
+        )
+        .await,
+        0
+    );
+    assert_eq!(
+        count(
+            &pool,
+            "SELECT COUNT(*) FROM order_line3 WHERE (ol_delivery_d IS NULL) <> (ol_o_id >= 2101)"
+        )
+        .await,
+        0
+    );
+
+    // Randomized shares stay close to the requested percentages
+    let original = count(
+        &pool,
+        "SELECT COUNT(*) FROM item3 WHERE i_data LIKE '%ORIGINAL%'",
+    )
+    .await;
+    assert!(
+        (8_000..=12_000).contains(&original),
+        "{} ORIGINAL items",
+        original
+    );
+    let bad_credit = count(
+        &pool,
+        "SELECT COUNT(*) FROM customer3 WHERE c_credit = 'BC'",
+    )
+    .await;
+    assert!(
+        (2_400..=3_600).contains(&bad_credit),
+        "{} BC customers",
+        bad_credit
+    );
+
+    // Every last name of the first 1000 customers is a syllable triple
+    assert_eq!(
+        count(
+            &pool,
+            "SELECT COUNT(DISTINCT c_last) FROM customer3 WHERE c_id <= 1000"
+        )
+        .await,
+        1000
+    );
+    assert!(summary.rows > 0);
+
+    // A fresh population satisfies all twelve consistency conditions
+    let app = create_app(pool.clone()).await;
+    let (status, json) = get_json(&app, "/admin/consistency?warehouse_id=1").await;
+    assert_eq!(status, 200);
+    let conditions = json["conditions"].as_array().unwrap();
+    assert_eq!(conditions.len(), 12);
+    for condition in conditions {
+        assert_eq!(condition["passed"], true, "{}", condition);
+    }
+    assert_eq!(json["consistent"], true);
+
+    // A district whose D_YTD drifted breaks conditions 1 and 9, which name the district
+    sqlx::query("UPDATE district3 SET d_ytd = d_ytd + 1 WHERE d_w_id = 1 AND d_id = 4")
+        .execute(&pool)
+        .await
+        .unwrap();
+    let (status, json) = get_json(&app, "/admin/consistency").await;
+    sqlx::query("UPDATE district3 SET d_ytd = d_ytd - 1 WHERE d_w_id = 1 AND d_id = 4")
+        .execute(&pool)
+        .await
+        .unwrap();
+    assert_eq!(status, 200);
+    assert_eq!(json["consistent"], false);
+    let failed: Vec<u64> = json["conditions"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|condition| condition["passed"] == false)
+        .map(|condition| condition["condition"].as_u64().unwrap())
+        .collect();
+    assert_eq!(failed, vec![1, 9]);
+    assert_eq!(json["conditions"][8]["violations"][0]["d_id"], 4);
+
+    let (status, json) = get_json(&app, "/admin/consistency?warehouse_id=2").await;
+    assert_eq!(status, 404);
+    assert_eq!(json["code"], "warehouse_not_found");
+}
+
+async fn get_json(app: &axum::Router, uri: &str) -> (u16, serde_json::Value) {
+    let request = Request::builder()
+        .uri(uri)
+        .header("x-table-set", TABLE_SET.to_string())
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status().as_u16();
+    let bytes = response.into_body().collect().await.unwrap().to_bytes();
+    (status, serde_json::from_slice(&bytes).unwrap())
+}
@@ -0,0 +1,89 @@
+// Tests for the embedded migrations, run in a schema of their own
+mod common;
+
+use common::test_database_url;
+use rust_axum_rest_api::migrate::{self, Drift, MigrationError};
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use sqlx::PgPool;
+
+const SCHEMA: &str = "tpcc_migrate_test";
+
+// A pool whose tables and _sqlx_migrations live in an empty schema
+async fn connect_empty_schema() -> Option<(PgPool, PgPool)> {
+    let admin = PgPool::connect(&test_database_url()).await.ok()?;
+    sqlx::query(&format!("DROP SCHEMA IF EXISTS {SCHEMA} CASCADE"))
+        .execute(&admin)
+        .await
+        .unwrap();
+    sqlx::query(&format!("CREATE SCHEMA {SCHEMA}"))
+        .execute(&admin)
+        .await
+        .unwrap();
+    let options: PgConnectOptions = test_database_url().parse().unwrap();
+    let pool = PgPoolOptions::new()
+        .max_connections(2)
+        .connect_with(options.options([("search_path", SCHEMA)]))
+        .await
+        .unwrap();
+    Some((admin, pool))
+}
+
+#[tokio::test]
+async fn test_migrations_apply_once_and_detect_drift() {
+    let Some((admin, pool)) = connect_empty_schema().await else {
+        println!("⚠️  Database not available, skipping migration test");
+        return;
+    };
+    let versions: Vec<i64> = migrate::MIGRATOR.iter().map(|m| m.version).collect();
+
+    let status = migrate::status(&pool).await.unwrap();
+    assert!(status.applied.is_empty());
+    assert_eq!(status.pending, versions);
+    assert!(status.drift.is_empty());
+
+    let (before, after) = migrate::run(&pool).await.unwrap();
+    assert_eq!(before.pending, versions);
+    assert_eq!(after.applied, versions);
+    assert!(after.pending.is_empty());
+    let tables: i64 = sqlx::query_scalar(
+        "SELECT count(*) FROM pg_tables WHERE schemaname = $1 AND tablename LIKE '%1'",
+    )
+    .bind(SCHEMA)
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert_eq!(tables, 9);
+
+    // Nothing left to apply the second time
+    let (before, _) = migrate::run(&pool).await.unwrap();
+    assert!(before.pending.is_empty());
+
+    // An edited migration file and one from another build are drift
+    sqlx::query("UPDATE _sqlx_migrations SET checksum = '\\x00' WHERE version = $1")
+        .bind(versions[1])
+        .execute(&pool)
+        .await
+        .unwrap();
+    sqlx::query(
+        "INSERT INTO _sqlx_migrations (version, description, success, checksum, execution_time)
+         VALUES (999, 'from a newer build', true, '\\x00', 0)",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+    let result = migrate::run(&pool).await;
+    pool.close().await;
+    sqlx::query(&format!("DROP SCHEMA {SCHEMA} CASCADE"))
+        .execute(&admin)
+        .await
+        .unwrap();
+
+    match result {
+        Err(MigrationError::Drift(drift)) => {
+            assert_eq!(drift.len(), 2, "{:?}", drift);
+            assert!(matches!(drift[0], Drift::Modified { version, .. } if version == versions[1]));
+            assert_eq!(drift[1], Drift::Unknown { version: 999 });
+        }
+        other => panic!("expected drift, got {:?}", other.map(|_| ())),
+    }
+}
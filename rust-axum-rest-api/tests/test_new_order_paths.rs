@@ -0,0 +1,160 @@
+// The batched New-Order path against the per-line one: the same orders, entered
+// through each path in mirrored warehouses, leave the same responses and rows, as
+// do the five transactions with batched New-Orders against the inline ones
+mod common;
+
+use common::{assert_matches_inline, connect_test_db, rows, setup_warehouse, ExecutionPath};
+use rust_axum_rest_api::handlers::new_order::{
+    execute_new_order, NewOrderRequest, OrderLineRequest,
+};
+use rust_axum_rest_api::transaction::{NewOrderPath, TransactionSettings};
+use rust_axum_rest_api::transaction_pool::TransactionPool;
+
+// Each path orders from its own warehouse and one remote line from the other's
+const PER_LINE_WAREHOUSE: i16 = 991;
+const BATCHED_WAREHOUSE: i16 = 990;
+const INLINE_WAREHOUSE: i16 = 983;
+const BATCHED_MIX_WAREHOUSE: i16 = 982;
+const DISTRICT: i16 = 1;
+const ITEMS: [i32; 6] = [3001, 3002, 3003, 3004, 3005, 3006];
+const UNUSED_ITEM: i32 = 2_000_000;
+
+// The orders both paths enter: lines out of item order with a remote line,
+// a stock row listed twice, an unused item after valid lines, and quantities
+// large enough to restock
+fn orders(warehouse_id: i16, remote_warehouse_id: i16) -> Vec<Vec<OrderLineRequest>> {
+    let line = |item_id, supply_warehouse_id, quantity| OrderLineRequest {
+        item_id,
+        supply_warehouse_id,
+        quantity,
+    };
+    vec![
+        vec![
+            line(ITEMS[4], warehouse_id, 5),
+            line(ITEMS[0], warehouse_id, 3),
+            line(ITEMS[5], remote_warehouse_id, 2),
+            line(ITEMS[2], warehouse_id, 10),
+        ],
+        vec![
+            line(ITEMS[1], warehouse_id, 1),
+            line(ITEMS[2], warehouse_id, 4),
+            line(ITEMS[1], warehouse_id, 2),
+        ],
+        vec![
+            line(ITEMS[3], warehouse_id, 1),
+            line(UNUSED_ITEM, warehouse_id, 1),
+            line(ITEMS[4], warehouse_id, 1),
+        ],
+        vec![
+            line(ITEMS[0], warehouse_id, 9),
+            line(ITEMS[2], warehouse_id, 6),
+        ],
+    ]
+}
+
+#[tokio::test]
+async fn test_batched_new_order_matches_per_line() {
+    let Some(pool) = connect_test_db().await else {
+        println!("⚠️  Database not available, skipping New-Order path test");
+        return;
+    };
+    setup_warehouse(&pool, PER_LINE_WAREHOUSE, 1, &ITEMS).await;
+    setup_warehouse(&pool, BATCHED_WAREHOUSE, 1, &ITEMS).await;
+
+    let mut responses = Vec::new();
+    for (path, warehouse_id, remote_warehouse_id) in [
+        (NewOrderPath::PerLine, PER_LINE_WAREHOUSE, BATCHED_WAREHOUSE),
+        (NewOrderPath::Batched, BATCHED_WAREHOUSE, PER_LINE_WAREHOUSE),
+    ] {
+        let settings = TransactionSettings {
+            new_order_path: path,
+            ..TransactionSettings::default()
+        };
+        let mut outcomes = Vec::new();
+        for order_lines in orders(warehouse_id, remote_warehouse_id) {
+            let outcome = execute_new_order(
+                &pool,
+                &settings,
+                &NewOrderRequest {
+                    warehouse_id,
+                    district_id: DISTRICT,
+                    customer_id: 1,
+                    order_lines,
+                },
+            )
+            .await
+            .unwrap_or_else(|e| panic!("{} New-Order failed: {}", path, e));
+
+            // Everything but the warehouse IDs and entry date, as JSON
+            let mut json = serde_json::to_value(&outcome.value).unwrap();
+            json.as_object_mut().unwrap().remove("order_entry_date");
+            json.as_object_mut().unwrap().remove("warehouse_id");
+            for line in json["order_lines"].as_array_mut().into_iter().flatten() {
+                let remote = line["supply_warehouse_id"] != warehouse_id;
+                line["supply_warehouse_id"] = remote.into();
+            }
+            outcomes.push(json);
+        }
+        responses.push(outcomes);
+    }
+
+    let (per_line, batched) = (&responses[0], &responses[1]);
+    assert_eq!(per_line, batched);
+    assert_eq!(per_line[0]["outcome"], "committed");
+    assert_eq!(per_line[0]["order_lines"][1]["brand_generic"], "B");
+    assert_eq!(per_line[1]["order_lines"][2]["stock_quantity"], 9);
+    assert_eq!(per_line[2]["outcome"], "rolled_back");
+    assert_eq!(per_line[2]["item_id"], UNUSED_ITEM);
+    assert_eq!(per_line[3]["order_id"], 3);
+
+    assert_eq!(
+        rows(&pool, PER_LINE_WAREHOUSE).await,
+        rows(&pool, BATCHED_WAREHOUSE).await
+    );
+    let order_lines: i64 =
+        sqlx::query_scalar("SELECT count(*) FROM order_line1 WHERE ol_w_id = $1")
+            .bind(PER_LINE_WAREHOUSE)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    assert_eq!(order_lines, 9);
+    let orders: Vec<(i32, i16, Option<i16>)> = sqlx::query_as(
+        "SELECT o_id, o_ol_cnt, o_all_local FROM orders1 WHERE o_w_id = $1 ORDER BY o_id",
+    )
+    .bind(PER_LINE_WAREHOUSE)
+    .fetch_all(&pool)
+    .await
+    .unwrap();
+    assert_eq!(
+        orders,
+        vec![(1, 4, Some(0)), (2, 3, Some(1)), (3, 2, Some(1))]
+    );
+    let stock: Vec<(i32, i16, f64, i16, i16)> = sqlx::query_as(
+        "SELECT s_i_id, s_quantity, s_ytd::float8, s_order_cnt, s_remote_cnt
+         FROM stock1 WHERE s_w_id = $1 ORDER BY s_i_id",
+    )
+    .bind(PER_LINE_WAREHOUSE)
+    .fetch_all(&pool)
+    .await
+    .unwrap();
+    // Restocked by 91 when short: 12 - 10 = 2, 2 - 4 + 91 = 89, then 89 - 6 = 83
+    assert_eq!(stock[2], (ITEMS[2], 83, 20.0, 3, 0));
+    // The remote line of the other path's first order
+    assert_eq!(stock[5], (ITEMS[5], 10, 2.0, 1, 1));
+}
+
+#[tokio::test]
+async fn test_batched_new_order_matches_inline_transactions() {
+    let Some(pool) = connect_test_db().await else {
+        println!("⚠️  Database not available, skipping New-Order path test");
+        return;
+    };
+    let path = ExecutionPath {
+        transactions: TransactionPool::Sqlx(pool.clone()),
+        settings: TransactionSettings {
+            new_order_path: NewOrderPath::Batched,
+            ..TransactionSettings::default()
+        },
+    };
+    assert_matches_inline(&pool, path, INLINE_WAREHOUSE, BATCHED_MIX_WAREHOUSE).await;
+}
@@ -0,0 +1,170 @@
+// Integration tests for routing requests to sysbench-style table sets (warehouse2, stock2, ...)
+mod common;
+
+use axum::body::Body;
+use common::connect_test_db;
+use http_body_util::BodyExt;
+use hyper::{Method, Request};
+use rust_axum_rest_api::create_app;
+use rust_axum_rest_api::table_set::{self, TableSets, TPCC_TABLES};
+use sqlx::PgPool;
+use tower::ServiceExt;
+
+const TABLE_SET: u32 = 2;
+const TEST_WAREHOUSE: i16 = 995;
+
+// Create table set 2 with the layout of set 1 and a warehouse, district and customer in it
+async fn setup_table_set(pool: &PgPool) {
+    for table in TPCC_TABLES {
+        sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS {table}{TABLE_SET} (LIKE {table}1 INCLUDING ALL)"
+        ))
+        .execute(pool)
+        .await
+        .expect("Failed to create table set");
+    }
+
+    for statement in [
+        "DELETE FROM history2 WHERE h_w_id = $1",
+        "DELETE FROM customer2 WHERE c_w_id = $1",
+        "DELETE FROM district2 WHERE d_w_id = $1",
+        "DELETE FROM warehouse2 WHERE w_id = $1",
+        "INSERT INTO warehouse2 (w_id, w_name, w_tax, w_ytd) VALUES ($1, 'SET2WH', 0.10, 300000)",
+        "INSERT INTO district2 (d_id, d_w_id, d_name, d_tax, d_ytd, d_next_o_id)
+         VALUES (1, $1, 'SET2DIST', 0.05, 30000, 1)",
+        "INSERT INTO customer2 (c_id, c_d_id, c_w_id, c_first, c_middle, c_last, c_since, c_credit,
+                                c_credit_lim, c_discount, c_balance, c_ytd_payment, c_payment_cnt,
+                                c_delivery_cnt, c_data)
+         VALUES (1, 1, $1, 'SETTWO', 'OE', 'SETLAST', NOW(), 'GC', 50000, 0.05, -10, 10, 1, 0, 'set 2')",
+    ] {
+        sqlx::query(statement)
+            .bind(TEST_WAREHOUSE)
+            .execute(pool)
+            .await
+            .expect("Failed to prepare table set data");
+    }
+}
+
+async fn send(
+    app: &axum::Router,
+    method: Method,
+    uri: &str,
+    table_set: Option<&str>,
+    body: Option<serde_json::Value>,
+) -> (u16, serde_json::Value) {
+    let mut request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("content-type", "application/json");
+    if let Some(table_set) = table_set {
+        request = request.header("x-table-set", table_set);
+    }
+    let request = request
+        .body(match body {
+            Some(json) => Body::from(json.to_string()),
+            None => Body::empty(),
+        })
+        .unwrap();
+
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status().as_u16();
+    let bytes = response.into_body().collect().await.unwrap().to_bytes();
+    let json = serde_json::from_slice(&bytes).unwrap_or(serde_json::Value::Null);
+    (status, json)
+}
+
+fn has_warehouse(json: &serde_json::Value, name: &str) -> bool {
+    json.as_array()
+        .unwrap()
+        .iter()
+        .any(|warehouse| warehouse["w_name"] == name)
+}
+
+#[tokio::test]
+async fn test_requests_are_routed_to_table_set() {
+    let Some(pool) = connect_test_db().await else {
+        println!("⚠️  Database not available, skipping table set test");
+        return;
+    };
+    setup_table_set(&pool).await;
+
+    // Discovery leaves out a set without views rather than creating them
+    sqlx::query("DROP SCHEMA IF EXISTS tpcc_tables2 CASCADE")
+        .execute(&pool)
+        .await
+        .unwrap();
+    assert!(!TableSets::discover(&pool)
+        .await
+        .numbers()
+        .contains(&TABLE_SET));
+    let view_schemas: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM pg_namespace WHERE nspname = 'tpcc_tables2'")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    assert_eq!(view_schemas, 0);
+    let created = table_set::create_views(&pool).await.unwrap();
+    assert!(created.contains(&TABLE_SET));
+
+    // The sets share the connections of the configured pool
+    let table_sets = TableSets::discover(&pool).await;
+    let numbers = table_sets.numbers();
+    assert!(numbers.contains(&TABLE_SET));
+    let connections: u32 = numbers
+        .iter()
+        .map(|&number| {
+            let set_pool = table_sets.pool(number).unwrap();
+            assert!(set_pool.options().get_max_connections() >= 1);
+            set_pool.options().get_max_connections()
+        })
+        .sum();
+    assert!(connections <= pool.options().get_max_connections());
+
+    let app = create_app(pool.clone()).await;
+
+    // Header, query parameter and default (set 1)
+    let (status, json) = send(&app, Method::GET, "/warehouses", Some("2"), None).await;
+    assert_eq!(status, 200);
+    assert!(has_warehouse(&json, "SET2WH"));
+
+    let (status, json) = send(&app, Method::GET, "/warehouses?table_set=2", None, None).await;
+    assert_eq!(status, 200);
+    assert!(has_warehouse(&json, "SET2WH"));
+
+    let (status, json) = send(&app, Method::GET, "/warehouses", None, None).await;
+    assert_eq!(status, 200);
+    assert!(!has_warehouse(&json, "SET2WH"));
+
+    // Writes go to the selected set as well
+    let (status, json) = send(
+        &app,
+        Method::POST,
+        "/payment",
+        Some("2"),
+        Some(serde_json::json!({
+            "warehouse_id": TEST_WAREHOUSE,
+            "district_id": 1,
+            "customer_id": 1,
+            "amount": 100.0
+        })),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(json["customer"]["c_first"], "SETTWO");
+
+    let history: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM history2 WHERE h_w_id = $1")
+        .bind(TEST_WAREHOUSE)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(history, 1);
+
+    // Unknown and malformed table sets
+    let (status, json) = send(&app, Method::GET, "/warehouses", Some("99"), None).await;
+    assert_eq!(status, 404);
+    assert_eq!(json["code"], "table_set_not_found");
+
+    let (status, json) = send(&app, Method::GET, "/warehouses", Some("two"), None).await;
+    assert_eq!(status, 400);
+    assert_eq!(json["code"], "invalid_request");
+}
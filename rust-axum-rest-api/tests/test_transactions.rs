@@ -0,0 +1,855 @@
+// Integration tests for the TPC-C transaction endpoints.
+// Each test works in its own district of test warehouse 998 (or in its own warehouse when it
+// spans every district) so tests can run in parallel.
+mod common;
+
+use axum::body::Body;
+use common::connect_test_db;
+use http_body_util::BodyExt;
+use hyper::{Method, Request};
+use rust_axum_rest_api::delivery_queue::DeferredDeliveryStatus;
+use rust_axum_rest_api::error::ApiError;
+use rust_axum_rest_api::handlers::new_order::{
+    execute_new_order, NewOrderRequest, OrderLineRequest,
+};
+use rust_axum_rest_api::handlers::order_status::{execute_order_status, OrderStatusQuery};
+use rust_axum_rest_api::handlers::payment::{execute_payment, PaymentRequest};
+use rust_axum_rest_api::handlers::stock_level::{execute_stock_level, StockLevelQuery};
+use rust_axum_rest_api::transaction::{
+    begin, run_transaction, IsolationLevel, RetryPolicy, TransactionSettings,
+};
+use rust_axum_rest_api::{create_app, create_state};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tower::ServiceExt;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::Layer;
+
+const TEST_WAREHOUSE: i16 = 998;
+const DELIVERY_WAREHOUSE: i16 = 997;
+const DEFERRED_DELIVERY_WAREHOUSE: i16 = 996;
+const SHUTDOWN_WAREHOUSE: i16 = 994;
+
+// Reset the given district of the test warehouse and insert three customers
+// sharing the last name TXLAST (first names ALPHA, BRAVO, CHARLIE)
+async fn setup_district(pool: &PgPool, district_id: i16) {
+    setup_warehouse_district(pool, TEST_WAREHOUSE, district_id).await;
+}
+
+async fn setup_warehouse_district(pool: &PgPool, warehouse_id: i16, district_id: i16) {
+    sqlx::query(
+        "INSERT INTO warehouse1 (w_id, w_name, w_tax, w_ytd) VALUES ($1, 'TxTestWH', 0.10, 300000)
+         ON CONFLICT (w_id) DO NOTHING",
+    )
+    .bind(warehouse_id)
+    .execute(pool)
+    .await
+    .expect("Failed to insert test warehouse");
+
+    for table in [
+        "history1 WHERE h_c_w_id = $1 AND h_c_d_id = $2",
+        "order_line1 WHERE ol_w_id = $1 AND ol_d_id = $2",
+        "new_orders1 WHERE no_w_id = $1 AND no_d_id = $2",
+        "orders1 WHERE o_w_id = $1 AND o_d_id = $2",
+        "customer1 WHERE c_w_id = $1 AND c_d_id = $2",
+        "district1 WHERE d_w_id = $1 AND d_id = $2",
+    ] {
+        sqlx::query(&format!("DELETE FROM {}", table))
+            .bind(warehouse_id)
+            .bind(district_id)
+            .execute(pool)
+            .await
+            .expect("Failed to clean test district");
+    }
+
+    sqlx::query(
+        "INSERT INTO district1 (d_id, d_w_id, d_name, d_tax, d_ytd, d_next_o_id)
+         VALUES ($1, $2, 'TxDist', 0.05, 30000, 1)",
+    )
+    .bind(district_id)
+    .bind(warehouse_id)
+    .execute(pool)
+    .await
+    .expect("Failed to insert test district");
+
+    for (c_id, c_first) in [(3, "CHARLIE"), (1, "ALPHA"), (2, "BRAVO")] {
+        sqlx::query(
+            "INSERT INTO customer1 (c_id, c_d_id, c_w_id, c_first, c_middle, c_last, c_since, c_credit,
+                                    c_credit_lim, c_discount, c_balance, c_ytd_payment, c_payment_cnt,
+                                    c_delivery_cnt, c_data)
+             VALUES ($1, $2, $3, $4, 'OE', 'TXLAST', NOW(), 'GC', 50000, 0.05, -10, 10, 1, 0, 'tx test')",
+        )
+        .bind(c_id)
+        .bind(district_id)
+        .bind(warehouse_id)
+        .bind(c_first)
+        .execute(pool)
+        .await
+        .expect("Failed to insert test customer");
+    }
+}
+
+async fn send_json(
+    app: &axum::Router,
+    method: Method,
+    uri: &str,
+    body: Option<serde_json::Value>,
+) -> (u16, serde_json::Value) {
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("content-type", "application/json")
+        .body(match body {
+            Some(json) => Body::from(json.to_string()),
+            None => Body::empty(),
+        })
+        .unwrap();
+
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status().as_u16();
+    let bytes = response.into_body().collect().await.unwrap().to_bytes();
+    let json = serde_json::from_slice(&bytes).unwrap_or(serde_json::Value::Null);
+    (status, json)
+}
+
+#[tokio::test]
+async fn test_payment_by_last_name_picks_middle_customer() {
+    let Some(pool) = connect_test_db().await else {
+        println!("⚠️  Database not available, skipping payment by last name test");
+        return;
+    };
+    let district_id = 1;
+    setup_district(&pool, district_id).await;
+    let app = create_app(pool).await;
+
+    let (status, json) = send_json(
+        &app,
+        Method::POST,
+        "/payment",
+        Some(serde_json::json!({
+            "warehouse_id": TEST_WAREHOUSE,
+            "district_id": district_id,
+            "customer_last_name": "TXLAST",
+            "amount": 12.5
+        })),
+    )
+    .await;
+
+    assert_eq!(status, 200);
+    // Sorted by first name: ALPHA, BRAVO, CHARLIE -> BRAVO is the middle customer
+    assert_eq!(json["customer"]["c_id"], 2);
+    assert_eq!(json["customer"]["c_first"], "BRAVO");
+
+    // Supplying both selectors is rejected
+    let (status, _) = send_json(
+        &app,
+        Method::POST,
+        "/payment",
+        Some(serde_json::json!({
+            "warehouse_id": TEST_WAREHOUSE,
+            "district_id": district_id,
+            "customer_id": 1,
+            "customer_last_name": "TXLAST",
+            "amount": 12.5
+        })),
+    )
+    .await;
+    assert_eq!(status, 400);
+}
+
+#[tokio::test]
+async fn test_order_status_by_last_name_reports_selection() {
+    let Some(pool) = connect_test_db().await else {
+        println!("⚠️  Database not available, skipping order status by last name test");
+        return;
+    };
+    let district_id = 2;
+    setup_district(&pool, district_id).await;
+    sqlx::query(
+        "INSERT INTO orders1 (o_id, o_d_id, o_w_id, o_c_id, o_entry_d, o_ol_cnt, o_all_local)
+         VALUES (1, $1, $2, 2, NOW(), 1, 1)",
+    )
+    .bind(district_id)
+    .bind(TEST_WAREHOUSE)
+    .execute(&pool)
+    .await
+    .expect("Failed to insert test order");
+    let app = create_app(pool).await;
+
+    let uri = format!(
+        "/order-status?warehouse_id={}&district_id={}&customer_last_name=TXLAST",
+        TEST_WAREHOUSE, district_id
+    );
+    let (status, json) = send_json(&app, Method::GET, &uri, None).await;
+
+    assert_eq!(status, 200);
+    assert_eq!(json["customer_selection"]["selected_by"], "last_name");
+    assert_eq!(json["customer_selection"]["c_id"], 2);
+    assert_eq!(json["customer_selection"]["name_count"], 3);
+    assert_eq!(json["latest_order"]["o_id"], 1);
+
+    let uri = format!(
+        "/order-status?warehouse_id={}&district_id={}&customer_last_name=NOSUCHNAME",
+        TEST_WAREHOUSE, district_id
+    );
+    let (status, json) = send_json(&app, Method::GET, &uri, None).await;
+    assert_eq!(status, 404);
+    assert_eq!(json["code"], "customer_not_found");
+    assert_eq!(json["entity"]["customer_last_name"], "NOSUCHNAME");
+}
+
+#[tokio::test]
+async fn test_errors_have_structured_json_bodies() {
+    let Some(pool) = connect_test_db().await else {
+        println!("⚠️  Database not available, skipping structured error test");
+        return;
+    };
+    let app = create_app(pool).await;
+
+    // Unknown warehouse -> 404 naming the missing row
+    let (status, json) = send_json(
+        &app,
+        Method::POST,
+        "/payment",
+        Some(serde_json::json!({
+            "warehouse_id": 32000,
+            "district_id": 1,
+            "customer_id": 1,
+            "amount": 1.0
+        })),
+    )
+    .await;
+    assert_eq!(status, 404);
+    assert_eq!(json["code"], "warehouse_not_found");
+    assert_eq!(json["entity"]["warehouse_id"], 32000);
+
+    // Extractor rejections keep their status but use the same body
+    let (status, json) = send_json(&app, Method::GET, "/stock-level", None).await;
+    assert_eq!(status, 400);
+    assert_eq!(json["code"], "invalid_request");
+    assert!(json["message"].is_string());
+
+    let (status, json) = send_json(
+        &app,
+        Method::POST,
+        "/new-order",
+        Some(serde_json::json!({ "warehouse_id": "not a number" })),
+    )
+    .await;
+    assert_eq!(status, 422);
+    assert_eq!(json["code"], "invalid_request");
+}
+
+#[tokio::test]
+async fn test_remote_customer_payment_records_both_districts() {
+    let Some(pool) = connect_test_db().await else {
+        println!("⚠️  Database not available, skipping remote customer payment test");
+        return;
+    };
+    let (paying_district, customer_district) = (3, 4);
+    setup_district(&pool, paying_district).await;
+    setup_district(&pool, customer_district).await;
+    let app = create_app(pool.clone()).await;
+
+    let (status, json) = send_json(
+        &app,
+        Method::POST,
+        "/payment",
+        Some(serde_json::json!({
+            "warehouse_id": TEST_WAREHOUSE,
+            "district_id": paying_district,
+            "customer_id": 1,
+            "customer_warehouse_id": TEST_WAREHOUSE,
+            "customer_district_id": customer_district,
+            "amount": 100.0
+        })),
+    )
+    .await;
+
+    assert_eq!(status, 200);
+    assert_eq!(json["district"]["d_id"], paying_district);
+    assert_eq!(json["customer"]["c_d_id"], customer_district);
+
+    // District YTD moves on the paying district only
+    let ytd: Vec<(i16, String)> = sqlx::query_as(
+        "SELECT d_id, d_ytd::text FROM district1 WHERE d_w_id = $1 AND d_id IN ($2, $3) ORDER BY d_id",
+    )
+    .bind(TEST_WAREHOUSE)
+    .bind(paying_district)
+    .bind(customer_district)
+    .fetch_all(&pool)
+    .await
+    .unwrap();
+    assert_eq!(
+        ytd,
+        vec![
+            (paying_district, "30100.00".to_string()),
+            (customer_district, "30000.00".to_string())
+        ]
+    );
+
+    // The customer's balance is updated in its home district
+    let balance: String = sqlx::query_scalar(
+        "SELECT c_balance::text FROM customer1 WHERE c_w_id = $1 AND c_d_id = $2 AND c_id = 1",
+    )
+    .bind(TEST_WAREHOUSE)
+    .bind(customer_district)
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert_eq!(balance, "-110.00");
+
+    // History records both the customer's and the paying district
+    let history: (i16, i16) = sqlx::query_as(
+        "SELECT h_c_d_id, h_d_id FROM history1 WHERE h_c_w_id = $1 AND h_c_d_id = $2 AND h_c_id = 1",
+    )
+    .bind(TEST_WAREHOUSE)
+    .bind(customer_district)
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert_eq!(history, (customer_district, paying_district));
+}
+
+#[tokio::test]
+async fn test_full_delivery_processes_every_district() {
+    let Some(pool) = connect_test_db().await else {
+        println!("⚠️  Database not available, skipping full delivery test");
+        return;
+    };
+    for district_id in 1..=10 {
+        setup_warehouse_district(&pool, DELIVERY_WAREHOUSE, district_id).await;
+    }
+    // Undelivered orders in districts 1 and 2 only
+    for district_id in [1i16, 2] {
+        sqlx::query(
+            "INSERT INTO orders1 (o_id, o_d_id, o_w_id, o_c_id, o_entry_d, o_ol_cnt, o_all_local)
+             VALUES (1, $1, $2, 1, NOW(), 1, 1)",
+        )
+        .bind(district_id)
+        .bind(DELIVERY_WAREHOUSE)
+        .execute(&pool)
+        .await
+        .expect("Failed to insert test order");
+        sqlx::query("INSERT INTO new_orders1 (no_o_id, no_d_id, no_w_id) VALUES (1, $1, $2)")
+            .bind(district_id)
+            .bind(DELIVERY_WAREHOUSE)
+            .execute(&pool)
+            .await
+            .expect("Failed to insert test new order");
+        sqlx::query(
+            "INSERT INTO order_line1 (ol_o_id, ol_d_id, ol_w_id, ol_number, ol_i_id, ol_supply_w_id,
+                                      ol_quantity, ol_amount, ol_dist_info)
+             VALUES (1, $1, $2, 1, 1, $2, 5, 25.0, 'tx test')",
+        )
+        .bind(district_id)
+        .bind(DELIVERY_WAREHOUSE)
+        .execute(&pool)
+        .await
+        .expect("Failed to insert test order line");
+    }
+    let app = create_app(pool.clone()).await;
+
+    let (status, json) = send_json(
+        &app,
+        Method::POST,
+        "/delivery",
+        Some(serde_json::json!({ "warehouse_id": DELIVERY_WAREHOUSE, "carrier_id": 7 })),
+    )
+    .await;
+
+    assert_eq!(status, 200);
+    assert_eq!(json["total_orders_delivered"], 2);
+    assert_eq!(json["delivered_orders"][0]["district_id"], 1);
+    assert_eq!(json["delivered_orders"][1]["district_id"], 2);
+    assert_eq!(json["delivered_orders"][0]["carrier_id"], 7);
+    assert_eq!(
+        json["skipped_districts"],
+        serde_json::json!([3, 4, 5, 6, 7, 8, 9, 10])
+    );
+
+    let carriers: Vec<Option<i16>> =
+        sqlx::query_scalar("SELECT o_carrier_id FROM orders1 WHERE o_w_id = $1 ORDER BY o_d_id")
+            .bind(DELIVERY_WAREHOUSE)
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+    assert_eq!(carriers, vec![Some(7), Some(7)]);
+
+    // Carrier outside 1-10 is rejected
+    let (status, _) = send_json(
+        &app,
+        Method::POST,
+        "/delivery",
+        Some(serde_json::json!({ "warehouse_id": DELIVERY_WAREHOUSE, "carrier_id": 11 })),
+    )
+    .await;
+    assert_eq!(status, 400);
+
+    // So is a missing carrier
+    let (status, _) = send_json(
+        &app,
+        Method::POST,
+        "/delivery",
+        Some(serde_json::json!({ "warehouse_id": DELIVERY_WAREHOUSE })),
+    )
+    .await;
+    assert_eq!(status, 400);
+}
+
+#[tokio::test]
+async fn test_deferred_delivery_records_result() {
+    let Some(pool) = connect_test_db().await else {
+        println!("⚠️  Database not available, skipping deferred delivery test");
+        return;
+    };
+    let warehouse_id = DEFERRED_DELIVERY_WAREHOUSE;
+    setup_warehouse_district(&pool, warehouse_id, 1).await;
+    sqlx::query(
+        "INSERT INTO orders1 (o_id, o_d_id, o_w_id, o_c_id, o_entry_d, o_ol_cnt, o_all_local)
+         VALUES (1, 1, $1, 1, NOW(), 0, 1)",
+    )
+    .bind(warehouse_id)
+    .execute(&pool)
+    .await
+    .expect("Failed to insert test order");
+    sqlx::query("INSERT INTO new_orders1 (no_o_id, no_d_id, no_w_id) VALUES (1, 1, $1)")
+        .bind(warehouse_id)
+        .execute(&pool)
+        .await
+        .expect("Failed to insert test new order");
+    let app = create_app(pool).await;
+
+    let (status, json) = send_json(
+        &app,
+        Method::POST,
+        "/delivery?deferred=true",
+        Some(
+            serde_json::json!({ "warehouse_id": warehouse_id, "district_id": 1, "carrier_id": 3 }),
+        ),
+    )
+    .await;
+    assert_eq!(status, 202);
+    let ticket = json["ticket"].as_u64().unwrap();
+
+    // Poll the result log until a worker has completed the delivery
+    let mut record = serde_json::Value::Null;
+    for _ in 0..50 {
+        let (status, json) =
+            send_json(&app, Method::GET, &format!("/delivery/{}", ticket), None).await;
+        assert_eq!(status, 200);
+        record = json;
+        if record["status"] == "completed" {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+
+    assert_eq!(record["status"], "completed");
+    assert!(record["queued_at"].is_string());
+    assert!(record["started_at"].is_string());
+    assert!(record["completed_at"].is_string());
+    assert_eq!(record["districts"][0]["outcome"], "delivered");
+    assert_eq!(record["districts"][0]["order"]["order_id"], 1);
+
+    let (status, _) = send_json(&app, Method::GET, "/delivery/999999999", None).await;
+    assert_eq!(status, 404);
+}
+
+#[tokio::test]
+async fn test_new_order_with_unused_item_rolls_back() {
+    let Some(pool) = connect_test_db().await else {
+        println!("⚠️  Database not available, skipping new order rollback test");
+        return;
+    };
+    let district_id = 5;
+    setup_district(&pool, district_id).await;
+    let app = create_app(pool.clone()).await;
+
+    let (status, json) = send_json(
+        &app,
+        Method::POST,
+        "/new-order",
+        Some(serde_json::json!({
+            "warehouse_id": TEST_WAREHOUSE,
+            "district_id": district_id,
+            "customer_id": 2,
+            "order_lines": [
+                { "item_id": 2_000_000, "supply_warehouse_id": TEST_WAREHOUSE, "quantity": 1 }
+            ]
+        })),
+    )
+    .await;
+
+    assert_eq!(status, 200);
+    assert_eq!(json["outcome"], "rolled_back");
+    assert_eq!(json["message"], "Item number is not valid");
+    assert_eq!(json["order_id"], 1);
+    assert_eq!(json["item_id"], 2_000_000);
+    assert_eq!(json["customer"]["last_name"], "TXLAST");
+
+    // Nothing from the rolled-back order remains
+    let next_o_id: i32 =
+        sqlx::query_scalar("SELECT d_next_o_id FROM district1 WHERE d_w_id = $1 AND d_id = $2")
+            .bind(TEST_WAREHOUSE)
+            .bind(district_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    assert_eq!(next_o_id, 1);
+    let orders: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM orders1 WHERE o_w_id = $1 AND o_d_id = $2")
+            .bind(TEST_WAREHOUSE)
+            .bind(district_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    assert_eq!(orders, 0);
+
+    // Counted as a rollback, not as a failure
+    let (status, json) = send_json(&app, Method::GET, "/new-order/stats", None).await;
+    assert_eq!(status, 200);
+    assert_eq!(json["rolled_back"], 1);
+    assert_eq!(json["failed"], 0);
+}
+
+#[tokio::test]
+async fn test_run_transaction_retries_serialization_failures() {
+    let Some(pool) = connect_test_db().await else {
+        println!("⚠️  Database not available, skipping transaction retry test");
+        return;
+    };
+    let policy = RetryPolicy {
+        max_attempts: 3,
+        base_delay: Duration::from_millis(1),
+        max_delay: Duration::from_millis(5),
+    };
+
+    // Fail with the given SQLSTATE on the first `failures` runs
+    let run = |sqlstate: &'static str, failures: u32| {
+        let pool = pool.clone();
+        let calls = Arc::new(AtomicU32::new(0));
+        let counter = Arc::clone(&calls);
+        async move {
+            let result = run_transaction(&policy, "test", || async {
+                if counter.fetch_add(1, Ordering::SeqCst) < failures {
+                    let statement = format!(
+                        "DO $$ BEGIN RAISE EXCEPTION 'injected' USING ERRCODE = '{}'; END $$",
+                        sqlstate
+                    );
+                    sqlx::query(&statement)
+                        .execute(&pool)
+                        .await
+                        .map_err(|e| ApiError::database("injecting failure", e))?;
+                }
+                Ok("done")
+            })
+            .await;
+            (result, calls.load(Ordering::SeqCst))
+        }
+    };
+
+    let (result, calls) = run("40001", 2).await;
+    assert_eq!(result.unwrap().attempts, 3);
+    assert_eq!(calls, 3);
+
+    let (result, calls) = run("40P01", 5).await;
+    let error = result.err().unwrap();
+    assert_eq!(error.code(), "deadlock_detected");
+    assert!(matches!(error, ApiError::Retried { attempts: 3, .. }));
+    assert_eq!(calls, 3);
+
+    // Other errors are not retried
+    let (result, calls) = run("23505", 1).await;
+    assert_eq!(result.err().unwrap().code(), "database_error");
+    assert_eq!(calls, 1);
+}
+
+#[tokio::test]
+async fn test_begin_sets_isolation_level() {
+    let Some(pool) = connect_test_db().await else {
+        println!("⚠️  Database not available, skipping isolation level test");
+        return;
+    };
+
+    for (name, expected) in [
+        ("RC", "read committed"),
+        ("rr", "repeatable read"),
+        ("SERIALIZABLE", "serializable"),
+    ] {
+        let level: IsolationLevel = name.parse().unwrap();
+        let mut tx = begin(&pool, Some(level)).await.unwrap();
+        let actual: String = sqlx::query_scalar("SHOW transaction_isolation")
+            .fetch_one(&mut *tx)
+            .await
+            .unwrap();
+        assert_eq!(actual, expected);
+        tx.rollback().await.unwrap();
+    }
+
+    assert!("snapshot".parse::<IsolationLevel>().is_err());
+}
+
+#[tokio::test]
+async fn test_transactions_run_in_process_without_http() {
+    let Some(pool) = connect_test_db().await else {
+        println!("⚠️  Database not available, skipping in-process transaction test");
+        return;
+    };
+    let district_id = 6;
+    setup_district(&pool, district_id).await;
+    let settings = TransactionSettings::default();
+
+    let payment = execute_payment(
+        &pool,
+        &settings,
+        &PaymentRequest {
+            warehouse_id: TEST_WAREHOUSE,
+            district_id,
+            customer_id: None,
+            customer_last_name: Some("TXLAST".to_string()),
+            customer_warehouse_id: None,
+            customer_district_id: None,
+            amount: 25.0,
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(payment.attempts, 1);
+    assert_eq!(payment.value.customer.c_first, "BRAVO");
+
+    let stock_level = execute_stock_level(
+        &pool,
+        &settings,
+        &StockLevelQuery {
+            warehouse_id: TEST_WAREHOUSE,
+            district_id,
+            threshold: 15,
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(stock_level.value.low_stock_count, 0);
+
+    // Validation happens in the library function, not only in the handler
+    let error = execute_order_status(
+        &pool,
+        &settings,
+        &OrderStatusQuery {
+            warehouse_id: TEST_WAREHOUSE,
+            district_id,
+            customer_id: None,
+            customer_last_name: None,
+        },
+    )
+    .await
+    .err()
+    .unwrap();
+    assert_eq!(error.code(), "invalid_request");
+}
+
+#[tokio::test]
+async fn test_metrics_expose_requests_transactions_and_pools() {
+    let Some(pool) = connect_test_db().await else {
+        println!("⚠️  Database not available, skipping metrics test");
+        return;
+    };
+    let district_id = 7;
+    setup_district(&pool, district_id).await;
+    let app = create_app(pool).await;
+
+    let (status, _) = send_json(
+        &app,
+        Method::POST,
+        "/payment",
+        Some(serde_json::json!({
+            "warehouse_id": TEST_WAREHOUSE,
+            "district_id": district_id,
+            "customer_id": 1,
+            "amount": 5.0
+        })),
+    )
+    .await;
+    assert_eq!(status, 200);
+    let (status, _) = send_json(
+        &app,
+        Method::GET,
+        &format!(
+            "/order-status?warehouse_id={}&district_id={}&customer_id=999999",
+            TEST_WAREHOUSE, district_id
+        ),
+        None,
+    )
+    .await;
+    assert_eq!(status, 404);
+
+    let request = Request::builder()
+        .uri("/metrics")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let bytes = response.into_body().collect().await.unwrap().to_bytes();
+    let text = String::from_utf8(bytes.to_vec()).unwrap();
+
+    assert!(text.contains(r#"http_requests_total{method="POST",route="/payment",status="200"}"#));
+    assert!(text.contains(r#"http_errors_total{code="customer_not_found",route="/order-status"}"#));
+    assert!(text.contains(
+        r#"tpcc_transaction_duration_seconds_count{outcome="ok",transaction="payment"}"#
+    ));
+    assert!(text.contains("db_pool_begin_duration_seconds_bucket"));
+    assert!(text.contains(r#"db_pool_connections{state="idle",table_set="1"}"#));
+    assert!(text.contains(r#"db_pool_max_connections{table_set="1"}"#));
+}
+
+type SpanFields = HashMap<String, String>;
+
+// Records every span's name and fields, including fields recorded after creation
+#[derive(Clone, Default)]
+struct SpanCapture {
+    spans: Arc<Mutex<HashMap<u64, (String, SpanFields)>>>,
+}
+
+struct FieldVisitor<'a>(&'a mut SpanFields);
+
+impl Visit for FieldVisitor<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{:?}", value));
+    }
+}
+
+impl<S: tracing::Subscriber> Layer<S> for SpanCapture {
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, _ctx: Context<'_, S>) {
+        let mut fields = HashMap::new();
+        attrs.record(&mut FieldVisitor(&mut fields));
+        self.spans
+            .lock()
+            .unwrap()
+            .insert(id.into_u64(), (attrs.metadata().name().to_string(), fields));
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, _ctx: Context<'_, S>) {
+        if let Some((_, fields)) = self.spans.lock().unwrap().get_mut(&id.into_u64()) {
+            values.record(&mut FieldVisitor(fields));
+        }
+    }
+}
+
+impl SpanCapture {
+    fn fields(&self, name: &str) -> SpanFields {
+        self.spans
+            .lock()
+            .unwrap()
+            .values()
+            .find(|(span_name, _)| span_name == name)
+            .map(|(_, fields)| fields.clone())
+            .unwrap_or_else(|| panic!("no {} span", name))
+    }
+}
+
+#[tokio::test]
+async fn test_new_order_steps_are_traced_with_ids() {
+    let Some(pool) = connect_test_db().await else {
+        println!("⚠️  Database not available, skipping tracing test");
+        return;
+    };
+    let district_id = 8;
+    setup_district(&pool, district_id).await;
+
+    let capture = SpanCapture::default();
+    let _subscriber =
+        tracing::subscriber::set_default(tracing_subscriber::registry().with(capture.clone()));
+
+    // An unused item rolls the order back after the district, customer and order steps
+    let outcome = execute_new_order(
+        &pool,
+        &TransactionSettings::default(),
+        &NewOrderRequest {
+            warehouse_id: TEST_WAREHOUSE,
+            district_id,
+            customer_id: 3,
+            order_lines: vec![OrderLineRequest {
+                item_id: 2_000_000,
+                supply_warehouse_id: TEST_WAREHOUSE,
+                quantity: 1,
+            }],
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(outcome.attempts, 1);
+
+    let transaction = capture.fields("transaction");
+    assert_eq!(transaction["transaction"], "\"new-order\"");
+    assert_eq!(transaction["attempt"], "1");
+
+    let new_order = capture.fields("new_order");
+    assert_eq!(new_order["w_id"], TEST_WAREHOUSE.to_string());
+    assert_eq!(new_order["d_id"], district_id.to_string());
+    assert_eq!(new_order["c_id"], "3");
+    assert_eq!(new_order["o_id"], "1");
+
+    assert_eq!(
+        capture.fields("get_warehouse_data")["w_id"],
+        TEST_WAREHOUSE.to_string()
+    );
+    assert_eq!(capture.fields("get_and_update_district")["o_id"], "1");
+    assert_eq!(capture.fields("get_customer_data")["c_id"], "3");
+    assert_eq!(capture.fields("insert_new_order")["o_id"], "1");
+    assert_eq!(capture.fields("get_item_data")["i_id"], "2000000");
+}
+
+#[tokio::test]
+async fn test_shutdown_drains_deferred_deliveries_and_closes_pools() {
+    let Some(pool) = connect_test_db().await else {
+        println!("⚠️  Database not available, skipping shutdown test");
+        return;
+    };
+    let warehouse_id = SHUTDOWN_WAREHOUSE;
+    for district_id in 1..=3 {
+        setup_warehouse_district(&pool, warehouse_id, district_id).await;
+    }
+    let state = create_state(pool.clone()).await;
+
+    let tickets: Vec<u64> = (0..5)
+        .map(|_| {
+            state
+                .delivery_queue
+                .enqueue(1, pool.clone(), warehouse_id, 4, vec![1, 2, 3])
+                .unwrap()
+                .ticket
+        })
+        .collect();
+
+    state
+        .shutdown(tokio::time::Instant::now() + Duration::from_secs(30))
+        .await;
+
+    // Everything queued before shutdown was processed
+    for ticket in tickets {
+        let record = state.delivery_queue.record(ticket).unwrap();
+        assert!(record.status == DeferredDeliveryStatus::Completed);
+        assert_eq!(record.districts.len(), 3);
+    }
+    assert_eq!(state.delivery_queue.pending(), 0);
+
+    // New work is refused
+    let error = state
+        .delivery_queue
+        .enqueue(1, pool.clone(), warehouse_id, 4, vec![1])
+        .err()
+        .unwrap();
+    assert_eq!(error.code(), "shutting_down");
+
+    // The configured pool on sqlx, the health checks' own one on the pipelined driver
+    assert!(state.pool.is_closed());
+    for number in state.table_sets.numbers() {
+        assert!(state.table_sets.pool(number).unwrap().is_closed());
+    }
+}
@@ -12,12 +12,69 @@ export type {
   OrderSummary,
   OrdersListResponse,
   OrderStatusQuery,
+  CustomerSelectionInfo,
   CustomerInfo,
   LatestOrderInfo,
   OrderLineInfo,
   OrderStatusResponse,
 } from '../types/orders';
 
+// ===== API ERRORS =====
+
+// Error codes returned by the API in the `code` field of error bodies
+export type ApiErrorCode =
+  | 'invalid_request'
+  | 'warehouse_not_found'
+  | 'district_not_found'
+  | 'customer_not_found'
+  | 'item_not_found'
+  | 'stock_not_found'
+  | 'order_not_found'
+  | 'delivery_ticket_not_found'
+  | 'delivery_queue_full'
+  | 'serialization_failure'
+  | 'deadlock_detected'
+  | 'database_error'
+  | 'internal_error';
+
+export interface ApiErrorBody {
+  code: ApiErrorCode;
+  message: string;
+  entity?: Record<string, number | string>; // Key fields of the row, e.g. { item_id: 123 }
+}
+
+export class ApiRequestError extends Error {
+  readonly status: number;
+  readonly code?: ApiErrorCode;
+  readonly entity?: ApiErrorBody['entity'];
+
+  constructor(action: string, status: number, statusText: string, body?: ApiErrorBody, text?: string) {
+    super(`${action}: ${status} ${statusText}. ${body?.message ?? text ?? 'Unknown error'}`);
+    this.name = 'ApiRequestError';
+    this.status = status;
+    this.code = body?.code;
+    this.entity = body?.entity;
+  }
+
+  // Serialization failures and deadlocks can be retried by the caller
+  get retryable(): boolean {
+    return this.code === 'serialization_failure' || this.code === 'deadlock_detected';
+  }
+}
+
+const toApiRequestError = async (action: string, response: Response): Promise<ApiRequestError> => {
+  const text = await response.text().catch(() => undefined);
+  try {
+    const body = JSON.parse(text ?? '') as ApiErrorBody;
+    if (typeof body.code === 'string') {
+      return new ApiRequestError(action, response.status, response.statusText, body);
+    }
+  } catch {
+    // Not a JSON error body
+  }
+  return new ApiRequestError(action, response.status, response.statusText, undefined, text);
+};
+
 // ===== NEW ORDER TYPES =====
 
 export interface OrderLineRequest {
@@ -52,6 +109,7 @@ export interface OrderLineSummary {
 }
 
 export interface NewOrderResponse {
+  outcome: 'committed';
   order_id: number;
   customer: CustomerSummary;
   warehouse_tax: string; // BigDecimal as string
@@ -61,6 +119,27 @@ export interface NewOrderResponse {
   order_lines: OrderLineSummary[];
 }
 
+// Returned instead of NewOrderResponse when an order line names an unused item ID
+export interface NewOrderRollback {
+  outcome: 'rolled_back';
+  warehouse_id: number;
+  district_id: number;
+  order_id: number; // Allocated, then released by the rollback
+  customer: CustomerSummary;
+  item_id: number;
+  message: string; // "Item number is not valid"
+}
+
+export class NewOrderRolledBackError extends Error {
+  readonly rollback: NewOrderRollback;
+
+  constructor(rollback: NewOrderRollback) {
+    super(`Order ${rollback.order_id} rolled back: ${rollback.message} (item ${rollback.item_id})`);
+    this.name = 'NewOrderRolledBackError';
+    this.rollback = rollback;
+  }
+}
+
 // ===== NEW ORDER API =====
 
 export const submitNewOrder = async (orderRequest: NewOrderRequest): Promise<NewOrderResponse> => {
@@ -73,11 +152,13 @@ export const submitNewOrder = async (orderRequest: NewOrderRequest): Promise<New
   });
 
   if (!response.ok) {
-    const errorText = await response.text().catch(() => 'Unknown error');
-    throw new Error(`Failed to submit order: ${response.status} ${response.statusText}. ${errorText}`);
+    throw await toApiRequestError('Failed to submit order', response);
   }
 
-  const orderResponse: NewOrderResponse = await response.json();
+  const orderResponse: NewOrderResponse | NewOrderRollback = await response.json();
+  if (orderResponse.outcome === 'rolled_back') {
+    throw new NewOrderRolledBackError(orderResponse);
+  }
   return orderResponse;
 };
 
@@ -108,8 +189,7 @@ export const fetchOrders = async (query: OrdersQuery = {}): Promise<OrdersListRe
   });
 
   if (!response.ok) {
-    const errorText = await response.text().catch(() => 'Unknown error');
-    throw new Error(`Failed to fetch orders: ${response.status} ${response.statusText}. ${errorText}`);
+    throw await toApiRequestError('Failed to fetch orders', response);
   }
 
   const ordersResponse: OrdersListResponse = await response.json();
@@ -135,8 +215,7 @@ export const fetchOrderStatus = async (query: OrderStatusQuery): Promise<OrderSt
   });
 
   if (!response.ok) {
-    const errorText = await response.text().catch(() => 'Unknown error');
-    throw new Error(`Failed to fetch order status: ${response.status} ${response.statusText}. ${errorText}`);
+    throw await toApiRequestError('Failed to fetch order status', response);
   }
 
   const orderStatusResponse: OrderStatusResponse = await response.json();
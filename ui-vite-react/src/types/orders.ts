@@ -58,6 +58,12 @@ export interface OrderStatusQuery {
   customer_id: number;
 }
 
+export interface CustomerSelectionInfo {
+  selected_by: 'customer_id' | 'last_name';
+  c_id: number;
+  name_count: number;
+}
+
 export interface CustomerInfo {
   c_id: number;
   c_first?: string;
@@ -81,6 +87,7 @@ export interface OrderLineInfo {
 }
 
 export interface OrderStatusResponse {
+  customer_selection: CustomerSelectionInfo;
   customer: CustomerInfo;
   latest_order: LatestOrderInfo;
   order_lines: OrderLineInfo[];